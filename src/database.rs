@@ -5,122 +5,424 @@ use std::{
     collections::BTreeMap,
     fs::File,
     io::{Error, ErrorKind},
-    path::Path,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
     *,
 };
-use std::{convert::TryInto, io};
+use std::{convert::TryInto, io, time::Duration};
 use std::{fs::OpenOptions, io::prelude::*};
 
-use bucket::descriptor::BucketDescription;
+use bucket::{
+    config::BucketConfiguration,
+    descriptor::{BucketDescription, BucketDesriptor},
+    document::field::descriptor::FieldDescriptor,
+};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use dashmap::{DashMap, mapref::one::RefMut};
+use dashmap::{DashMap, mapref::one::{Ref, RefMut}};
 use fs2::*;
 use log::trace;
 
 pub mod bucket;
+mod checkpoint;
+pub mod config;
 pub mod descriptor;
+pub mod error;
+mod lock;
 
-use self::bucket::{document::DocumentConvert, Bucket};
+use self::bucket::{
+    document::{json::document_to_json, Document, DocumentConvert},
+    Bucket, RowId,
+};
+use checkpoint::{AutoCheckpoint, CheckpointHandle};
+use config::DatabaseConfig;
 use descriptor::DBDescriptor;
+use error::NonaneError;
+use lock::DatabaseLock;
 
 // Statically compiled options
 /// Extension used for buckets
 static EXTENSION: &'static str = ".page";
+/// Name of the file holding the database descriptor
+static DESCRIPTOR_FILE: &str = "database.desc";
+
+/// Ids of the documents inserted by `Database::insert_many_skip_invalid`, along with the
+/// position and error of every value which was skipped
+pub type PartialInsert = (Vec<RowId>, Vec<(usize, NonaneError)>);
 
 #[derive(Clone)]
-pub struct Database<'a, 'b> {
-    store_dir: Arc<&'b Path>,              // Directory to store buckets
+pub struct Database<'a> {
+    store_dir: Arc<PathBuf>,               // Directory to store buckets
     buckets: DashMap<&'a str, Bucket<'a>>, // BTree of in-use buckets
     descriptor: Arc<Option<DBDescriptor>>,
+    config: DatabaseConfig,
+    checkpoints: Arc<DashMap<String, CheckpointHandle>>,
+    auto_checkpoint: Arc<parking_lot::Mutex<Option<AutoCheckpoint>>>,
+    lock: Option<Arc<DatabaseLock>>,
 }
 
-impl<'a, 'b> Database<'a, 'b> {
-    pub fn open(path: &'b str) -> Result<Database<'a, 'b>, Box<dyn std::error::Error>> {
-        // Initialize database struct
-        let mut db = Database {
-            store_dir: Arc::new(&Path::new(path)),
-            buckets: DashMap::new(),
-            descriptor: Arc::new(None),
-        };
+impl<'a> Database<'a> {
+    pub fn open(path: &str) -> Result<Database<'a>, NonaneError> {
+        Database::open_with_config(path, DatabaseConfig::default())
+    }
+
+    /// Opens a database, applying `config` to every bucket opened afterwards
+    pub fn open_with_config(
+        path: &str,
+        config: DatabaseConfig,
+    ) -> Result<Database<'a>, NonaneError> {
+        let mut db = Database::new(path, config);
 
         // Create the database directory if it doesn't exist
         trace!("Checking if database already exists");
         if !db.store_dir.is_dir() {
-            db.create_head_dir()?;
-
-            // Create descriptor file and write to it
-            let dynamic = DBDescriptor::dynamic();
-            dynamic.save_to_path(&db.store_dir.join(&Path::new("database.desc")))?;
-
-            // Assign descriptor
-            db.descriptor = Arc::new(Some(dynamic));
+            db.create_descriptor()?;
         } else {
-            db.descriptor = Arc::new(Some(DBDescriptor::load_from_path(
-                &db.store_dir.join(&Path::new("database.desc")),
-            )?));
+            db.load_descriptor()?;
         }
 
         trace!("Successfully loaded and initialized a database");
         Ok(db)
     }
 
+    /// Opens a database, waiting up to `timeout` for another handle to release it
+    ///
+    /// Errors with `NonaneError::DatabaseLocked` if the database is still locked after the timeout.
+    pub fn open_with_lock_timeout(
+        path: &str,
+        timeout: Duration,
+    ) -> Result<Database<'a>, NonaneError> {
+        let config = DatabaseConfig {
+            lock_timeout: Some(timeout),
+            ..DatabaseConfig::default()
+        };
+
+        Database::open_with_config(path, config)
+    }
+
+    /// Creates a new database, errors with `NonaneError::AlreadyExists` if `path` already exists
+    pub fn create(path: &str) -> Result<Database<'a>, NonaneError> {
+        let mut db = Database::new(path, DatabaseConfig::default());
+        if db.store_dir.exists() {
+            return Err(NonaneError::AlreadyExists);
+        }
+
+        db.create_descriptor()?;
+
+        trace!("Successfully created a database");
+        Ok(db)
+    }
+
+    /// Opens an existing database, errors with `NonaneError::NotFound` if there is no database at `path`
+    pub fn open_existing(path: &str) -> Result<Database<'a>, NonaneError> {
+        let mut db = Database::new(path, DatabaseConfig::default());
+        if !db.store_dir.join(DESCRIPTOR_FILE).is_file() {
+            return Err(NonaneError::NotFound);
+        }
+
+        db.load_descriptor()?;
+
+        trace!("Successfully loaded a database");
+        Ok(db)
+    }
+
+    fn new(path: &str, config: DatabaseConfig) -> Database<'a> {
+        Database {
+            store_dir: Arc::new(PathBuf::from(path)),
+            buckets: DashMap::new(),
+            descriptor: Arc::new(None),
+            config,
+            checkpoints: Arc::new(DashMap::new()),
+            auto_checkpoint: Arc::new(parking_lot::Mutex::new(None)),
+            lock: None,
+        }
+    }
+
+    /// Creates the database directory and writes a new descriptor to it
+    fn create_descriptor(&mut self) -> Result<(), NonaneError> {
+        self.create_head_dir()?;
+        self.acquire_lock()?;
+
+        // Create descriptor file and write to it
+        let dynamic = DBDescriptor::dynamic();
+        dynamic.save_to_path(&self.store_dir.join(DESCRIPTOR_FILE))?;
+
+        // Assign descriptor
+        self.descriptor = Arc::new(Some(dynamic));
+        Ok(())
+    }
+
+    /// Loads the descriptor of an existing database
+    fn load_descriptor(&mut self) -> Result<(), NonaneError> {
+        self.acquire_lock()?;
+        self.descriptor = Arc::new(Some(DBDescriptor::load_from_path(
+            &self.store_dir.join(DESCRIPTOR_FILE),
+        )?));
+        Ok(())
+    }
+
+    /// Locks the database so no other handle can open it while this one is in use
+    fn acquire_lock(&mut self) -> Result<(), NonaneError> {
+        let lock = DatabaseLock::acquire(&self.store_dir, self.config.lock_timeout)?;
+        self.lock = Some(Arc::new(lock));
+        Ok(())
+    }
+
     /// Creates directory to hold buckets and database information
     pub fn create_head_dir(&self) -> std::io::Result<()> {
         trace!("Creating head directory for database");
         Ok(fs::create_dir(self.store_dir.as_ref())?)
     }
 
+    /// Returns the configuration the database was opened with
+    pub fn get_config(&self) -> &DatabaseConfig {
+        &self.config
+    }
+
+    /// Opens or creates a bucket
+    ///
+    /// `config` overrides the database wide bucket configuration for this bucket only.
+    /// Names are used as file names, so they may only contain ASCII letters, digits, `_` and
+    /// `-`. A `.` would let the files of one bucket pass for those of another, as they're named
+    /// after the bucket followed by extensions. Other names error with
+    /// `NonaneError::InvalidBucketName`.
+    ///
+    /// An existing bucket keeps its stored descriptor, a supplied `descriptor` describing other
+    /// fields errors with `NonaneError::SchemaMismatch`. Pass `None` to open it as stored.
     pub fn open_bucket(
         &mut self,
         name: &'a str,
         descriptor: Option<BucketDescription>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // Try to load an already existing bucket
-        let res = self.load_bucket(name.clone(), descriptor.clone());
-        match res {
-            Ok(b) => {
-                // Load an existing bucket if it exists
-                self.buckets.insert(name, b);
+        config: Option<BucketConfiguration>,
+    ) -> Result<(), NonaneError> {
+        validate_bucket_name(name)?;
+        let mut config = config.unwrap_or(self.config.bucket);
+        if self.config.threadless {
+            config.synchronous = true;
+            config.readers = 1;
+        }
+
+        // Reopening an already open bucket replaces it and doesn't count towards the limit
+        if let Some(limit) = self.config.max_open_buckets {
+            if !self.buckets.contains_key(name) && self.buckets.len() >= limit {
+                return Err(NonaneError::TooManyBuckets { limit });
             }
-            Err(e) => {
+        }
+
+        // Try to load an already existing bucket
+        let res = self.load_bucket(name, descriptor.clone(), config);
+        let bucket = match res {
+            // Load an existing bucket if it exists
+            Ok(b) => b,
+            Err(NonaneError::Io(e)) if e.kind() == ErrorKind::NotFound => {
+                // Checked before the file is created, so no empty bucket file is left behind
+                if descriptor.is_none() {
+                    return Err(NonaneError::MissingDescriptor {
+                        name: name.to_string(),
+                    });
+                }
+
                 // Create a new bucket if it doesn't exist
                 let p = self
                     .store_dir
                     .join(Path::new(&(name.to_owned() + EXTENSION)));
                 let pager = File::create(&p)?;
                 let pager = OpenOptions::new().read(true).write(true).open(&p)?;
-                self.buckets
-                    .insert(name, Bucket::new(name, pager, p, true, descriptor)?);
+                Bucket::new(name, pager, p, true, descriptor, config)?
+            }
+            // Never overwrite a bucket which exists but can't be loaded
+            Err(e) => return Err(e),
+        };
+
+        self.checkpoints
+            .insert(name.to_owned(), bucket.checkpoint_handle()?);
+        self.buckets.insert(name, bucket);
+
+        Ok(())
+    }
+
+    /// Opens or creates a bucket described by `T`, see `open_bucket`
+    ///
+    /// The descriptor returned by `T::get_description` is used like an explicitly supplied one,
+    /// an existing bucket storing other fields errors with `NonaneError::SchemaMismatch`.
+    pub fn open_bucket_for<T: BucketDesriptor>(
+        &mut self,
+        name: &'a str,
+    ) -> Result<(), NonaneError> {
+        self.open_bucket(name, Some(T::get_description()), None)
+    }
+
+    /// Checkpoints every open bucket, see `Bucket::checkpoint`
+    pub fn checkpoint(&self) -> Result<(), NonaneError> {
+        for b in self.buckets.iter() {
+            b.checkpoint()?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes a single open bucket, leaving writes queued for other buckets alone, see
+    /// `Bucket::flush`
+    pub fn flush_bucket(&self, name: &str) -> Result<(), NonaneError> {
+        let bucket = self
+            .buckets
+            .get(name)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: name.to_owned() })?;
+
+        bucket.flush()
+    }
+
+    /// Closes a bucket and stops keeping it open, leaving its files on disk
+    ///
+    /// Queued writes are written and the writer thread is stopped, see `Bucket::close`. The
+    /// bucket is loaded again by a later `open_bucket`. Errors with `NonaneError::BucketNotFound`
+    /// if no bucket named `name` is open.
+    pub fn close_bucket(&mut self, name: &str) -> Result<(), NonaneError> {
+        self.checkpoints.remove(name);
+        let (_, bucket) = self
+            .buckets
+            .remove(name)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: name.to_owned() })?;
+
+        bucket.close()
+    }
+
+    /// Closes every open bucket and releases the database, see `Bucket::close`
+    ///
+    /// Every bucket is closed even if closing one of them fails, the first error is returned.
+    pub fn close(self) -> Result<(), NonaneError> {
+        self.stop_auto_checkpoint();
+
+        let mut res = Ok(());
+        for b in self.buckets.iter() {
+            let closed = b.close();
+            if res.is_ok() {
+                res = closed;
             }
         }
 
+        res
+    }
+
+    /// Starts a background thread checkpointing every open bucket each `interval`
+    ///
+    /// Inserts are never blocked by a checkpoint, they keep being queued while it syncs.
+    /// Replaces an already running checkpoint thread. Errors with `NonaneError::ThreadsDisabled`
+    /// if the database was opened with `DatabaseConfig::threadless`.
+    pub fn start_auto_checkpoint(&self, interval: Duration) -> Result<(), NonaneError> {
+        if self.config.threadless {
+            return Err(NonaneError::ThreadsDisabled);
+        }
+
+        let mut auto_checkpoint = self.auto_checkpoint.lock();
+        if let Some(running) = auto_checkpoint.take() {
+            running.stop();
+        }
+
+        *auto_checkpoint = Some(AutoCheckpoint::start(interval, self.checkpoints.clone())?);
         Ok(())
     }
 
+    /// Stops the background checkpoint thread, waiting for a running checkpoint to finish
+    pub fn stop_auto_checkpoint(&self) {
+        if let Some(running) = self.auto_checkpoint.lock().take() {
+            running.stop();
+        }
+    }
+
     fn load_bucket(
         &self,
         name: &'a str,
         descriptor: Option<BucketDescription>,
-    ) -> Result<Bucket<'a>, Box<dyn std::error::Error>> {
+        config: BucketConfiguration,
+    ) -> Result<Bucket<'a>, NonaneError> {
         // Check if the bucket exists
         let p = self
             .store_dir
             .join(Path::new(&(name.to_owned() + EXTENSION)));
         if !p.exists() {
-            return Err(Box::new(Error::new(
+            return Err(NonaneError::Io(Error::new(
                 ErrorKind::NotFound,
                 "bucket was not found",
             )));
         }
 
         let file = OpenOptions::new().read(true).write(true).open(&p)?;
-        Ok(Bucket::new(name, file, p, false, descriptor)?)
+        Bucket::new(name, file, p, false, descriptor, config)
     }
 
-    pub fn borrow_buckets(&mut self) -> DashMap<&'a str, Bucket<'a>> {
-        self.buckets.clone()
+    /// Names of the open buckets, sorted
+    pub fn list_buckets(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.buckets.iter().map(|b| b.key().to_string()).collect();
+        names.sort();
+        names
+    }
+
+    /// Names of every bucket stored in the database directory, open or not, sorted
+    ///
+    /// Buckets are found by their files, files whose name isn't a valid bucket name are skipped.
+    pub fn list_all_buckets(&self) -> Result<Vec<String>, NonaneError> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(self.store_dir.as_ref())? {
+            let file_name = entry?.file_name();
+            let name = match file_name.to_str().and_then(|n| n.strip_suffix(EXTENSION)) {
+                Some(name) => name,
+                None => continue,
+            };
+
+            if validate_bucket_name(name).is_ok() {
+                names.push(name.to_owned());
+            }
+        }
+
+        names.sort();
+        Ok(names)
+    }
+
+    /// Whether a bucket named `name` is open or stored in the database directory
+    pub fn bucket_exists(&self, name: &str) -> bool {
+        if self.buckets.contains_key(name) {
+            return true;
+        }
+
+        validate_bucket_name(name).is_ok()
+            && self
+                .store_dir
+                .join(Path::new(&(name.to_owned() + EXTENSION)))
+                .is_file()
+    }
+
+    /// Borrows the map of open buckets, without cloning it
+    pub fn borrow_buckets(&self) -> &DashMap<&'a str, Bucket<'a>> {
+        &self.buckets
+    }
+
+    /// Borrows an open bucket, `None` if no bucket with the name is open
+    ///
+    /// Only the bucket's shard of the map is locked, for as long as the reference is held.
+    pub fn get_bucket(&self, name: &str) -> Option<Ref<'_, &'a str, Bucket<'a>>> {
+        self.buckets.get(name)
+    }
+
+    /// Mutably borrows an open bucket, `None` if no bucket with the name is open
+    ///
+    /// Writes through the database to buckets of the same shard wait until the reference is
+    /// dropped, see `with_bucket` to scope the borrow to a closure.
+    pub fn get_bucket_mut(&self, name: &str) -> Option<RefMut<'_, &'a str, Bucket<'a>>> {
+        self.buckets.get_mut(name)
+    }
+
+    /// Runs `f` on an open bucket, only locking that bucket while it runs
+    pub fn with_bucket<R, F>(&self, name: &str, f: F) -> Result<R, NonaneError>
+    where
+        F: FnOnce(&mut Bucket<'a>) -> R,
+    {
+        let mut bucket = self
+            .buckets
+            .get_mut(name)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: name.to_owned() })?;
+
+        Ok(f(&mut bucket))
     }
 
     /// Inserts a new key and value into a bucket
@@ -129,97 +431,1082 @@ impl<'a, 'b> Database<'a, 'b> {
         bucket: &str,
         key: isize,
         value: T,
-    ) -> Result<(usize, [u8; 24]), Box<dyn std::error::Error>> {
-        let bucket = self.buckets.get_mut(bucket);
-        let mut bucket = match bucket {
-            Some(b) => b,
-            None => {
-                return Err(Box::new(Error::new(
-                    ErrorKind::NotFound,
-                    "bucket was not found",
-                )))
-            }
-        };
+    ) -> Result<(usize, [u8; 24]), NonaneError> {
+        let document = convert_document(value)?;
+        self.insert_document(bucket, key, document)
+    }
+
+    /// Inserts an already built document into a bucket, validating it against the bucket's fields
+    pub fn insert_document(
+        &mut self,
+        bucket: &str,
+        key: isize,
+        document: Document,
+    ) -> Result<(usize, [u8; 24]), NonaneError> {
+        let mut bucket = self
+            .buckets
+            .get_mut(bucket)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: bucket.to_owned() })?;
+
+        validate_document(&bucket, &document)?;
 
-        // Get a document from the value
-        let document = value.convert_to();
-        let document = match document {
-            Some(d) => d,
-            None => {
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "failed to convert to document",
-                )))
+        bucket.insert(&document)
+    }
+
+    /// Inserts a new key and value into a bucket, returning once it has been synced to disk,
+    /// see `Bucket::insert_durable`
+    pub fn insert_durable<T: DocumentConvert>(
+        &mut self,
+        bucket: &str,
+        key: isize,
+        value: T,
+    ) -> Result<RowId, NonaneError> {
+        let document = convert_document(value)?;
+        let mut bucket = self
+            .buckets
+            .get_mut(bucket)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: bucket.to_owned() })?;
+
+        validate_document(&bucket, &document)?;
+        bucket.insert_durable(&document)
+    }
+
+    /// Inserts every value into a bucket, validating all of them before inserting any
+    ///
+    /// A value which can't be converted, doesn't match the bucket's fields or violates a unique
+    /// constraint errors with `NonaneError::InvalidBatchItem` holding its position, nothing is
+    /// inserted in that case. The values are queued as a single write, see
+    /// `Bucket::insert_batch`, so a full queue doesn't leave part of them inserted either.
+    pub fn insert_many<T: DocumentConvert>(
+        &mut self,
+        bucket: &str,
+        values: Vec<T>,
+    ) -> Result<Vec<RowId>, NonaneError> {
+        let mut bucket = self
+            .buckets
+            .get_mut(bucket)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: bucket.to_owned() })?;
+
+        let fields = field_description(&bucket);
+        let mut documents = Vec::with_capacity(values.len());
+        for (index, value) in values.into_iter().enumerate() {
+            let document = convert_document(value)
+                .and_then(|d| validate_fields(&fields, &d).map(|_| d))
+                .map_err(|e| NonaneError::InvalidBatchItem {
+                    index,
+                    error: Box::new(e),
+                })?;
+            documents.push(document);
+        }
+
+        bucket.insert_batch(&documents)
+    }
+
+    /// Inserts every valid value into a bucket, skipping the ones which fail
+    ///
+    /// Returns the ids of the inserted documents along with the position and error of every
+    /// value which couldn't be converted, validated or inserted.
+    pub fn insert_many_skip_invalid<T: DocumentConvert>(
+        &mut self,
+        bucket: &str,
+        values: Vec<T>,
+    ) -> Result<PartialInsert, NonaneError> {
+        let mut bucket = self
+            .buckets
+            .get_mut(bucket)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: bucket.to_owned() })?;
+
+        let fields = field_description(&bucket);
+        let mut inserted = Vec::new();
+        let mut errors = Vec::new();
+        for (index, value) in values.into_iter().enumerate() {
+            let res = convert_document(value)
+                .and_then(|d| validate_fields(&fields, &d).map(|_| d))
+                .and_then(|d| bucket.insert(&d));
+
+            match res {
+                Ok(id) => inserted.push(id),
+                Err(e) => errors.push((index, e)),
             }
-        };
+        }
 
-        {
-            let p = bucket.descriptor.as_ref().as_ref().unwrap().pull();
-            let p = p.as_ref();
-            let field_description = &p.field_description;
-
-            if document.get_fields().len() < field_description.len() {
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "to few fields where defined in insert request",
-                )));
-            } else if document.get_fields().len() > field_description.len() {
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "to many fields where defined in insert request",
-                )));
+        Ok((inserted, errors))
+    }
+
+    /// Converts every document written to `bucket` to `T`
+    ///
+    /// Errors with `InvalidData` if a document doesn't convert to `T`.
+    pub fn find<T: DocumentConvert<ConvertFrom = T>>(
+        &self,
+        bucket: &str,
+        key: isize,
+    ) -> Result<Vec<T>, NonaneError> {
+        let bucket = self
+            .buckets
+            .get(bucket)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: bucket.to_owned() })?;
+
+        bucket.scan()?.iter().map(convert_from_document).collect()
+    }
+
+    /// Converts the documents of `bucket` for which `pred` returns true to `T`
+    ///
+    /// Errors with `InvalidData` if a matching document doesn't convert to `T`.
+    pub fn find_where<T, F>(&self, bucket: &str, pred: F) -> Result<Vec<T>, NonaneError>
+    where
+        T: DocumentConvert<ConvertFrom = T>,
+        F: Fn(&Document) -> bool,
+    {
+        let bucket = self
+            .buckets
+            .get(bucket)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: bucket.to_owned() })?;
+
+        bucket.find_where(pred)?.iter().map(convert_from_document).collect()
+    }
+
+    /// Counts the documents of a bucket whose `field` holds the serialized `value`, see
+    /// `Bucket::index_count`
+    pub fn index_count(
+        &self,
+        bucket: &str,
+        field: &str,
+        value: &[u8],
+    ) -> Result<usize, NonaneError> {
+        let bucket = self
+            .buckets
+            .get(bucket)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: bucket.to_owned() })?;
+
+        bucket.index_count(field, value)
+    }
+
+    /// Finds every document in `bucket` whose `field` starts with `prefix`, see `Bucket::find_prefix`
+    ///
+    /// Errors with `InvalidData` if a matching document doesn't convert to `T`.
+    pub fn find_prefix<T: DocumentConvert<ConvertFrom = T>>(
+        &self,
+        bucket: &str,
+        field: &str,
+        prefix: &[u8],
+    ) -> Result<Vec<T>, NonaneError> {
+        let bucket = self
+            .buckets
+            .get(bucket)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: bucket.to_owned() })?;
+
+        bucket.find_prefix(field, prefix)?.iter().map(convert_from_document).collect()
+    }
+
+    /// Converts every document in `bucket` to `T` and collects the results of `f` which are `Some`
+    ///
+    /// Documents are read and converted one at a time, only the results are kept in memory.
+    /// Errors with `InvalidData` if a document doesn't convert to `T`.
+    pub fn scan_map<T, R, F>(&self, bucket: &str, mut f: F) -> Result<Vec<R>, NonaneError>
+    where
+        T: DocumentConvert<ConvertFrom = T>,
+        F: FnMut(T) -> Option<R>,
+    {
+        let bucket = self
+            .buckets
+            .get(bucket)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: bucket.to_owned() })?;
+
+        let mut results = Vec::new();
+        bucket.try_for_each_document(|_, d| {
+            results.extend(f(convert_from_document(d)?));
+            Ok(())
+        })?;
+
+        Ok(results)
+    }
+
+    /// Writes every document in `bucket` to `writer` as a JSON object per line
+    ///
+    /// Documents are written one at a time as they're read, see `document_to_json` for how
+    /// fields are decoded. Returns the amount of documents written.
+    pub fn export_jsonl<W: Write>(&self, bucket: &str, mut writer: W) -> Result<usize, NonaneError> {
+        let bucket = self
+            .buckets
+            .get(bucket)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: bucket.to_owned() })?;
+
+        let mut count = 0;
+        bucket.try_for_each_document(|_, d| {
+            serde_json::to_writer(&mut writer, &document_to_json(d))
+                .map_err(|e| NonaneError::Io(e.into()))?;
+            writer.write_all(b"\n")?;
+            count += 1;
+            Ok(())
+        })?;
+
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Copies the database descriptor and every stored bucket into `dest`, to back the database
+    /// up
+    ///
+    /// `dest` is created if it doesn't exist, files already in it are overwritten. Writes to each
+    /// open bucket, through any handle, are blocked while its files are copied, after its queued
+    /// writes are written, see `Bucket::hold_writes`. Reads aren't blocked. Other buckets keep
+    /// taking writes, so each bucket is copied consistently but not necessarily at the same point
+    /// in time as the others. Closed buckets are copied as stored. The copy can be opened with
+    /// `Database::open`.
+    pub fn snapshot_to(&self, dest: &Path) -> Result<(), NonaneError> {
+        fs::create_dir_all(dest)?;
+        if fs::canonicalize(dest)? == fs::canonicalize(self.store_dir.as_ref())? {
+            return Err(NonaneError::Io(Error::new(
+                ErrorKind::InvalidInput,
+                "can't snapshot a database into its own directory",
+            )));
+        }
+
+        let page_size = self
+            .descriptor
+            .as_ref()
+            .as_ref()
+            .map_or_else(page_size::get, |d| d.page_size);
+        copy_file(
+            &self.store_dir.join(DESCRIPTOR_FILE),
+            &dest.join(DESCRIPTOR_FILE),
+            page_size,
+        )?;
+
+        for name in self.list_all_buckets()? {
+            // Held until the bucket's files are copied, so no write lands in between
+            let bucket = self.buckets.get(name.as_str());
+            let _held = match bucket.as_ref() {
+                Some(bucket) => Some(bucket.hold_writes()?),
+                None => None,
+            };
+
+            for file in bucket_files(&self.store_dir, &name)? {
+                copy_file(&self.store_dir.join(&file), &dest.join(&file), page_size)?;
             }
+        }
+
+        trace!("Snapshotted database to {:?}", dest);
+        Ok(())
+    }
+
+    /// Deletes the document with the id returned by `insert`, see `Bucket::delete_at`
+    ///
+    /// Returns false if the bucket holds no document with the id. The document is looked up like
+    /// `Bucket::get_by_id` does, so it's found after `update_at` or `compact` moved it.
+    pub fn drop(&mut self, bucket: &str, id: &[u8; 24]) -> Result<bool, NonaneError> {
+        let mut bucket = self
+            .buckets
+            .get_mut(bucket)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: bucket.to_owned() })?;
+
+        match bucket.locate_id(id)? {
+            Some((offset, _)) => bucket.delete_at(offset),
+            None => Ok(false),
+        }
+    }
+
+    /// Mutably borrows an open bucket, like `get_bucket_mut`, erroring with
+    /// `NonaneError::BucketNotFound` if no bucket with the name is open
+    ///
+    /// Used like in `main.rs`, with `Account` implementing `BucketDesriptor` and
+    /// `DocumentConvert`. Not compiled as a doctest since the crate has no library target, see
+    /// the `get_mut_bucket_hands_out_a_usable_guard` test instead.
+    ///
+    /// ```ignore
+    /// let mut db = Database::open("./database")?;
+    /// db.open_bucket_for::<Account>("accounts")?;
+    ///
+    /// let mut buck = db.get_mut_bucket("accounts")?;
+    /// let count = buck.count_documents()?;
+    /// let account = Account::new("Anton", "Hagsér", "anton.hagser@epsidel.se", Vec::new());
+    /// buck.insert(&account.convert_to().unwrap())?;
+    /// buck.flush()?;
+    ///
+    /// assert_eq!(buck.count_documents()?, count + 1);
+    /// assert_eq!(buck.get_name(), "accounts");
+    /// ```
+    pub fn get_mut_bucket(
+        &self,
+        name: &str,
+    ) -> Result<RefMut<'_, &'a str, Bucket<'a>>, NonaneError> {
+        self.get_bucket_mut(name)
+            .ok_or_else(|| NonaneError::BucketNotFound { name: name.to_owned() })
+    }
+}
+
+/// Longest allowed bucket name, leaving room for the extensions of the bucket's files
+const MAX_BUCKET_NAME_LEN: usize = 200;
+
+/// Checks that a bucket name is safe to use as a file name within the database directory
+fn validate_bucket_name(name: &str) -> Result<(), NonaneError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_BUCKET_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if !valid {
+        return Err(NonaneError::InvalidBucketName {
+            name: name.to_owned(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Names of the files stored for the bucket named `name`, its page, overflow file and indexes
+///
+/// Bucket names hold no `.`, see `validate_bucket_name`, so only the files of the bucket start
+/// with its name followed by a `.`.
+fn bucket_files(store_dir: &Path, name: &str) -> Result<Vec<ffi::OsString>, NonaneError> {
+    let mut files = vec![ffi::OsString::from(name.to_owned() + EXTENSION)];
+    let overflow = format!("{}.{}", name, bucket::overflow::EXTENSION);
+    let prefix = name.to_owned() + ".";
+    for entry in fs::read_dir(store_dir)? {
+        let file_name = entry?.file_name();
+        let belongs = file_name.to_str().is_some_and(|f| {
+            f == overflow
+                || (f.starts_with(&prefix)
+                    && (f.ends_with(&format!(".{}", bucket::index::INDEX_EXTENSION))
+                        || f.ends_with(&format!(".{}", bucket::index::SORTED_INDEX_EXTENSION))))
+        });
+
+        if belongs {
+            files.push(file_name);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Copies the file at `src` to `dest` a page at a time, then syncs the copy to disk
+fn copy_file(src: &Path, dest: &Path, page_size: usize) -> Result<(), NonaneError> {
+    let mut src = File::open(src)?;
+    let mut dest = File::create(dest)?;
+    let mut page = vec![0; page_size];
+    loop {
+        let read = src.read(&mut page)?;
+        if read == 0 {
+            break;
+        }
+
+        dest.write_all(&page[..read])?;
+    }
+
+    dest.sync_all()?;
+    Ok(())
+}
+
+/// Converts a value to a document
+fn convert_document<T: DocumentConvert>(value: T) -> Result<Document, NonaneError> {
+    value.convert_to().ok_or_else(|| {
+        NonaneError::Io(Error::new(
+            ErrorKind::InvalidData,
+            "failed to convert to document",
+        ))
+    })
+}
+
+/// Converts a document to a value
+fn convert_from_document<T: DocumentConvert<ConvertFrom = T>>(
+    doc: &Document,
+) -> Result<T, NonaneError> {
+    T::convert_from(doc).ok_or_else(|| {
+        NonaneError::Io(Error::new(
+            ErrorKind::InvalidData,
+            "failed to convert from document",
+        ))
+    })
+}
+
+/// Checks that a document holds exactly the fields described by the bucket
+fn validate_document(bucket: &Bucket, document: &Document) -> Result<(), NonaneError> {
+    let p = bucket.descriptor.as_ref().as_ref().unwrap().pull();
+    validate_fields(&p.as_ref().field_description, document)
+}
+
+/// Copies the fields described by the bucket, to validate many documents with a single pull of
+/// the descriptor
+fn field_description(bucket: &Bucket) -> Vec<FieldDescriptor> {
+    let p = bucket.descriptor.as_ref().as_ref().unwrap().pull();
+    p.as_ref().field_description.clone()
+}
+
+/// Checks that a document holds exactly the described fields
+fn validate_fields(
+    field_description: &[FieldDescriptor],
+    document: &Document,
+) -> Result<(), NonaneError> {
+    if document.get_fields().len() < field_description.len() {
+        return Err(NonaneError::FieldMismatch {
+            reason: "to few fields where defined in insert request".to_owned(),
+        });
+    } else if document.get_fields().len() > field_description.len() {
+        return Err(NonaneError::FieldMismatch {
+            reason: "to many fields where defined in insert request".to_owned(),
+        });
+    }
+
+    // Current solution loops through all fields, won't be very effiecent with a big amount of fields
+    // Todo: Fix, solution is very slow (maybe a hashmap?)
+    // Todo: (maybe generics to match it? Might not be able to in current rust versions)
+    for f in document.get_fields().iter() {
+        let mut found_f = false;
+        for is_f in field_description.iter() {
+            if is_f.is_match(f) {
+                found_f = true;
 
-            // Current solution loops through all fields, won't be very effiecent with a big amount of fields
-            // Todo: Fix, solution is very slow (maybe a hashmap?)
-            // Todo: (maybe generics to match it? Might not be able to in current rust versions)
-            for f in document.get_fields().iter() {
-                let mut found_f = false;
-                for is_f in field_description.iter() {
-                    if is_f.is_match(f) {
-                        found_f = true;
+                match is_f.get_max_len() {
+                    Some(max_len) if f.get_data().len() > max_len => {
+                        return Err(NonaneError::FieldTooLong {
+                            field: f.get_key().to_string_lossy().into_owned(),
+                            max_len,
+                        });
                     }
+                    _ => {}
                 }
+            }
+        }
 
-                if !found_f {
-                    return Err(Box::new(Error::new(
-                        ErrorKind::NotFound,
-                        "field does not exist",
-                    )));
-                }
+        if !found_f {
+            return Err(NonaneError::FieldMismatch {
+                reason: format!("field {:?} does not exist", f.get_key()),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, thread};
+
+    use crate::{
+        utils::testing::{copy_dir, int_description, int_document, temp_path},
+        Account,
+    };
+
+    use super::{
+        bucket::{
+            config::{BucketConfiguration, DriveType},
+            descriptor::BucketDescription,
+            document::{
+                field::{descriptor::FieldDescriptor, fieldtype::FieldType, Field},
+                Document, DocumentConvert,
+            },
+        },
+        bucket_files, Database, DatabaseConfig, NonaneError,
+    };
+
+    /// Value holding a single named `Int32` field, the name lets it miss the bucket's fields
+    struct Number(&'static str, i32);
+
+    impl DocumentConvert for Number {
+        type ConvertFrom = Number;
+
+        fn convert_to(self) -> Option<Document> {
+            Some(Document::new(vec![Field::new(self.0, self.1)?]))
+        }
+
+        fn convert_from(doc: &Document) -> Option<Number> {
+            Some(Number("n", doc.get_i32("n")?))
+        }
+    }
+
+    fn count(db: &Database, bucket: &str) -> usize {
+        db.flush_bucket(bucket).unwrap();
+        db.get_mut_bucket(bucket).unwrap().count_documents().unwrap()
+    }
+
+    #[test]
+    fn insert_many_inserts_nothing_when_a_value_is_invalid() {
+        let mut db = Database::open(temp_path("database-insert-many-invalid")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+
+        let values = vec![Number("n", 1), Number("x", 2), Number("n", 3)];
+        let res = db.insert_many("numbers", values);
+        assert!(matches!(res, Err(NonaneError::InvalidBatchItem { index: 1, .. })));
+        assert_eq!(count(&db, "numbers"), 0);
+
+        let ids = db.insert_many("numbers", vec![Number("n", 1), Number("n", 2)]).unwrap();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(count(&db, "numbers"), 2);
+    }
+
+    #[test]
+    fn insert_many_inserts_nothing_on_a_unique_violation() {
+        let n = FieldDescriptor::new("n", FieldType::Int32).unwrap().unique();
+        let description = BucketDescription::new(vec![n]);
+        let mut db = Database::open(temp_path("database-insert-many-unique")).unwrap();
+        db.open_bucket("numbers", Some(description), None).unwrap();
+        db.insert("numbers", 0, Number("n", 1)).unwrap();
+
+        // Held by a stored document
+        let res = db.insert_many("numbers", vec![Number("n", 2), Number("n", 1)]);
+        assert!(matches!(res, Err(NonaneError::InvalidBatchItem { index: 1, .. })));
+
+        // Held by a document earlier in the batch
+        let values = vec![Number("n", 2), Number("n", 3), Number("n", 2)];
+        let res = db.insert_many("numbers", values);
+        assert!(matches!(res, Err(NonaneError::InvalidBatchItem { index: 2, .. })));
+        assert_eq!(count(&db, "numbers"), 1);
+
+        // Nothing of the failed batches was kept in the unique index
+        db.insert_many("numbers", vec![Number("n", 2), Number("n", 3)]).unwrap();
+        assert_eq!(count(&db, "numbers"), 3);
+    }
+
+    #[test]
+    fn insert_many_inserts_nothing_when_the_queue_is_full() {
+        let config = BucketConfiguration {
+            drive_type: DriveType::HDD,
+            queue_capacity: Some(1),
+            queue_full_wait: None,
+            ..Default::default()
+        };
+        let mut db = Database::open(temp_path("database-insert-many-queue-full")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), Some(config)).unwrap();
+
+        // The writer sleeps before draining the queue, so it's still full for the batch
+        db.insert_document("numbers", 0, int_document(1)).unwrap();
+        let res = db.insert_many("numbers", vec![Number("n", 2), Number("n", 3)]);
+        assert!(matches!(res, Err(NonaneError::QueueFull)));
+        assert_eq!(count(&db, "numbers"), 1);
+    }
+
+    #[test]
+    fn insert_many_skip_invalid_reports_skipped_values_by_position() {
+        let mut db = Database::open(temp_path("database-insert-many-skip")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+
+        let values = vec![Number("n", 1), Number("x", 2), Number("n", 3)];
+        let (inserted, errors) = db.insert_many_skip_invalid("numbers", values).unwrap();
+        assert_eq!(inserted.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 1);
+        assert!(matches!(errors[0].1, NonaneError::FieldMismatch { .. }));
+
+        db.flush_bucket("numbers").unwrap();
+        let bucket = db.get_bucket("numbers").unwrap();
+        let values: Vec<Option<i32>> = inserted
+            .iter()
+            .map(|(offset, _)| bucket.get_at(*offset as u64).unwrap().unwrap().get_i32("n"))
+            .collect();
+        assert_eq!(values, vec![Some(1), Some(3)]);
+    }
+
+    #[test]
+    fn snapshot_taken_during_inserts_reopens_whole() {
+        let dest = temp_path("database-snapshot-copy");
+        let mut db = Database::open(temp_path("database-snapshot")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket("numbers").unwrap().clone();
+        for n in 0..100 {
+            bucket.insert(&int_document(n)).unwrap();
+        }
+
+        // Inserts through a clone keep going while the snapshot is taken
+        let inserts = thread::spawn(move || {
+            for n in 100..2000 {
+                bucket.insert(&int_document(n)).unwrap();
             }
+        });
+        db.snapshot_to(Path::new(dest)).unwrap();
+        inserts.join().unwrap();
+        db.close().unwrap();
+
+        let mut copy = Database::open(dest).unwrap();
+        copy.open_bucket("numbers", None, None).unwrap();
+        let mut bucket = copy.get_bucket_mut("numbers").unwrap();
+        let count = bucket.count_documents().unwrap();
+        assert!(count >= 100);
+        assert_eq!(bucket.count_documents_slow().unwrap(), count);
+
+        let numbers: Vec<i32> = bucket
+            .scan()
+            .unwrap()
+            .iter()
+            .map(|d| d.get_i32("n").unwrap())
+            .collect();
+        assert_eq!(numbers, (0..count as i32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn buckets_follow_the_database_config_unless_overridden() {
+        let config = DatabaseConfig::new(BucketConfiguration {
+            readers: 2,
+            intern_field_names: true,
+            pad_rows: false,
+            ..Default::default()
+        });
+        let mut db = Database::open_with_config(temp_path("database-config"), config).unwrap();
+        db.open_bucket("configured", Some(int_description()), None).unwrap();
+        db.open_bucket("overridden", Some(int_description()), Some(BucketConfiguration::default()))
+            .unwrap();
+
+        let mut bucket = db.get_bucket_mut("configured").unwrap();
+        assert_eq!(bucket.get_config().readers, 2);
+        let offset = bucket.insert(&int_document(1)).unwrap().0 as u64;
+        bucket.flush().unwrap();
+
+        // Both readers are handed out, the pool doesn't open a third one
+        let readers = bucket.readers.as_ref().unwrap();
+        let mut first = readers.pull();
+        let second = readers.pull();
+        assert!(readers.try_pull().is_none());
+        drop(second);
+
+        let (header, _) = first.as_mut_ref().read_raw_row(offset).unwrap();
+        assert!(header.is_interned());
+        drop(first);
+        drop(bucket);
+
+        let bucket = db.get_bucket("overridden").unwrap();
+        assert_eq!(bucket.get_config(), &BucketConfiguration::default());
+    }
+
+    #[test]
+    fn get_mut_bucket_hands_out_a_usable_guard() {
+        let mut db = Database::open(temp_path("database-get-mut-bucket")).unwrap();
+        assert!(matches!(
+            db.get_mut_bucket("accounts"),
+            Err(NonaneError::BucketNotFound { .. })
+        ));
+
+        db.open_bucket("accounts", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_mut_bucket("accounts").unwrap();
+        assert_eq!(bucket.count_documents().unwrap(), 0);
+        bucket.insert(&int_document(1)).unwrap();
+        bucket.flush().unwrap();
+        assert_eq!(bucket.count_documents().unwrap(), 1);
+        assert_eq!(bucket.get_name(), "accounts");
+    }
+
+    #[test]
+    fn create_and_open_existing_are_strict() {
+        let path = temp_path("database-strict-open");
+        assert!(matches!(Database::open_existing(path), Err(NonaneError::NotFound)));
+
+        Database::create(path).unwrap().close().unwrap();
+        assert!(matches!(Database::create(path), Err(NonaneError::AlreadyExists)));
+        Database::open_existing(path).unwrap();
+    }
+
+    #[test]
+    fn opening_past_the_bucket_limit_errors() {
+        let config = DatabaseConfig {
+            max_open_buckets: Some(2),
+            ..Default::default()
+        };
+        let path = temp_path("database-bucket-limit");
+        let mut db = Database::open_with_config(path, config).unwrap();
+        db.open_bucket("first", Some(int_description()), None).unwrap();
+        db.open_bucket("second", Some(int_description()), None).unwrap();
+        assert!(matches!(
+            db.open_bucket("third", Some(int_description()), None),
+            Err(NonaneError::TooManyBuckets { limit: 2 })
+        ));
+        assert!(!db.bucket_exists("third"));
+
+        // Reopening an open bucket doesn't count towards the limit
+        db.open_bucket("second", Some(int_description()), None).unwrap();
+    }
+
+    #[test]
+    fn flush_bucket_only_flushes_the_named_bucket() {
+        let slow = BucketConfiguration {
+            drive_type: DriveType::HDD,
+            ..Default::default()
+        };
+        let mut db = Database::open(temp_path("database-flush-bucket")).unwrap();
+        db.open_bucket("flushed", Some(int_description()), None).unwrap();
+        db.open_bucket("queued", Some(int_description()), Some(slow)).unwrap();
+        let mut flushed = db.get_bucket("flushed").unwrap().clone();
+        let mut queued = db.get_bucket("queued").unwrap().clone();
+
+        for n in 0..10 {
+            flushed.insert(&int_document(n)).unwrap();
+            queued.insert(&int_document(n)).unwrap();
+        }
+        db.flush_bucket("flushed").unwrap();
+
+        assert_eq!(flushed.count_documents_slow().unwrap(), 10);
+        assert!(queued.queue_depth() > 0);
+        assert!(matches!(
+            db.flush_bucket("missing"),
+            Err(NonaneError::BucketNotFound { name }) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn insert_document_reads_back_a_hand_built_document() {
+        let mut db = Database::open(temp_path("database-insert-document")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+
+        let document = Document::new(vec![Field::new("n", 42).unwrap()]);
+        let (offset, _) = db.insert_document("numbers", 0, document).unwrap();
+        db.flush_bucket("numbers").unwrap();
+
+        let bucket = db.get_bucket("numbers").unwrap();
+        let read = bucket.get_at(offset as u64).unwrap().unwrap();
+        assert_eq!(read.get_i32("n"), Some(42));
+        drop(bucket);
+
+        let invalid = Document::new(vec![Field::new("missing", 1).unwrap()]);
+        assert!(db.insert_document("numbers", 0, invalid).is_err());
+        assert_eq!(count(&db, "numbers"), 1);
+    }
+
+    #[test]
+    fn scan_map_projects_and_filters_in_one_pass() {
+        let mut db = Database::open(temp_path("database-scan-map")).unwrap();
+        db.open_bucket_for::<Account>("accounts").unwrap();
+        for email in &["ada@example.com", "grace@spam.test", "alan@example.com"] {
+            db.insert("accounts", 0, Account::new("first", "last", email, vec![1, 2, 3]))
+                .unwrap();
         }
+        db.flush_bucket("accounts").unwrap();
+
+        let mut emails = db
+            .scan_map("accounts", |account: Account| {
+                Some(account.email).filter(|email| !email.ends_with("@spam.test"))
+            })
+            .unwrap();
+        emails.sort();
+        assert_eq!(emails, vec!["ada@example.com", "alan@example.com"]);
+    }
+
+    #[test]
+    fn reads_error_on_documents_which_dont_convert() {
+        let mut db = Database::open(temp_path("database-convert-errors")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        db.insert("numbers", 0, Number("n", 1)).unwrap();
+        db.flush_bucket("numbers").unwrap();
 
-        Ok(bucket.insert(&document)?)
+        assert_eq!(db.find::<Number>("numbers", 0).unwrap().len(), 1);
+        assert!(db.find::<Account>("numbers", 0).is_err());
+        assert!(db.find_where::<Account, _>("numbers", |_| true).is_err());
+        assert!(db.scan_map("numbers", |account: Account| Some(account.email)).is_err());
     }
 
-    pub fn find<T>(&self, bucket: &str, key: isize) -> std::io::Result<Vec<T>> {
-        let bucket = self.buckets.get(bucket);
-        let bucket = match bucket {
-            Some(b) => b,
-            None => return Err(Error::new(ErrorKind::NotFound, "bucket was not found")),
+    #[test]
+    fn export_jsonl_writes_a_decoded_object_per_line() {
+        let description = BucketDescription::new(vec![
+            FieldDescriptor::new("name", FieldType::Text).unwrap(),
+            FieldDescriptor::new("n", FieldType::Int32).unwrap(),
+            FieldDescriptor::new("data", FieldType::Bytes).unwrap(),
+        ]);
+        let mut db = Database::open(temp_path("database-export-jsonl")).unwrap();
+        db.open_bucket("things", Some(description), None).unwrap();
+        for n in 0..3 {
+            let document = Document::new(vec![
+                Field::new("name", format!("thing {}", n)).unwrap(),
+                Field::new("n", n).unwrap(),
+                Field::new("data", &[n as u8, 0xff][..]).unwrap(),
+            ]);
+            db.insert_document("things", 0, document).unwrap();
+        }
+        db.flush_bucket("things").unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(db.export_jsonl("things", &mut out).unwrap(), 3);
+
+        let mut lines: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        lines.sort_by_key(|line| line["n"].as_i64());
+        assert_eq!(lines.len(), 3);
+        for (n, line) in lines.iter().enumerate() {
+            assert_eq!(line["name"], format!("thing {}", n));
+            assert_eq!(line["n"], n as i64);
+            assert_eq!(line["data"], base64::encode([n as u8, 0xff]));
+            let id = line["_id"].as_str().unwrap();
+            assert_eq!(uuid::Uuid::parse_str(id).unwrap().to_hyphenated().to_string(), id);
+        }
+    }
+
+    #[test]
+    fn bucket_names_escaping_the_store_are_rejected() {
+        let path = temp_path("database-bucket-names");
+        let mut db = Database::open(path).unwrap();
+        for name in &["../evil", "a/b", "a\\b", "..", "", "v1.archive"] {
+            assert!(
+                matches!(
+                    db.open_bucket(name, Some(int_description()), None),
+                    Err(NonaneError::InvalidBucketName { name: rejected }) if rejected == *name
+                ),
+                "{:?} was accepted",
+                name
+            );
+        }
+        assert!(!Path::new(path).join("../evil.page").exists());
+
+        for name in &["accounts", "user_events-2024", "v1-archive"] {
+            db.open_bucket(name, Some(int_description()), None).unwrap();
+        }
+        assert_eq!(db.list_buckets().len(), 3);
+    }
+
+    #[test]
+    fn durable_insert_survives_a_crash_right_after_it_returns() {
+        let slow = BucketConfiguration {
+            drive_type: DriveType::HDD,
+            ..Default::default()
         };
+        let path = temp_path("database-insert-durable");
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("numbers", Some(int_description()), Some(slow)).unwrap();
+        for n in 0..5 {
+            db.insert("numbers", 0, Number("n", n)).unwrap();
+        }
+        let (offset, _) = db.insert_durable("numbers", 0, Number("n", 42)).unwrap();
+
+        // The copy is what a crash leaves behind, the database is neither flushed nor closed
+        let crashed = temp_path("database-insert-durable-crashed");
+        copy_dir(path, crashed);
 
-        Ok(Vec::new())
+        let mut reopened = Database::open(crashed).unwrap();
+        reopened.open_bucket("numbers", None, None).unwrap();
+        let bucket = reopened.get_bucket("numbers").unwrap();
+        let read = bucket.get_at(offset as u64).unwrap().unwrap();
+        assert_eq!(read.get_i32("n"), Some(42));
+        drop(bucket);
+
+        db.flush_bucket("numbers").unwrap();
+        assert_eq!(count(&db, "numbers"), 6);
     }
 
-    pub fn drop<T>(&mut self, bucket: &str, key: isize) -> std::io::Result<Vec<T>> {
-        let bucket = self.buckets.get_mut(bucket);
-        let bucket = match bucket {
-            Some(b) => b,
-            None => return Err(Error::new(ErrorKind::NotFound, "bucket was not found")),
+    #[test]
+    fn with_bucket_reads_through_the_map_in_place() {
+        let mut db = Database::open(temp_path("database-with-bucket")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        for n in 0..3 {
+            db.insert("numbers", 0, Number("n", n)).unwrap();
+        }
+        db.flush_bucket("numbers").unwrap();
+
+        assert!(std::ptr::eq(db.borrow_buckets(), &db.buckets));
+        let counted = db.with_bucket("numbers", |b| b.count_documents()).unwrap();
+        assert_eq!(counted.unwrap(), 3);
+        assert!(matches!(
+            db.with_bucket("missing", |b| b.count_documents()),
+            Err(NonaneError::BucketNotFound { name }) if name == "missing"
+        ));
+    }
+
+    /// Amount of threads of the process named `name`, as listed by Linux
+    #[cfg(target_os = "linux")]
+    fn threads_named(name: &str) -> usize {
+        std::fs::read_dir("/proc/self/task")
+            .unwrap()
+            .filter_map(|task| std::fs::read_to_string(task.ok()?.path().join("comm")).ok())
+            .filter(|comm| comm.trim_end() == name)
+            .count()
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn threadless_database_spawns_no_threads() {
+        let mut threaded = Database::open(temp_path("database-threaded")).unwrap();
+        threaded.open_bucket("threaded_ctl", Some(int_description()), None).unwrap();
+        assert_eq!(threads_named("threaded_ctl"), 1);
+
+        let config = DatabaseConfig {
+            threadless: true,
+            ..Default::default()
         };
+        let mut db = Database::open_with_config(temp_path("database-threadless"), config).unwrap();
+        db.open_bucket("threadless_b", Some(int_description()), None).unwrap();
+        for n in 0..10 {
+            db.insert("threadless_b", 0, Number("n", n)).unwrap();
+        }
+        let bucket = db.get_bucket("threadless_b").unwrap();
+        assert_eq!(bucket.count_documents_slow().unwrap(), 10);
+        assert!(bucket.writer_thread.is_none());
+        drop(bucket);
+        assert_eq!(db.find::<Number>("threadless_b", 0).unwrap().len(), 10);
+        assert_eq!(threads_named("threadless_b"), 0);
+        assert!(matches!(
+            db.start_auto_checkpoint(std::time::Duration::from_secs(1)),
+            Err(NonaneError::ThreadsDisabled)
+        ));
+    }
+
+    #[test]
+    fn close_writes_the_queue_and_joins_the_writer() {
+        let slow = BucketConfiguration {
+            drive_type: DriveType::HDD,
+            ..Default::default()
+        };
+        let path = temp_path("database-close");
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("closing_b", Some(int_description()), Some(slow)).unwrap();
+        let mut clone = db.get_bucket("closing_b").unwrap().clone();
+        for n in 0..100 {
+            db.insert("closing_b", 0, Number("n", n)).unwrap();
+        }
+        assert!(clone.queue_depth() > 0);
+        #[cfg(target_os = "linux")]
+        assert_eq!(threads_named("closing_b"), 1);
 
-        Ok(Vec::new())
+        db.close().unwrap();
+        #[cfg(target_os = "linux")]
+        assert_eq!(threads_named("closing_b"), 0);
+        assert!(matches!(
+            clone.insert(&int_document(100)),
+            Err(NonaneError::BucketClosed { .. })
+        ));
+        drop(clone);
+
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("closing_b", None, None).unwrap();
+        assert_eq!(count(&db, "closing_b"), 100);
     }
 
-    pub fn get_mut_bucket(&'a mut self, bucket: &str) -> std::io::Result<RefMut<&'a str, Bucket>> {
-        let bucket = self.buckets.get_mut(bucket);
-        let bucket = match bucket {
-            Some(b) => b,
-            None => return Err(Error::new(ErrorKind::NotFound, "bucket was not found")),
+    #[test]
+    fn dropped_field_is_neither_read_nor_required() {
+        let description = BucketDescription::new(vec![
+            FieldDescriptor::new("n", FieldType::Int32).unwrap(),
+            FieldDescriptor::new("note", FieldType::Text).unwrap(),
+        ]);
+        let full = |n: i32| {
+            Document::new(vec![
+                Field::new("n", n).unwrap(),
+                Field::new("note", "old".to_string()).unwrap(),
+            ])
         };
+        let mut db = Database::open(temp_path("database-drop-field")).unwrap();
+        db.open_bucket("notes", Some(description), None).unwrap();
+        let (before, _) = db.insert_document("notes", 0, full(1)).unwrap();
+        db.flush_bucket("notes").unwrap();
+
+        db.get_bucket_mut("notes").unwrap().drop_field("note").unwrap();
+        assert!(db.insert_document("notes", 0, full(2)).is_err());
+        let (after, _) = db.insert_document("notes", 0, int_document(3)).unwrap();
+        db.flush_bucket("notes").unwrap();
+
+        let bucket = db.get_bucket("notes").unwrap();
+        let read = bucket.get_at(before as u64).unwrap().unwrap();
+        assert_eq!(read.get_i32("n"), Some(1));
+        assert!(read.read_field("note").is_none());
+        let read = bucket.get_at(after as u64).unwrap().unwrap();
+        assert_eq!(read.get_i32("n"), Some(3));
+        drop(bucket);
+
+        assert!(matches!(
+            db.get_bucket_mut("notes").unwrap().drop_field("note"),
+            Err(NonaneError::FieldNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn closed_bucket_is_reloaded_from_its_file() {
+        let mut db = Database::open(temp_path("database-close-bucket")).unwrap();
+        db.open_bucket("evicted_b", Some(int_description()), None).unwrap();
+        db.open_bucket("kept", Some(int_description()), None).unwrap();
+        for n in 0..10 {
+            db.insert("evicted_b", 0, Number("n", n)).unwrap();
+        }
+
+        db.close_bucket("evicted_b").unwrap();
+        assert_eq!(db.list_buckets(), vec!["kept".to_string()]);
+        assert!(db.get_bucket("evicted_b").is_none());
+        #[cfg(target_os = "linux")]
+        assert_eq!(threads_named("evicted_b"), 0);
+        assert!(matches!(
+            db.close_bucket("evicted_b"),
+            Err(NonaneError::BucketNotFound { .. })
+        ));
+
+        db.open_bucket("evicted_b", None, None).unwrap();
+        let mut numbers: Vec<i32> = db
+            .find::<Number>("evicted_b", 0)
+            .unwrap()
+            .into_iter()
+            .map(|n| n.1)
+            .collect();
+        numbers.sort_unstable();
+        assert_eq!(numbers, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn values_past_the_max_length_are_rejected_after_a_reopen() {
+        let field = FieldDescriptor::new("email", FieldType::Text).unwrap();
+        let description = BucketDescription::new(vec![field.with_max_len(16)]);
+        let email = |email: &str| {
+            Document::new(vec![Field::new("email", email.to_string()).unwrap()])
+        };
+        let path = temp_path("database-max-len");
+        {
+            let mut db = Database::open(path).unwrap();
+            db.open_bucket("emails", Some(description), None).unwrap();
+            db.insert_document("emails", 0, email("ada@example.com")).unwrap();
+            db.flush_bucket("emails").unwrap();
+        }
+
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("emails", None, None).unwrap();
+        db.insert_document("emails", 0, email("grace@hopper.io")).unwrap();
+        assert!(matches!(
+            db.insert_document("emails", 0, email("a.much.longer.address@example.com")),
+            Err(NonaneError::FieldTooLong { field, .. }) if field == "email"
+        ));
+        assert_eq!(count(&db, "emails"), 2);
+    }
+
+    #[test]
+    fn drop_finds_documents_moved_by_updates_and_compaction() {
+        let description =
+            BucketDescription::new(vec![FieldDescriptor::new("text", FieldType::Text).unwrap()]);
+        let text = |t: &str| Document::new(vec![Field::new("text", t.to_string()).unwrap()]);
+        let mut db = Database::open(temp_path("database-drop-moved")).unwrap();
+        db.open_bucket("texts", Some(description), None).unwrap();
+
+        let (grown, compacted) = {
+            let mut bucket = db.get_bucket_mut("texts").unwrap();
+            let (offset, grown) = bucket.insert(&text("short")).unwrap();
+            let (removed, _) = bucket.insert(&text("removed")).unwrap();
+            let (_, compacted) = bucket.insert(&text("compacted")).unwrap();
+            bucket.flush().unwrap();
+
+            // Growing the first document appends it, deleting the second moves the third
+            let moved = bucket.update_at(offset as u64, &text("a lot longer than before")).unwrap();
+            assert_ne!(moved.0, offset as u64);
+            bucket.delete_at(removed as u64).unwrap();
+            bucket.compact().unwrap();
+            (grown, compacted)
+        };
+
+        assert!(db.drop("texts", &grown).unwrap());
+        assert!(db.drop("texts", &compacted).unwrap());
+        assert!(!db.drop("texts", &grown).unwrap());
+
+        let mut bucket = db.get_bucket_mut("texts").unwrap();
+        bucket.flush().unwrap();
+        assert_eq!(bucket.get_by_id(&grown).unwrap(), None);
+        assert_eq!(bucket.count_documents().unwrap(), 0);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 0);
+    }
+
+    #[test]
+    fn bucket_files_only_lists_the_files_of_the_bucket() {
+        let path = temp_path("database-bucket-files");
+        let mut db = Database::open(path).unwrap();
+        for name in &["v1", "v1-archive"] {
+            db.open_bucket(name, Some(int_description()), None).unwrap();
+            db.get_bucket_mut(name).unwrap().create_index("n").unwrap();
+        }
 
-        Ok(bucket)
+        let mut files: Vec<_> = bucket_files(Path::new(path), "v1")
+            .unwrap()
+            .into_iter()
+            .map(|f| f.into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["v1.n.idx", "v1.page"]);
     }
 }
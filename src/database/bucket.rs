@@ -1,9 +1,10 @@
 use std::{
-    collections::VecDeque,
+    collections::{BTreeMap, VecDeque},
     convert::TryInto,
-    fs::File,
-    io::{Error, ErrorKind, Read, Seek, SeekFrom, Write},
-    path::PathBuf,
+    ffi::{CStr, CString},
+    fs::{self, File},
+    io::{BufWriter, Error, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
@@ -12,27 +13,51 @@ use std::{
 };
 
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
-use crossbeam_queue::ArrayQueue;
 use fs2::*;
-use parking_lot::Mutex;
-use reader::Reader;
+use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
+use iter::BucketIter;
+use schema::Schema;
+use reader::{Reader, ReaderFactory, ReaderLease};
 use serde::{Deserialize, Serialize};
 
-use descriptor::BucketDescription;
+use descriptor::{
+    BucketDescription, BucketDescriptionV3, BucketDescriptionV4, BucketDescriptionV5,
+    LegacyBucketDescription,
+};
 
-use crate::utils::{self, pool::Pool};
+use crate::{
+    database::{checkpoint::CheckpointHandle, error::NonaneError},
+    utils::{self, pool::Pool},
+};
 
 use self::{
-    document::Document,
+    config::BucketConfiguration,
+    document::{
+        field::{descriptor::FieldDescriptor, fieldtype::FieldType, Field},
+        csv, raw, Document,
+    },
+    index::ValueIndex,
+    overflow::{Overflow, OverflowRef},
+    row::RowHeader,
+    unique::UniqueIndex,
     writer::{
-        queued::{QueuedWriteInformation, QueuedWriter, WriterThread},
-        Writer,
+        count_location, last_write_location, offset_location,
+        queued::{QueuedWriteInformation, QueuedWriter, WriteQueue, WriterThread},
+        wal::WriteAheadLog,
+        WriteGuard, Writer,
     },
 };
 
 pub mod descriptor;
 pub mod document;
+pub mod encryption;
+pub mod index;
+pub mod iter;
+pub mod overflow;
 pub mod reader;
+pub mod row;
+pub mod schema;
+pub mod unique;
 pub mod writer;
 pub mod config;
 
@@ -44,17 +69,272 @@ static MIN_FREE_BYTES: u64 = 1_048_576; // A minimum of 1 MB of free space
 
 static MAX_ITEMS_IN_QUEUE: usize = 50000;
 
+/// Most rows `Bucket::reserve` makes room for, as a multiple of the write queue's capacity
+const MAX_RESERVE_FACTOR: usize = 16;
+
+/// Identifies an inserted document, returned by `Bucket::insert`
+///
+/// Holds the offset of the document's row and its id, see `pack_id`.
+pub type RowId = (usize, [u8; 24]);
+
+/// Name of the field holding the uuid of every stored document
+pub const ID_FIELD: &str = "_id";
+
+/// Packs the uuid of a document and the offset of its row into a document id
+pub fn pack_id(uuid: uuid::Uuid, offset: u64) -> [u8; 24] {
+    let mut id = [0; 24];
+    id[..16].copy_from_slice(uuid.as_bytes());
+    LittleEndian::write_u64(&mut id[16..], offset);
+    id
+}
+
+/// Splits a document id created by `pack_id` into the uuid and row offset of the document
+pub fn unpack_id(id: &[u8; 24]) -> (uuid::Uuid, u64) {
+    let mut uuid = [0; 16];
+    uuid.copy_from_slice(&id[..16]);
+    (uuid::Uuid::from_bytes(uuid), LittleEndian::read_u64(&id[16..]))
+}
+
+/// Name of the unique constraint backing the primary key, see `Bucket::set_primary_key`
+pub const PRIMARY_KEY_CONSTRAINT: &str = "primary_key";
+
+/// Identifies a bucket file, stored in its first bytes
+const FORMAT_MAGIC: [u8; 4] = *b"NNDB";
+
+/// Version of the bucket file format written by this build, stored after `FORMAT_MAGIC`
+///
+/// Files written before the version was stored carry no magic and are reported as version 0.
+/// Version 1 files lack the stored document count, version 2 files the field names and
+/// defaults of the descriptor, version 3 files its compression codec, version 4 files the
+/// maximum lengths of its fields and version 5 files their unique flags, they're upgraded when
+/// opened. Version 6 files store the length of the descriptor as a `u32`, see
+/// `WIDE_DESCRIPTOR_VERSION`, and version 7 files don't store where the last write started, see
+/// `LAST_WRITE_VERSION`.
+pub const FORMAT_VERSION: u32 = 8;
+
+/// Oldest format version which can still be opened, see `Bucket::upgrade_format`
+const MIN_FORMAT_VERSION: u32 = 1;
+
+/// Extension of the file a compaction writes the compacted bucket to, see `Bucket::compact`
+const COMPACTING_EXTENSION: &str = "compacting";
+
+/// Extension the compacted bucket is renamed to once it's complete, it's copied over the bucket
+/// file from there
+const COMPACTED_EXTENSION: &str = "compacted";
+
+/// First format version storing the length of the descriptor as a `u64`, older versions store
+/// a `u32`
+const WIDE_DESCRIPTOR_VERSION: u32 = 7;
+
+/// First format version storing the offset the rows of the last write start at, see
+/// `writer::last_write_location`
+///
+/// Older versions reserve only the offset for next document and the document count at the end
+/// of the first page, their tail is checked from the first row on open.
+const LAST_WRITE_VERSION: u32 = 8;
+
+/// Offset within the first page of the offsets stored at its end in a file of `version`, the
+/// descriptor has to end in front of it to fit the first page
+fn page_tail_start(version: u32) -> u64 {
+    if version < LAST_WRITE_VERSION {
+        offset_location()
+    } else {
+        last_write_location()
+    }
+}
+
+/// Amount of bytes in front of the descriptor in a file of `version`, the magic, format version
+/// and descriptor length
+fn file_header_size(version: u32) -> u64 {
+    let len_size = if version < WIDE_DESCRIPTOR_VERSION {
+        std::mem::size_of::<u32>()
+    } else {
+        std::mem::size_of::<u64>()
+    };
+
+    (FORMAT_MAGIC.len() + std::mem::size_of::<u32>() + len_size) as u64
+}
+
+/// Writes the file header of a bucket in format `version` whose descriptor is `len` bytes long
+///
+/// Versions before `WIDE_DESCRIPTOR_VERSION` are only written for descriptors fitting the first
+/// page, so their length fits the `u32` they store it as.
+fn write_file_header(file: &mut File, version: u32, len: u64) -> std::io::Result<()> {
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&FORMAT_MAGIC)?;
+    file.write_u32::<LittleEndian>(version)?;
+    if version < WIDE_DESCRIPTOR_VERSION {
+        file.write_u32::<LittleEndian>(len as u32)
+    } else {
+        file.write_u64::<LittleEndian>(len)
+    }
+}
+
+/// Reads the file header of a bucket, returning its format version and the length of its
+/// descriptor
+///
+/// Errors with `NonaneError::UnsupportedFormat` if the file was written in a version which can't
+/// be opened.
+fn read_file_header(file: &mut File) -> Result<(u32, u64), NonaneError> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut magic = [0; 4];
+    file.read_exact(&mut magic)?;
+    if magic != FORMAT_MAGIC {
+        return Err(NonaneError::UnsupportedFormat { version: 0 });
+    }
+
+    let version = file.read_u32::<LittleEndian>()?;
+    if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&version) {
+        return Err(NonaneError::UnsupportedFormat { version });
+    }
+
+    let len = if version < WIDE_DESCRIPTOR_VERSION {
+        file.read_u32::<LittleEndian>()? as u64
+    } else {
+        file.read_u64::<LittleEndian>()?
+    };
+
+    Ok((version, len))
+}
+
+/// Offset of the descriptor and of the first row in a file of `version` whose descriptor is
+/// `len` bytes long
+///
+/// A descriptor which fits the first page follows the file header, a larger one starts at the
+/// second page and spans as many pages as it needs. Rows start at the first page boundary after
+/// the descriptor.
+fn descriptor_layout(version: u32, len: u64) -> (u64, u64) {
+    let page = page_size::get() as u64;
+    let header_size = file_header_size(version);
+
+    // The offsets and document count are stored in the last bytes of the first page
+    if header_size.saturating_add(len) <= page_tail_start(version) {
+        (header_size, page)
+    } else {
+        let pages = len.div_ceil(page);
+        (page, pages.saturating_mul(page).saturating_add(page))
+    }
+}
+
+/// Writes the file header and the descriptor serialized to `buf` as laid out by
+/// `descriptor_layout`
+///
+/// The rest of the pages in front of the rows is zeroed, so the file never holds leftovers of a
+/// previous descriptor. The offsets and count stored at the end of the first page are kept.
+fn write_descriptor(file: &mut File, version: u32, buf: &[u8]) -> std::io::Result<()> {
+    let header_size = file_header_size(version);
+    let (start, data_start) = descriptor_layout(version, buf.len() as u64);
+
+    let mut region = vec![0; (data_start - header_size) as usize];
+    let at = (start - header_size) as usize;
+    region[at..at + buf.len()].copy_from_slice(buf);
+
+    // Rows start on a page boundary, the region is the rest of the first page and any pages
+    // the descriptor spills into
+    let page = page_size::get() as u64;
+    write_file_header(file, version, buf.len() as u64)?;
+    file.write_all(&region[..(page_tail_start(version) - header_size) as usize])?;
+    file.seek(SeekFrom::Start(page))?;
+    file.write_all(&region[(page - header_size) as usize..])
+}
+
+/// Walks the row headers of `file` from `offset` up to `end`, returning where the valid rows end
+/// and the amount of documents they hold
+///
+/// A row whose header ends the data, see `RowHeader::ends_data`, or which reaches past `end`
+/// ends the valid rows.
+fn walk_rows(file: &mut File, mut offset: u64, end: u64) -> std::io::Result<(u64, u64)> {
+    let mut count = 0;
+    while offset + row::HEADER_SIZE <= end {
+        file.seek(SeekFrom::Start(offset))?;
+        let header = RowHeader::from_raw(file.read_u64::<LittleEndian>()?);
+        if header.ends_data() || offset + header.len > end {
+            break;
+        }
+
+        count += !header.is_deleted() as u64;
+        offset += header.len;
+    }
+
+    Ok((offset, count))
+}
+
+/// Geometry of a bucket file, see `Bucket::layout_info`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LayoutInfo {
+    pub page_size: u64,
+    /// Offset of the serialized descriptor
+    pub descriptor_start: u64,
+    /// Length of the serialized descriptor
+    pub descriptor_len: u64,
+    /// Offset of the first row
+    pub data_start: u64,
+    /// Version of the file format, see `FORMAT_VERSION`
+    pub format_version: u32,
+    /// Offset of the next row as stored on disk, trails behind while writes are queued
+    pub stored_offset: u64,
+    /// Multiple rows are padded to, 1 if rows aren't padded
+    pub row_alignment: u64,
+    /// Codec compressed rows are stored with
+    pub compression: row::Compression,
+}
+
+/// Size and fragmentation of a bucket, see `Bucket::stats`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BucketStats {
+    /// Size of the bucket file on disk
+    pub file_size: u64,
+    /// Offset right after the last row written to disk
+    pub offset: u64,
+    /// Documents written to disk
+    pub documents: usize,
+    /// Rows of deleted documents, they're kept until the bucket is compacted
+    pub tombstones: usize,
+    /// Bytes taken up by the rows of deleted documents, headers included
+    pub tombstone_bytes: u64,
+    /// Bytes following the documents within their rows, added to align them or left behind by
+    /// documents replaced in place with smaller ones
+    pub padding_bytes: u64,
+}
+
+/// Space reclaimed by `Bucket::compact`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompactionStats {
+    /// Bytes the bucket file shrank by
+    pub bytes_reclaimed: u64,
+    /// Documents stored at another offset afterwards
+    pub documents_moved: usize,
+}
+
 #[derive(Clone)]
 /// A bucket defines a datastructure, it contains a whole database within it
 pub struct Bucket<'a> {
     pub(crate) name: Arc<&'a str>,
     pub(crate) path: Arc<PathBuf>,
-    pub(crate) descriptor: Arc<Option<Pool<BucketDescription>>>,
-    pub(crate) will_write: Arc<AtomicBool>,
-    pub(crate) readers: Option<Arc<Pool<Reader<'a>>>>,
+    pub(crate) descriptor: Arc<Option<Pool<BucketDescription, BucketDescription>>>,
+    pub(crate) will_write: Arc<RwLock<()>>,
+    pub(crate) readers: Option<Arc<Pool<Reader<'a>, ReaderFactory<'a>>>>,
     pub(crate) writer: Arc<Mutex<Writer<'a>>>,
     pub(crate) writer_thread: Option<WriterThread>,
     pub(crate) atomic_offset: Arc<AtomicUsize>,
+    /// Held by inserts from reserving the offset of their row until it's queued, see `append`
+    pub(crate) appending: Arc<Mutex<()>>,
+    /// Shared by every change to the bucket's files, held exclusively while they're copied, see
+    /// `hold_writes`
+    pub(crate) changing: Arc<RwLock<()>>,
+    pub(crate) config: BucketConfiguration,
+    pub(crate) overflow: Arc<Overflow>,
+    pub(crate) unique: Arc<Mutex<UniqueIndex>>,
+    /// Value indexes created with `create_index`
+    pub(crate) indexes: Arc<Mutex<Vec<ValueIndex>>>,
+    /// Field names and defaults, shared with the readers
+    pub(crate) schema: Arc<RwLock<Schema>>,
+    /// Offset of the first row, right after the descriptor
+    pub(crate) data_start: u64,
+    /// Log of the queued writes, see `BucketConfiguration::write_ahead_log`
+    pub(crate) wal: Option<Arc<Mutex<WriteAheadLog>>>,
+    /// Whether the file stores where the last write started, see `LAST_WRITE_VERSION`
+    pub(crate) stores_last_write: Arc<AtomicBool>,
 }
 
 impl<'a> Bucket<'a> {
@@ -65,24 +345,31 @@ impl<'a> Bucket<'a> {
         path: PathBuf,
         should_init: bool,
         descriptor: Option<BucketDescription>,
-    ) -> Result<Bucket<'a>, Box<dyn std::error::Error>> {
-        let will_write = Arc::new(AtomicBool::new(false));
+        config: BucketConfiguration,
+    ) -> Result<Bucket<'a>, NonaneError> {
+        // A pool can never be empty, it would panic on the first pull
+        let config = BucketConfiguration {
+            readers: config.readers.max(1),
+            queue_capacity: config.queue_capacity.map(|c| c.max(1)),
+            ..config
+        };
+
+        let will_write = Arc::new(RwLock::new(()));
+        let stores_last_write = Arc::new(AtomicBool::new(false));
 
         // Initialize single writer
         let writer = Arc::new(Mutex::new(
-            Writer::new(name, &path.clone(), will_write.clone())
-                .expect("Failed to initialize writer for bucket"),
+            Writer::new(name, &path.clone(), will_write.clone(), stores_last_write.clone())?,
         ));
 
         // Initialize write queue
         let should_exit = Arc::new(AtomicBool::new(false));
-        let has_data: Arc<Mutex<bool>> = Arc::new(Mutex::new(false));
-        let write_queue: ArrayQueue<QueuedWriteInformation> = ArrayQueue::new(MAX_ITEMS_IN_QUEUE);
-        let write_queue = Arc::new(write_queue);
+        let write_queue = Arc::new(WriteQueue::new(config.queue_capacity));
 
         // Clones to be used within WriteThread struct to handle multi threaded writes
         let write_queue_cl = write_queue.clone();
         let should_exit_cl = should_exit.clone();
+        let will_write_cl = will_write.clone();
 
         // Path for QueuedWriter to write at
         let p = path.clone();
@@ -97,6 +384,16 @@ impl<'a> Bucket<'a> {
             will_write: will_write.clone(),
             writer_thread: None,
             atomic_offset: Arc::new(AtomicUsize::new(0)),
+            appending: Arc::new(Mutex::new(())),
+            changing: Arc::new(RwLock::new(())),
+            config,
+            overflow: Arc::new(Overflow::new(&path)),
+            unique: Arc::new(Mutex::new(UniqueIndex::default())),
+            indexes: Arc::new(Mutex::new(Vec::new())),
+            schema: Arc::new(RwLock::new(Schema::default())),
+            data_start: page_size::get() as u64,
+            wal: None,
+            stores_last_write,
         };
 
         trace!(
@@ -105,78 +402,161 @@ impl<'a> Bucket<'a> {
         );
 
         // Initialize and load bucket
+        let mut compacted = false;
         if should_init {
             bucket.initialize(descriptor)?;
         } else {
+            // A compaction which was complete before a crash is copied over before reading
+            compacted = bucket.resume_compaction()?;
             bucket.load_page()?;
+
+            // The stored descriptor is used, a differing one was most likely edited by mistake
+            if let Some(descriptor) = descriptor.as_ref() {
+                let fields = bucket.get_description().mismatched_fields(descriptor);
+                if !fields.is_empty() {
+                    return Err(NonaneError::SchemaMismatch { fields });
+                }
+            }
+        }
+
+        bucket.schema = Arc::new(RwLock::new(Schema::new(&bucket.get_description())));
+
+        // Writes left in the log by a crash are written before the offset is read
+        if config.write_ahead_log && !config.synchronous {
+            let (mut wal, pending) = WriteAheadLog::open(&WriteAheadLog::path(&path))?;
+            if !should_init {
+                bucket.replay_wal(&pending)?;
+            }
+            wal.clear()?;
+            bucket.wal = Some(Arc::new(Mutex::new(wal)));
         }
+        let wal = bucket.wal.clone();
+        let stores_last_write = bucket.stores_last_write.clone();
 
         // Temporary reader to read initial offset
-        let mut reader = Reader::new(name, &path.clone(), will_write.clone(), None)
-            .expect("Failed to initialize reader for pool");
+        let mut reader = Reader::new(name, &path.clone(), will_write.clone(), None, None)?;
         let offset = reader.get_offset()? as usize;
+        let count = reader.get_stored_count()?;
         bucket.atomic_offset = Arc::new(AtomicUsize::new(offset));
 
         // Create the thread for writing for this bucket (and all clones of this bucket)
-        let (sender, receiver) = std::sync::mpsc::channel();
-        let thread = thread::Builder::new()
-            .name(name.into())
-            .spawn(move || {
-                let mut writer = QueuedWriter::new(p, write_queue, should_exit);
-                let _ = sender.send(writer.1);
-                writer.0.start(100_000_000);
-                writer.0
-            })
-            .unwrap();
+        if !config.synchronous {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let thread = thread::Builder::new()
+                .name(name.into())
+                .spawn(move || {
+                    let (writer, writer_thread) = QueuedWriter::new(
+                        p,
+                        write_queue,
+                        should_exit,
+                        will_write_cl,
+                        config.sync_policy,
+                        offset as u64,
+                        count,
+                    );
+                    let mut writer = writer.with_wal(wal).with_last_write(stores_last_write);
+                    let _ = sender.send(writer_thread);
+                    writer.start(config.drive_type);
+                    writer
+                })
+                .unwrap();
 
-        // Recieve Writer and assign it
-        let mut writer_thread = receiver.recv()?;
-        writer_thread.join_handle = Some(Arc::new(thread));
+            // Recieve Writer and assign it
+            let writer_thread = receiver
+                .recv()
+                .map_err(|_| Error::other("writer thread exited before starting"))?;
+            *writer_thread.join_handle.lock() = Some(thread);
 
-        // Assign thread data
-        bucket.writer_thread = Some(writer_thread);
+            // Assign thread data
+            bucket.writer_thread = Some(writer_thread);
+        }
 
-        // Initialize multi-readers
-        let readers = Pool::new(num_cpus::get(), || {
-            Reader::new(
-                name,
-                &path.clone(),
-                will_write.clone(),
-                Some(bucket.atomic_offset.clone()),
-            )
-            .expect("Failed to initialize reader for pool")
-        });
+        // Initialize multi-readers, more are opened under contention up to the configured amount
+        let factory = ReaderFactory {
+            name,
+            path,
+            will_write,
+            offset: bucket.atomic_offset.clone(),
+            overflow: bucket.overflow.clone(),
+            schema: bucket.schema.clone(),
+            key: config.encryption_key,
+            memory_mapped: config.memory_mapped,
+        };
+        let readers = Pool::try_lazy(1, config.readers, factory)?;
 
         // Assign readers
         bucket.readers = Some(Arc::new(readers));
+        bucket.upgrade_format()?;
+
+        // Rebuild the index backing the unique constraints from the stored documents
+        bucket.load_unique_index()?;
+        bucket.load_indexes()?;
+        if compacted {
+            bucket.finish_compaction()?;
+        }
 
         Ok(bucket)
     }
 
+    /// Writes the writes left in the write-ahead log by a crash to the bucket file, see
+    /// `BucketConfiguration::write_ahead_log`
+    ///
+    /// A write whose bytes are already stored was written before the crash, its change to the
+    /// document count isn't applied again.
+    fn replay_wal(&mut self, pending: &[QueuedWriteInformation]) -> Result<(), NonaneError> {
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut wrt = self.writer.lock();
+        let file = wrt.borrow_file();
+        file.seek(SeekFrom::Start(offset_location()))?;
+        let mut offset = file.read_u64::<LittleEndian>()?;
+
+        let mut documents = 0;
+        for info in pending.iter() {
+            let mut stored = vec![0; info.bytes.len()];
+            file.seek(SeekFrom::Start(info.seek.0))?;
+            let written = file.read_exact(&mut stored).is_ok() && stored == info.bytes;
+            if !written {
+                file.seek(SeekFrom::Start(info.seek.0))?;
+                file.write_all(&info.bytes)?;
+                documents += info.documents;
+            }
+
+            if !info.overwrite {
+                offset = offset.max(info.seek.1);
+            }
+        }
+
+        wrt.set_offset(offset)?;
+        wrt.add_to_count(documents)?;
+        wrt.borrow_file().sync_data()?;
+
+        info!("Replayed {} writes of bucket {} from its write-ahead log", pending.len(), self.name);
+        Ok(())
+    }
+
     pub fn initialize(
         &mut self,
         descriptor: Option<BucketDescription>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), NonaneError> {
         // Check if there are enough bytes of free space to run a database
         let stats = fs2::statvfs(self.path.as_ref())?;
         if stats.free_space() < MIN_FREE_BYTES {
-            return Err(Box::new(Error::new(
-                ErrorKind::AlreadyExists,
-                "Out of free space to initialize bucket",
-            )));
+            return Err(NonaneError::OutOfSpace);
         }
 
         // Check if the descriptor is defined
-        if descriptor.is_none() {
-            panic!(
-                "Couldn't initialize bucket due to descriptor not defined {}",
-                self.path.file_name().unwrap().to_str().unwrap()
-            );
-        } else {
-            self.descriptor = Arc::new(Some(Pool::new(num_cpus::get(), || {
-                descriptor.clone().unwrap()
-            })));
-        }
+        let descriptor = match descriptor {
+            Some(descriptor) => descriptor,
+            None => {
+                return Err(NonaneError::MissingDescriptor {
+                    name: self.name.to_string(),
+                })
+            }
+        };
+        self.descriptor = Arc::new(Some(Pool::lazy(1, self.config.readers, descriptor)));
 
         // Initialize the page and write it to disk
         self.initialize_page()?;
@@ -186,173 +566,3176 @@ impl<'a> Bucket<'a> {
 
     /// ### Lock the writer and prepare for insert
     /// Must be called before writing to a file as it will otherwise affect performance for reads
-    /// writes without calling this might error other reads
-    pub fn toggle_writer(&mut self) {
-        if self.will_write.load(Ordering::SeqCst) {
-            self.will_write.swap(true, Ordering::SeqCst);
-        } else {
-            self.will_write.swap(false, Ordering::SeqCst);
-        }
+    /// Waits for the reads in progress, reads wait until the returned guard is dropped or give up
+    /// after `reader::MAX_WRITE_WAIT`
+    pub fn begin_write(&self) -> WriteGuard {
+        WriteGuard::new(self.will_write.clone())
     }
 
     /// ### Initializes a page with the following structure
     ///
-    /// `Length of BucketDescription` as u16
+    /// `Length of BucketDescription` as u64
     ///
-    /// `BucketDescription`
+    /// `BucketDescription`, spanning as many pages as it needs, see `descriptor_layout`
     ///
     /// `Rows` are written below this
-    pub fn initialize_page(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn initialize_page(&mut self) -> Result<(), NonaneError> {
         trace!("Initializing initial page for bucket {}", self.name);
 
-        // Writes the descriptor to disk (WARN: Takes up a whol page)
-        let buf;
-        {
+        let buf = {
             let p = self.descriptor.as_ref().as_ref().unwrap().pull();
-            let r = p.as_ref();
-            let mut d = bincode::serialize(r)?;
-
-            let len = page_size::get() - d.len();
-            let mut append = Vec::with_capacity(len);
-            unsafe { append.set_len(len) };
-            d.append(&mut append);
-
-            buf = d;
-        }
+            bincode::serialize(p.as_ref())?
+        };
+        let (_, data_start) = descriptor_layout(FORMAT_VERSION, buf.len() as u64);
 
-        let buf = buf.as_slice();
-        self.toggle_writer();
         {
+            let _guard = self.begin_write();
             let mut wrt = self.writer.lock();
             let file = wrt.borrow_file();
-            file.write_u16::<LittleEndian>(buf.len().try_into().unwrap())?;
-            file.write(buf)?;
-            wrt.set_offset(page_size::get().try_into().unwrap())?;
+            write_descriptor(file, FORMAT_VERSION, &buf)?;
+            file.set_len(data_start)?;
+            self.stores_last_write.store(true, Ordering::SeqCst);
+            wrt.set_last_write(data_start)?;
+            wrt.set_offset(data_start)?;
         }
-        self.toggle_writer();
 
+        self.data_start = data_start;
         Ok(())
     }
 
-    /// Load an already existing page from a bucket
-    pub fn load_page(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        // Create a temporary reader
-        let mut reader = Reader::new(&self.name, &self.path, self.will_write.clone(), None)?;
-        let mut file = reader.borrow_file();
-        file.seek(SeekFrom::Start(0))?;
+    /// Replaces the bucket's descriptor, writing it to disk
+    ///
+    /// Once documents have been inserted the descriptor has to fit in the pages in front of
+    /// them, otherwise this errors with `NonaneError::DescriptorTooLarge`.
+    fn rewrite_descriptor(&mut self, descriptor: BucketDescription) -> Result<(), NonaneError> {
+        let changing = self.changing.clone();
+        let _changing = changing.read_recursive();
+        let buf = bincode::serialize(&descriptor)?;
+        let len = buf.len() as u64;
+        let mut version = FORMAT_VERSION;
+        let (_, mut data_start) = descriptor_layout(version, len);
+
+        let mut wrt = self.writer.lock();
+        let empty = self.atomic_offset.load(Ordering::SeqCst) as u64 == self.data_start;
+        if data_start != self.data_start && !empty {
+            // A descriptor which only fits the first page in the layout of a previous version,
+            // behind its smaller header or in front of its smaller tail, keeps that layout
+            // rather than moving the rows
+            let legacy = [LAST_WRITE_VERSION - 1, WIDE_DESCRIPTOR_VERSION - 1];
+            match legacy.iter().find(|v| descriptor_layout(**v, len).1 == self.data_start) {
+                Some(legacy) => {
+                    version = *legacy;
+                    data_start = self.data_start;
+                }
+                None => return Err(NonaneError::DescriptorTooLarge),
+            }
+        }
+
+        write_descriptor(wrt.borrow_file(), version, &buf)?;
 
-        // Read the descriptor length and then the bucket descriptor
-        let len = file.read_u16::<LittleEndian>()?;
-        let mut buf = Vec::with_capacity(len.into());
-        unsafe { buf.set_len(len.into()) };
-        file.read(&mut buf)?;
+        // The tail of an older layout may hold part of its descriptor, it's checked from the
+        // first row until a write stores where it started
+        let stores_last_write = version >= LAST_WRITE_VERSION;
+        if self.stores_last_write.swap(stores_last_write, Ordering::SeqCst) != stores_last_write {
+            wrt.set_last_write(0)?;
+        }
+
+        // An empty bucket can move its first row to make room for the descriptor
+        if data_start != self.data_start {
+            wrt.borrow_file().set_len(data_start)?;
+            wrt.set_offset(data_start)?;
+            self.atomic_offset
+                .store(data_start as usize, Ordering::SeqCst);
+            self.data_start = data_start;
+        }
+        wrt.borrow_file().sync_data()?;
 
-        self.descriptor = Arc::new(Some(Pool::new(num_cpus::get(), || {
-            bincode::deserialize::<BucketDescription>(buf.as_slice()).unwrap()
-        })));
+        self.descriptor = Arc::new(Some(Pool::lazy(1, self.config.readers, descriptor)));
         Ok(())
     }
 
-    pub fn get_name(&self) -> &str {
-        &self.name
+    pub fn load_page(&mut self) -> Result<(), NonaneError> {
+        // Create a temporary reader
+        let mut reader = Reader::new(&self.name, &self.path, self.will_write.clone(), None, None)?;
+        let mut file = reader.borrow_file();
+
+        // Check the format, then read the descriptor length and the bucket descriptor
+        let (version, len) = read_file_header(&mut file)?;
+        let (start, data_start) = descriptor_layout(version, len);
+
+        // A length reaching past the end of the file is corrupt, it's not allocated
+        if start.saturating_add(len) > file.metadata()?.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "descriptor reaches past the end of the bucket file",
+            )
+            .into());
+        }
+        let mut buf = vec![0; len as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut buf)?;
+
+        let descriptor = if version < 3 {
+            bincode::deserialize::<LegacyBucketDescription>(buf.as_slice())?.into()
+        } else if version < 4 {
+            bincode::deserialize::<BucketDescriptionV3>(buf.as_slice())?.into()
+        } else if version < 5 {
+            bincode::deserialize::<BucketDescriptionV4>(buf.as_slice())?.into()
+        } else if version < 6 {
+            bincode::deserialize::<BucketDescriptionV5>(buf.as_slice())?.into()
+        } else {
+            bincode::deserialize::<BucketDescription>(buf.as_slice())?
+        };
+        self.descriptor = Arc::new(Some(Pool::lazy(1, self.config.readers, descriptor)));
+        self.data_start = data_start;
+        self.stores_last_write
+            .store(version >= LAST_WRITE_VERSION, Ordering::SeqCst);
+
+        self.repair_tail()
     }
 
-    /// Insert a document into the store
-    pub fn insert(
-        &mut self,
-        document: &Document,
-    ) -> Result<(usize, [u8; 24]), Box<dyn std::error::Error>> {
-        let offset = self
-            .readers
-            .as_ref()
-            .unwrap()
-            .pull()
-            .as_mut_ref()
-            .get_offset()?;
+    /// Truncates a record torn by a crash while a chunk was written, along with anything past it
+    ///
+    /// Walks the row headers from where the last write started up to the stored offset, rows in
+    /// front of it were complete before it was written. Files which don't store where the last
+    /// write started are walked from the first row. A row whose header ends the data, see
+    /// `RowHeader::ends_data`, or which reaches past the stored offset or the end of the file
+    /// ends the valid rows. Rows reaching the stored offset are left as they are, along with any
+    /// space past it, like the space preallocated by `reserve`. Otherwise the file is truncated
+    /// after the last valid row and the offset and document count are set from the rows which
+    /// were kept.
+    fn repair_tail(&mut self) -> Result<(), NonaneError> {
+        let mut wrt = self.writer.lock();
+        let last_write = wrt.get_last_write()?;
+        let file = wrt.borrow_file();
+        let file_len = file.metadata()?.len();
+        file.seek(SeekFrom::Start(offset_location()))?;
+        let stored = file.read_u64::<LittleEndian>()?;
 
-        // Serialize document
-        let mut data = document.serialize()?;
+        let from = match last_write {
+            Some(from) if (self.data_start..=stored).contains(&from) => from,
+            _ => self.data_start,
+        };
+        let (valid, _) = walk_rows(file, from, stored.min(file_len))?;
+        if valid == stored {
+            return Ok(());
+        }
 
-        // Todo: Change to constant across whole DB
-        let additional_bytes = std::mem::size_of::<u64>();
-        let len = utils::numbers::round_to_multiple(data.len(), 8);
-        data.resize(len, 0);
+        // Only a torn record has the rows counted, the count stored with it may include it
+        let (_, count) = walk_rows(file, self.data_start, valid)?;
+        warn!(
+            "Bucket {} has a torn record, truncating it from {} to {} bytes",
+            self.name, file_len, valid
+        );
+        file.set_len(valid)?;
+        wrt.set_last_write(valid)?;
+        wrt.set_offset(valid)?;
+        wrt.set_count(count)?;
+        wrt.borrow_file().sync_data()?;
 
-        // Add length infront
-        let mut buf = Vec::new();
+        Ok(())
+    }
 
-        // Add length of document to ease reading
-        let mut len = Vec::new();
-        len.write_u64::<LittleEndian>((data.len() + std::mem::size_of::<u64>()) as u64)?;
-        buf.append(&mut len);
+    /// Rewrites a file written in an older format version as `FORMAT_VERSION`
+    ///
+    /// Version 1 files get the document count stored, by counting their rows once. The
+    /// descriptor of older files is written in the current layout.
+    fn upgrade_format(&mut self) -> Result<(), NonaneError> {
+        let (version, _) = {
+            let mut reader = self.readers.as_ref().unwrap().pull();
+            let mut file = reader.as_mut_ref().borrow_file();
+            read_file_header(&mut file)?
+        };
+        if version == FORMAT_VERSION {
+            return Ok(());
+        }
 
-        // Finally move the serialized data to the buffer
-        buf.append(&mut data);
+        if version < 2 {
+            // The header is written along with the descriptor below
+            let count = self.count_documents_slow()? as u64;
+            let mut wrt = self.writer.lock();
+            wrt.set_count(count)?;
+            wrt.borrow_file().sync_data()?;
 
-        let slice = buf.as_slice();
+            if let Some(writer_thread) = self.writer_thread.as_ref() {
+                let offset = self.atomic_offset.load(Ordering::SeqCst) as u64;
+                writer_thread.reset_stored(offset, count);
+            }
+        }
 
-        // Calculate new offset
-        let new_offset = offset + slice.len() as u64;
+        // The descriptor was loaded in the legacy layout, it's written back in the current one
+        self.rewrite_descriptor(self.get_description())
+    }
 
-        // Todo: Replace Try (?) with match to handle writing errors
-        // ! Not a big issue right now, but eventually it will become one 🚀
-        self.atomic_offset
-            .store(new_offset.try_into().unwrap(), Ordering::SeqCst);
+    fn load_unique_index(&mut self) -> Result<(), NonaneError> {
+        let mut index = UniqueIndex::new(&self.get_description());
+        if !index.is_empty() {
+            self.for_each_document(|offset, document| index.insert(document, offset))?;
+        }
 
-        // Set up queued write object
-        let info = QueuedWriteInformation {
-            seek: (offset, new_offset),
-            len: buf.len(),
-            bytes: buf,
-        };
+        self.unique = Arc::new(Mutex::new(index));
+        Ok(())
+    }
 
-        // Push it to the queue or error if it's full
-        // (not very effiecent, however exceeding X amount of inserts per second might be a problem, time to add a new cluster)
-        // Or I guess, if you're cool, add more ram
-        let wrt_thrd = self.writer_thread.as_ref().unwrap();
-        let res = wrt_thrd.q.push(info);
-        match res {
-            Ok(_) => {}
-            Err(e) => {
-                return Err(Box::new(std::io::Error::new(
-                    ErrorKind::TimedOut,
-                    "Out of queue space",
-                )));
+    /// Calls `f` with the offset and contents of every document written to disk
+    pub(crate) fn for_each_document<F>(&self, mut f: F) -> Result<(), NonaneError>
+    where
+        F: FnMut(u64, &Document),
+    {
+        self.try_for_each_document(|offset, document| {
+            f(offset, document);
+            Ok(())
+        })
+    }
+
+    /// Calls `f` with the offset and contents of every document written to disk, stopping at
+    /// the first error returned by `f`
+    pub(crate) fn try_for_each_document<F>(&self, mut f: F) -> Result<(), NonaneError>
+    where
+        F: FnMut(u64, &Document) -> Result<(), NonaneError>,
+    {
+        let mut reader = self.readers.as_ref().unwrap().pull();
+        let reader = reader.as_mut_ref();
+        let end = reader.get_stored_offset()?;
+
+        let mut offset = self.data_start;
+        while offset < end {
+            let (document, len) = reader.read_document_entry(offset)?;
+            if let Some(document) = document {
+                f(offset, &document)?;
             }
+            offset += len;
         }
 
-        // Todo: Implement indexing!
-        // Todo: Handle events with file.sync_all()
-        Ok((new_offset as usize, [0; 24]))
+        Ok(())
     }
 
-    pub fn count_documents(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+    /// Writes every document written to disk to `writer` as CSV, after a header naming the
+    /// bucket's fields
+    ///
+    /// The columns follow the order the fields are described in, whatever order the documents
+    /// hold them in, fields a document doesn't hold are left empty. See `csv::field_to_csv` for
+    /// how fields are formatted. Returns the amount of documents written.
+    pub fn export_csv<W: Write>(&self, mut writer: W) -> Result<usize, NonaneError> {
+        let description = self.get_description();
+        let columns: Vec<&CStr> = description
+            .field_description
+            .iter()
+            .map(|f| f.get_name())
+            .collect();
+
+        csv::write_header(&mut writer, &columns)?;
         let mut count = 0;
+        self.try_for_each_document(|_, document| {
+            csv::write_document(&mut writer, document, &columns)?;
+            count += 1;
+            Ok(())
+        })?;
 
-        // Borrow a reader
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Reads every document written to disk, in the order they were inserted
+    ///
+    /// Documents still queued for the writer thread aren't returned.
+    pub fn scan(&self) -> Result<Vec<Document>, NonaneError> {
+        let mut documents = Vec::new();
+        self.for_each_document(|_, document| documents.push(document.clone()))?;
+
+        Ok(documents)
+    }
+
+    /// Iterates over the documents written to disk, in the order they were inserted
+    ///
+    /// Documents are read lazily, one per call to `next`, so the bucket is never held in memory
+    /// as a whole. Documents inserted after the call aren't returned, nor are deleted ones.
+    pub fn iter(&self) -> Result<BucketIter<'_, 'a>, NonaneError> {
+        BucketIter::new(self.readers.as_ref().unwrap().pull(), self.data_start)
+    }
+
+    /// Reads a page of at most `take` documents written to disk, following the first `skip`
+    /// documents, in the order they were inserted
+    ///
+    /// Deleted documents don't count towards `skip` and `take`. Reading stops once the page is
+    /// full, the last page of the bucket holds fewer documents and skipping past the end returns
+    /// none. The skipped documents are still read, deep pages take longer than early ones.
+    pub fn find_page(&self, skip: usize, take: usize) -> Result<Vec<Document>, NonaneError> {
+        let mut iter = self.iter()?;
+        for document in iter.by_ref().take(skip) {
+            document?;
+        }
+
+        iter.take(take).collect()
+    }
+
+    /// Reads every document written to disk for which `pred` returns true
+    ///
+    /// Only a single pooled reader is borrowed, other readers of the pool stay available to
+    /// concurrent queries.
+    pub fn find_where<F>(&self, pred: F) -> Result<Vec<Document>, NonaneError>
+    where
+        F: Fn(&Document) -> bool,
+    {
+        let mut found = Vec::new();
+        self.for_each_document(|_, document| {
+            if pred(document) {
+                found.push(document.clone());
+            }
+        })?;
+
+        Ok(found)
+    }
+
+    /// Calls `f` with the offset, raw bytes and contents of every document written to disk
+    ///
+    /// The raw bytes are the row's payload after decompression and without padding, for rows
+    /// which aren't interned or hold overflow references they're the same as
+    /// `Document::serialize`. Every row is only parsed once, so unchanged rows can be copied
+    /// without serializing them again.
+    pub fn scan_both<F>(&self, mut f: F) -> Result<(), NonaneError>
+    where
+        F: FnMut(usize, &[u8], &Document),
+    {
         let mut reader = self.readers.as_ref().unwrap().pull();
         let reader = reader.as_mut_ref();
-        let mut file = reader.borrow_file();
+        let end = reader.get_stored_offset()?;
 
-        let mut offset = page_size::get() as u64;
-        loop {
-            file.seek(SeekFrom::Start(offset))?;
+        let mut offset = self.data_start;
+        while offset < end {
+            let entry = reader.read_row_entry(offset)?;
+            if let Some((row, document)) = entry.contents {
+                f(offset as usize, &row, &document);
+            }
+            offset += entry.len;
+        }
+
+        Ok(())
+    }
+
+    /// Finds every document whose `field` starts with the bytes in `prefix`
+    ///
+    /// Compares the field's serialized bytes, which for `Text` fields are its UTF-8 bytes. An
+    /// empty prefix matches every document containing the field. If the field has an index, see
+    /// `create_index` and `create_sorted_index`, the values it holds are compared and only the
+    /// matching documents are read. Otherwise every document written to disk is scanned. Either
+    /// way documents still queued for the writer thread aren't found.
+    pub fn find_prefix(&self, field: &str, prefix: &[u8]) -> Result<Vec<Document>, NonaneError> {
+        let indexed = self
+            .indexes
+            .lock()
+            .iter()
+            .find(|i| i.get_field() == field)
+            .map(|i| i.prefixed(prefix));
 
-            let size = file.read_u64::<LittleEndian>();
-            let size = match size {
-                Ok(s) => s,
-                Err(_) => {
-                    break;
+        let mut found = Vec::new();
+        match indexed {
+            Some(mut offsets) => {
+                let end = self.readers.as_ref().unwrap().pull().as_mut_ref().get_stored_offset()?;
+                offsets.retain(|o| *o < end);
+                offsets.sort_unstable();
+                for offset in offsets {
+                    if let Some(document) = self.get_at(offset)? {
+                        found.push(document);
+                    }
                 }
-            };
+            }
+            None => self.for_each_document(|_, document| {
+                let matches = document
+                    .read_field(field)
+                    .is_some_and(|f| f.get_data().starts_with(prefix));
+                if matches {
+                    found.push(document.clone());
+                }
+            })?,
+        }
 
-            offset += size as u64;
-            count += 1;
+        Ok(found)
+    }
+
+    /// Leases a reader which only sees the rows written to disk so far
+    ///
+    /// Rows appended while the lease is held, by inserts or by updates moving a document, aren't
+    /// visible through it, so long operations such as exports don't chase a growing bucket. Rows
+    /// which were already visible aren't pinned, documents updated in place or deleted while the
+    /// lease is held read as they are on disk. The lease holds one of the bucket's pooled readers
+    /// until it's dropped.
+    pub fn lease_reader(&self) -> Result<ReaderLease<'_, 'a>, NonaneError> {
+        ReaderLease::new(self.readers.as_ref().unwrap().pull(), self.data_start)
+    }
+
+    /// Attaches a key/value pair to the bucket, replacing any value already set for `key`
+    ///
+    /// Metadata is stored along with the descriptor and kept when the bucket is reopened.
+    pub fn set_meta(&mut self, key: &str, value: &str) -> Result<(), NonaneError> {
+        let mut descriptor = self.get_description();
+        descriptor.metadata.insert(key.to_owned(), value.to_owned());
+        self.rewrite_descriptor(descriptor)
+    }
+
+    /// Returns the metadata value set for `key`
+    pub fn get_meta(&self, key: &str) -> Option<String> {
+        self.get_description().metadata.get(key).cloned()
+    }
+
+    /// Returns every metadata key/value pair attached to the bucket
+    pub fn list_meta(&self) -> BTreeMap<String, String> {
+        self.get_description().metadata
+    }
+
+    /// Adds a field to the bucket's descriptor, documents stored before hold `default` for it
+    ///
+    /// Stored documents aren't rewritten, reads fill in `default` for every document which lacks
+    /// the field. Documents inserted afterwards have to hold the field like any other. Errors
+    /// with `NonaneError::FieldExists` if the bucket has or had a field with the same name, and
+    /// with `NonaneError::FieldMismatch` if `default` doesn't match `descriptor` or `descriptor`
+    /// is unique, the stored documents would all share `default`.
+    pub fn add_field(
+        &mut self,
+        descriptor: FieldDescriptor,
+        default: Field,
+    ) -> Result<(), NonaneError> {
+        let name = descriptor.get_name().to_owned();
+        if self.schema.read().names.contains(&name) {
+            return Err(NonaneError::FieldExists {
+                name: name.to_string_lossy().into_owned(),
+            });
+        }
+
+        if !descriptor.is_match(&default) {
+            return Err(NonaneError::FieldMismatch {
+                reason: "default doesn't match the name and type of the added field".to_owned(),
+            });
+        }
+
+        if descriptor.is_unique() {
+            return Err(NonaneError::FieldMismatch {
+                reason: "an added field can't be unique".to_owned(),
+            });
         }
 
-        return Ok(count);
+        let mut description = self.get_description();
+        description.field_description.push(descriptor);
+        description.field_names.push(name.clone());
+        description.defaults.push(default.clone());
+        self.rewrite_descriptor(description)?;
+
+        let mut schema = self.schema.write();
+        schema.names.push(name);
+        schema.defaults.push(default);
+        Ok(())
     }
-}
 
-unsafe impl<'a> Send for Bucket<'a> {}
-unsafe impl<'a> Sync for Bucket<'a> {}
+    /// Removes a field from the bucket's descriptor, documents are read as if they never held it
+    ///
+    /// Stored documents aren't rewritten, reads leave out the field instead. Documents inserted
+    /// afterwards must not hold it, and the name can't be added again. Any index on the field is
+    /// removed along with its file. Errors with `NonaneError::FieldNotFound` if the bucket has no
+    /// such field, and with `NonaneError::FieldMismatch` if it's unique or a unique constraint
+    /// covers it.
+    pub fn drop_field(&mut self, name: &str) -> Result<(), NonaneError> {
+        let changing = self.changing.clone();
+        let _changing = changing.read_recursive();
+        let mut description = self.get_description();
+        let position = description
+            .field_description
+            .iter()
+            .position(|f| f.get_name().to_bytes() == name.as_bytes())
+            .ok_or_else(|| NonaneError::FieldNotFound { name: name.to_owned() })?;
+
+        if description.field_description[position].is_unique() {
+            return Err(NonaneError::FieldMismatch {
+                reason: "field is unique".to_owned(),
+            });
+        }
+
+        if let Some(constraint) = description
+            .unique_constraints
+            .iter()
+            .find(|c| c.fields.iter().any(|f| f == name))
+        {
+            return Err(NonaneError::FieldMismatch {
+                reason: format!("field is part of the unique constraint {}", constraint.name),
+            });
+        }
+
+        description.field_description.remove(position);
+        description
+            .defaults
+            .retain(|f| f.get_key().to_bytes() != name.as_bytes());
+        self.rewrite_descriptor(description.clone())?;
+
+        *self.schema.write() = Schema::new(&description);
+
+        let mut indexes = self.indexes.lock();
+        for index in indexes.iter().filter(|i| i.get_field() == name) {
+            let path = if index.is_sorted() {
+                ValueIndex::sorted_path(&self.path, name)
+            } else {
+                ValueIndex::path(&self.path, name)
+            };
+            std::fs::remove_file(path)?;
+        }
+        indexes.retain(|i| i.get_field() != name);
+
+        Ok(())
+    }
+
+    /// Makes `field` the primary key of the bucket, no two documents may hold the same value for it
+    ///
+    /// The stored documents are checked first, errors with `NonaneError::DuplicateKey` if two of
+    /// them already share a value. Replaces any primary key set before.
+    pub fn set_primary_key(&mut self, field: &str) -> Result<(), NonaneError> {
+        let mut descriptor = self.get_description();
+        if !descriptor
+            .field_description
+            .iter()
+            .any(|f| f.get_name().to_bytes() == field.as_bytes())
+        {
+            return Err(NonaneError::FieldNotFound { name: field.to_owned() });
+        }
+
+        descriptor
+            .unique_constraints
+            .retain(|c| c.name != PRIMARY_KEY_CONSTRAINT);
+        let descriptor = descriptor.with_unique(PRIMARY_KEY_CONSTRAINT, &[field]);
+
+        // Queued documents have to be on disk to be part of the index
+        if let Some(writer_thread) = self.writer_thread.as_ref() {
+            writer_thread.wait_for_writes()?;
+        }
+
+        let mut index = UniqueIndex::new(&descriptor);
+        self.try_for_each_document(|offset, document| {
+            index.check(document, None).map_err(|_| NonaneError::DuplicateKey {
+                field: field.to_owned(),
+            })?;
+            index.insert(document, offset);
+            Ok(())
+        })?;
+
+        self.rewrite_descriptor(descriptor)?;
+        *self.unique.lock() = index;
+        Ok(())
+    }
+
+    /// Indexes the values of `field`, so `find_by_index` and `index_count` don't need to scan
+    /// the documents
+    ///
+    /// Any number of documents may share a value. The index is built from the stored documents,
+    /// kept up to date by later inserts and stored next to the bucket, see `ValueIndex`. It's
+    /// loaded again when the bucket is reopened. The index file holds the values as they are, so
+    /// encrypted buckets error with `NonaneError::EncryptedIndex`.
+    pub fn create_index(&mut self, field: &str) -> Result<(), NonaneError> {
+        self.add_index(field, None)
+    }
+
+    /// Indexes the values of the integer field `field` in order, so `find_range` can look up
+    /// the documents holding a range of values
+    ///
+    /// Kept and stored like the index of `create_index`, which it can also stand in for, and
+    /// refused on encrypted buckets the same way. A field may have both. Errors with
+    /// `NonaneError::FieldMismatch` if the field's type isn't an integer type whose values fit an
+    /// `i64`, see `ValueIndex::is_sortable`.
+    pub fn create_sorted_index(&mut self, field: &str) -> Result<(), NonaneError> {
+        let field_type = self
+            .get_description()
+            .field_description
+            .iter()
+            .find(|f| f.get_name().to_bytes() == field.as_bytes())
+            .map(|f| f.get_field_type())
+            .ok_or_else(|| NonaneError::FieldNotFound { name: field.to_owned() })?;
+
+        if !ValueIndex::is_sortable(field_type) {
+            return Err(NonaneError::FieldMismatch {
+                reason: format!("field {} of type {:?} can't be sorted", field, field_type),
+            });
+        }
+
+        self.add_index(field, Some(field_type))
+    }
+
+    /// Builds and adds an index of `field`, sorted if the field's type is given
+    fn add_index(&mut self, field: &str, sorted: Option<FieldType>) -> Result<(), NonaneError> {
+        let changing = self.changing.clone();
+        let _changing = changing.read_recursive();
+        if !self
+            .schema
+            .read()
+            .names
+            .iter()
+            .any(|n| n.as_bytes() == field.as_bytes())
+        {
+            return Err(NonaneError::FieldNotFound { name: field.to_owned() });
+        }
+
+        if self.config.encryption_key.is_some() {
+            return Err(NonaneError::EncryptedIndex { field: field.to_owned() });
+        }
+
+        if !ValueIndex::is_valid_field(field) {
+            return Err(NonaneError::Io(Error::new(
+                ErrorKind::InvalidInput,
+                "field name can't be used as part of an index file name",
+            )));
+        }
+
+        // Hold the unique index so no insert is missed between the scan and adding the index
+        let _unique = self.unique.lock();
+        if self
+            .indexes
+            .lock()
+            .iter()
+            .any(|i| i.get_field() == field && i.is_sorted() == sorted.is_some())
+        {
+            return Ok(());
+        }
+
+        if let Some(writer_thread) = self.writer_thread.as_ref() {
+            writer_thread.wait_for_writes()?;
+        }
+
+        let mut index = match sorted {
+            Some(field_type) => ValueIndex::create_sorted(
+                &ValueIndex::sorted_path(&self.path, field),
+                field,
+                field_type,
+            )?,
+            None => ValueIndex::create(&ValueIndex::path(&self.path, field), field)?,
+        };
+        self.try_for_each_document(|offset, document| Ok(index.insert(document, offset)?))?;
+        index.sync()?;
+
+        self.indexes.lock().push(index);
+        Ok(())
+    }
+
+    /// Offsets of the documents whose `field` holds the serialized `value`, in insertion order
+    ///
+    /// Errors with `NonaneError::IndexNotFound` if `field` wasn't indexed with `create_index` or
+    /// `create_sorted_index`.
+    pub fn find_by_index(&self, field: &str, value: &[u8]) -> Result<Vec<u64>, NonaneError> {
+        self.indexes
+            .lock()
+            .iter()
+            .find(|i| i.get_field() == field)
+            .map(|i| i.get(value).to_vec())
+            .ok_or_else(|| NonaneError::IndexNotFound { field: field.to_owned() })
+    }
+
+    /// Offsets of the documents whose integer `field` holds a value from `low` to `high`, both
+    /// included, ordered by value and then by insertion
+    ///
+    /// Errors with `NonaneError::IndexNotFound` if `field` wasn't indexed with
+    /// `create_sorted_index`.
+    pub fn find_range(&self, field: &str, low: i64, high: i64) -> Result<Vec<u64>, NonaneError> {
+        self.indexes
+            .lock()
+            .iter()
+            .find(|i| i.get_field() == field && i.is_sorted())
+            .and_then(|i| i.range(low, high))
+            .ok_or_else(|| NonaneError::IndexNotFound { field: field.to_owned() })
+    }
+
+    /// Adds an inserted document to every index of the bucket
+    fn insert_into_index(&self, document: &Document, offset: u64) -> Result<(), NonaneError> {
+        for index in self.indexes.lock().iter_mut() {
+            index.insert(document, offset)?;
+        }
+
+        Ok(())
+    }
+
+    /// Loads the indexes stored next to the bucket, for every field which has an index file
+    fn load_indexes(&mut self) -> Result<(), NonaneError> {
+        let end = self.atomic_offset.load(Ordering::SeqCst) as u64;
+
+        let mut indexes = Vec::new();
+        let description = self.get_description();
+        let schema = self.schema.read();
+        for name in schema.names.iter() {
+            let field = name.to_string_lossy();
+            if !ValueIndex::is_valid_field(&field) || schema.is_dropped(&field) {
+                continue;
+            }
+
+            let path = ValueIndex::path(&self.path, &field);
+            if path.exists() {
+                indexes.push(ValueIndex::open(&path, &field, end)?);
+            }
+
+            let path = ValueIndex::sorted_path(&self.path, &field);
+            let field_type = description
+                .field_description
+                .iter()
+                .find(|f| f.get_name() == name.as_c_str())
+                .map(|f| f.get_field_type());
+            if let (true, Some(field_type)) = (path.exists(), field_type) {
+                indexes.push(ValueIndex::open_sorted(&path, &field, field_type, end)?);
+            }
+        }
+
+        self.indexes = Arc::new(Mutex::new(indexes));
+        Ok(())
+    }
+
+    /// Counts the documents whose `field` holds the serialized `value`
+    ///
+    /// Answered from the index if `field` was indexed with `create_index`, otherwise every
+    /// document written to disk is scanned.
+    pub fn index_count(&self, field: &str, value: &[u8]) -> Result<usize, NonaneError> {
+        if let Some(index) = self.indexes.lock().iter().find(|i| i.get_field() == field) {
+            return Ok(index.count(value));
+        }
+
+        let mut count = 0;
+        self.for_each_document(|_, document| {
+            if document.read_field(field).is_some_and(|f| f.get_data() == value) {
+                count += 1;
+            }
+        })?;
+
+        Ok(count)
+    }
+
+    /// Returns the field set as primary key with `set_primary_key`
+    pub fn get_primary_key(&self) -> Option<String> {
+        self.get_description()
+            .unique_constraints
+            .into_iter()
+            .find(|c| c.name == PRIMARY_KEY_CONSTRAINT)
+            .and_then(|c| c.fields.into_iter().next())
+    }
+
+    /// Returns a copy of the bucket's descriptor
+    fn get_description(&self) -> BucketDescription {
+        self.descriptor
+            .as_ref()
+            .as_ref()
+            .unwrap()
+            .pull()
+            .as_ref()
+            .clone()
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Path of the bucket's page file, its other files are stored next to it
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get_config(&self) -> &BucketConfiguration {
+        &self.config
+    }
+
+    /// Insert a document into the store
+    ///
+    /// A document without an `ID_FIELD` is stored with a new v4 uuid as its first field, the
+    /// returned id holds the uuid and the offset of the document's row.
+    pub fn insert(&mut self, document: &Document) -> Result<RowId, NonaneError> {
+        let (uuid, identified) = Bucket::identify(document)?;
+        let document = identified.as_ref().unwrap_or(document);
+
+        self.append(document, uuid, None)
+    }
+
+    /// Inserts every document or none of them, see `insert`
+    ///
+    /// The rows of the documents follow each other and are queued as a single write, so a full
+    /// queue rejects the whole batch. A document which can't be stored, or holds values of a
+    /// unique constraint held by a stored document or a document before it in the batch, errors
+    /// with `NonaneError::InvalidBatchItem` holding its position.
+    pub fn insert_batch(&mut self, documents: &[Document]) -> Result<Vec<RowId>, NonaneError> {
+        if documents.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let changing = self.changing.clone();
+        let _changing = changing.read_recursive();
+        let item_error = |index, error| NonaneError::InvalidBatchItem {
+            index,
+            error: Box::new(error),
+        };
+
+        let mut identified = Vec::with_capacity(documents.len());
+        for (index, document) in documents.iter().enumerate() {
+            let (uuid, copy) = Bucket::identify(document).map_err(|e| item_error(index, e))?;
+            identified.push((uuid, copy));
+        }
+        let batch: Vec<&Document> = identified
+            .iter()
+            .zip(documents.iter())
+            .map(|((_, copy), document)| copy.as_ref().unwrap_or(document))
+            .collect();
+
+        // Hold the unique index until the documents are queued, see `append`
+        let mut unique = self.unique.lock();
+        unique
+            .check_batch(&batch)
+            .map_err(|(index, e)| item_error(index, e))?;
+
+        let mut rows = Vec::new();
+        let mut starts = Vec::with_capacity(batch.len());
+        let mut moved = Vec::new();
+        for (index, document) in batch.iter().enumerate() {
+            match self.encode_row(document) {
+                Ok((mut row, region)) => {
+                    starts.push(rows.len() as u64);
+                    rows.append(&mut row);
+                    moved.extend(region);
+                }
+                Err(e) => {
+                    moved.into_iter().rev().for_each(|r| self.discard_overflow(Some(r)));
+                    return Err(item_error(index, e));
+                }
+            }
+        }
+
+        let offset = match self.write_rows(rows, batch.len() as i64) {
+            Ok(offset) => offset,
+            Err(e) => {
+                moved.into_iter().rev().for_each(|r| self.discard_overflow(Some(r)));
+                return Err(e);
+            }
+        };
+
+        let mut ids = Vec::with_capacity(batch.len());
+        for ((document, (uuid, _)), start) in batch.iter().zip(identified.iter()).zip(starts) {
+            unique.insert(document, offset + start);
+            self.insert_into_index(document, offset + start)?;
+            ids.push(((offset + start) as usize, pack_id(*uuid, offset + start)));
+        }
+
+        Ok(ids)
+    }
+
+    /// Writes an identified document as a new row, a document stored at `own_offset` doesn't
+    /// count towards the unique constraints
+    fn append(
+        &mut self,
+        document: &Document,
+        uuid: uuid::Uuid,
+        own_offset: Option<u64>,
+    ) -> Result<RowId, NonaneError> {
+        let changing = self.changing.clone();
+        let _changing = changing.read_recursive();
+        // Hold the unique index until the document is queued, so concurrent inserts of the
+        // same values can't both pass the check
+        let unique = self.unique.lock();
+        let mut unique = if unique.is_empty() { None } else { Some(unique) };
+
+        self.append_locked(document, uuid, own_offset, unique.as_deref_mut())
+    }
+
+    /// Appends like `append`, while the caller holds the unique index, `None` if it's empty
+    fn append_locked(
+        &self,
+        document: &Document,
+        uuid: uuid::Uuid,
+        own_offset: Option<u64>,
+        unique: Option<&mut UniqueIndex>,
+    ) -> Result<RowId, NonaneError> {
+        if let Some(unique) = unique.as_deref() {
+            unique.check(document, own_offset)?;
+        }
+
+        let (row, moved) = self.encode_row(document)?;
+        let offset = match self.write_rows(row, 1) {
+            Ok(offset) => offset,
+            Err(e) => {
+                self.discard_overflow(moved);
+                return Err(e);
+            }
+        };
+
+        if let Some(unique) = unique {
+            unique.insert(document, offset);
+        }
+        self.insert_into_index(document, offset)?;
+
+        // Todo: Handle events with file.sync_all()
+        Ok((offset as usize, pack_id(uuid, offset)))
+    }
+
+    /// Reserves room for `rows` at the end of the bucket and writes them as a single write,
+    /// returning the offset they start at
+    ///
+    /// `rows` are one or more rows following each other holding `documents` documents. The write
+    /// is queued for the writer thread, or written right away by synchronous buckets. The room is
+    /// given back if the write can't be queued or written.
+    fn write_rows(&self, rows: Vec<u8>, documents: i64) -> Result<u64, NonaneError> {
+        // Synchronous buckets write on this thread, holding the writer orders the inserts
+        let mut sync_writer = if self.config.synchronous {
+            Some(self.writer.lock())
+        } else {
+            None
+        };
+
+        // Held until the rows are queued, so no other insert reserves an offset in the meantime
+        // and rows which can't be queued give their offset back without leaving a gap
+        let _appending = self.appending.lock();
+        let offset = self.reserve_offset(rows.len() as u64)?;
+
+        let info = QueuedWriteInformation {
+            seek: (offset, offset + rows.len() as u64),
+            len: rows.len(),
+            bytes: rows,
+            overwrite: false,
+            documents,
+        };
+
+        let res = match sync_writer.as_mut() {
+            Some(writer) => writer
+                .write_rows(offset, &info.bytes, documents)
+                .map_err(|e| e.into()),
+            None => self.push_write(info),
+        };
+
+        if let Err(e) = res {
+            self.atomic_offset.store(offset as usize, Ordering::SeqCst);
+            return Err(e);
+        }
+
+        Ok(offset)
+    }
+
+    /// Reserves `len` bytes at the end of the bucket for a row, returning the offset it starts at
+    ///
+    /// Errors with `NonaneError::BucketQuotaExceeded` if the row would grow the bucket past
+    /// `BucketConfiguration::max_bucket_bytes`.
+    fn reserve_offset(&self, len: u64) -> Result<u64, NonaneError> {
+        let limit = self.config.max_bucket_bytes;
+        self.atomic_offset
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |offset| {
+                let end = offset as u64 + len;
+                match limit {
+                    Some(limit) if end > limit => None,
+                    _ => Some(end as usize),
+                }
+            })
+            .map(|offset| offset as u64)
+            .map_err(|_| NonaneError::BucketQuotaExceeded {
+                limit: limit.unwrap_or(u64::MAX),
+            })
+    }
+
+    /// Serializes a document into a row, header included, as configured for the bucket
+    ///
+    /// Moves large bytes fields to the overflow file, see `move_to_overflow`. Returns the row
+    /// along with the region of the overflow file the fields were moved to, which has to be
+    /// discarded if the row isn't written, see `discard_overflow`.
+    fn encode_row(
+        &self,
+        document: &Document,
+    ) -> Result<(Vec<u8>, Option<OverflowRef>), NonaneError> {
+        // Move large bytes fields to the overflow file, leaving a reference in the row
+        let (moved, region) = match self.move_to_overflow(document)? {
+            Some((moved, region)) => (Some(moved), Some(region)),
+            None => (None, None),
+        };
+        let row_document = moved.as_ref().unwrap_or(document);
+
+        let row = (|| {
+            // Serialize document, storing field names by position if they're all part of the
+            // bucket
+            let mut flags = 0;
+            let interned = if self.config.intern_field_names {
+                row_document.serialize_interned(&self.schema.read().names)?
+            } else {
+                None
+            };
+            let data = match interned {
+                Some(data) => {
+                    flags |= row::INTERNED;
+                    data
+                }
+                None => row_document.serialize()?,
+            };
+
+            self.frame_row(data, flags)
+        })();
+
+        match row {
+            Ok(row) => Ok((row, region)),
+            Err(e) => {
+                self.discard_overflow(region);
+                Err(e)
+            }
+        }
+    }
+
+    /// Drops the fields a row which wasn't written moved to the overflow file, see `encode_row`
+    ///
+    /// Errors are only logged, as the caller is already handing back the error which kept the
+    /// row from being written.
+    fn discard_overflow(&self, region: Option<OverflowRef>) {
+        if let Some(region) = region {
+            if let Err(e) = self.overflow.discard(region) {
+                warn!("Failed to discard overflow of bucket {}: {}", self.name, e);
+            }
+        }
+    }
+
+    /// Compresses, encrypts and pads a serialized document as configured for the bucket and
+    /// puts a header holding `flags` in front of it
+    fn frame_row(&self, mut data: Vec<u8>, mut flags: u64) -> Result<Vec<u8>, NonaneError> {
+        // Compress documents above the threshold, as long as it saves space
+        if let Some(min) = self.config.compress_min_bytes {
+            if data.len() > min {
+                let codec = self.schema.read().compression;
+                if let Some(compressed) = row::compress(&data, codec)? {
+                    if compressed.len() < data.len() {
+                        data = compressed;
+                        flags |= row::COMPRESSED;
+                    }
+                }
+            }
+        }
+
+        if let Some(key) = self.config.encryption_key.as_ref() {
+            data = encryption::encrypt(&data, key)?;
+            flags |= row::ENCRYPTED;
+        }
+
+        // Todo: Change to constant across whole DB
+        let additional_bytes = std::mem::size_of::<u64>();
+        if self.config.pad_rows {
+            let len = utils::numbers::round_to_multiple(data.len(), 8);
+            data.resize(len, 0);
+        }
+
+        // Add length infront
+        let mut buf = Vec::new();
+
+        // Add length of document to ease reading
+        let mut len = Vec::new();
+        let header = RowHeader::new((data.len() + std::mem::size_of::<u64>()) as u64, flags);
+        len.write_u64::<LittleEndian>(header.to_raw())?;
+        buf.append(&mut len);
+
+        // Finally move the serialized data to the buffer
+        buf.append(&mut data);
+
+        Ok(buf)
+    }
+
+    /// Returns the uuid of a document, along with a copy of the document holding a new uuid as
+    /// its first field if it has none yet
+    fn identify(document: &Document) -> Result<(uuid::Uuid, Option<Document>), NonaneError> {
+        match document.read_field(ID_FIELD) {
+            Some(_) => {
+                let uuid = document.get_uuid(ID_FIELD).ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        "the id field of a document has to hold a uuid",
+                    )
+                })?;
+
+                Ok((uuid, None))
+            }
+            None => {
+                let uuid = uuid::Uuid::new_v4();
+                let id = Field::from_raw(
+                    CString::new(ID_FIELD).unwrap(),
+                    FieldType::Uuid,
+                    uuid.as_bytes().to_vec(),
+                );
+
+                let mut fields = Vec::with_capacity(document.get_fields().len() + 1);
+                fields.push(id);
+                fields.extend(document.get_fields().iter().cloned());
+                Ok((uuid, Some(Document::new(fields))))
+            }
+        }
+    }
+
+    /// Deletes the document stored at `offset`, returning false if no document is stored there
+    /// or it was already deleted. An offset pointing into a row usually errors while parsing it.
+    ///
+    /// The row is kept and marked as deleted, see `row::DELETED`, so scans step over it. The
+    /// mark is written by the writer thread like any other write, a synchronous bucket writes it
+    /// right away.
+    pub fn delete_at(&mut self, offset: u64) -> Result<bool, NonaneError> {
+        let changing = self.changing.clone();
+        let _changing = changing.read_recursive();
+        if offset < self.data_start || offset >= self.atomic_offset.load(Ordering::SeqCst) as u64 {
+            return Ok(false);
+        }
+
+        // Hold the unique index, inserts can't reuse the deleted values before the row is marked
+        let mut unique = self.unique.lock();
+
+        self.delete_locked(offset, &mut unique)
+    }
+
+    /// Deletes like `delete_at`, while the caller holds the unique index
+    fn delete_locked(&self, offset: u64, unique: &mut UniqueIndex) -> Result<bool, NonaneError> {
+        self.wait_for_row(offset)?;
+        let mut reader = self.readers.as_ref().unwrap().pull();
+        let reader = reader.as_mut_ref();
+
+        let document = match reader.read_document_entry(offset) {
+            Ok((Some(document), _)) => document,
+            Ok((None, _)) | Err(NonaneError::NoDocumentAt { .. }) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let (header, _) = reader.read_raw_row(offset)?;
+
+        let mut mark = vec![0; row::HEADER_SIZE as usize];
+        LittleEndian::write_u64(
+            &mut mark,
+            RowHeader::new(header.len, header.flags | row::DELETED).to_raw(),
+        );
+
+        match self.writer_thread.as_ref() {
+            Some(_) if !self.config.synchronous => self.push_write(QueuedWriteInformation {
+                seek: (offset, offset + row::HEADER_SIZE),
+                len: mark.len(),
+                bytes: mark,
+                overwrite: true,
+                documents: -1,
+            })?,
+            _ => {
+                let mut writer = self.writer.lock();
+                writer.add_to_count(-1)?;
+                writer.overwrite(offset, &mark)?;
+            }
+        }
+
+        unique.remove(&document, offset);
+        for index in self.indexes.lock().iter_mut() {
+            index.remove(&document, offset)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Replaces the document stored at `offset`, returning the offset and id it's stored under
+    ///
+    /// The document keeps the uuid of the document it replaces. If its row fits within the row
+    /// of the old document it's written in place, like inserts through the writer thread unless
+    /// the bucket is synchronous, and the offset stays the same. Otherwise the old document is
+    /// deleted and the new one is appended, so a returned offset other than `offset` tells the
+    /// document moved. Errors with `NonaneError::NoDocumentAt` if no document is stored at
+    /// `offset`.
+    pub fn update_at(
+        &mut self,
+        offset: u64,
+        document: &Document,
+    ) -> Result<(u64, [u8; 24]), NonaneError> {
+        let changing = self.changing.clone();
+        let _changing = changing.read_recursive();
+        if offset < self.data_start || offset >= self.atomic_offset.load(Ordering::SeqCst) as u64 {
+            return Err(NonaneError::NoDocumentAt { offset });
+        }
+
+        // Hold the unique index, the old document stays the one checked against until replaced
+        let mut unique = self.unique.lock();
+
+        self.wait_for_row(offset)?;
+        let (old, old_len) = match self
+            .readers
+            .as_ref()
+            .unwrap()
+            .pull()
+            .as_mut_ref()
+            .read_document_entry(offset)?
+        {
+            (Some(old), len) => (old, len),
+            (None, _) => return Err(NonaneError::NoDocumentAt { offset }),
+        };
+
+        let uuid = old.get_uuid(ID_FIELD).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, "stored document has no id")
+        })?;
+        let mut document = document.clone();
+        document.set_field(Field::from_raw(
+            CString::new(ID_FIELD).unwrap(),
+            FieldType::Uuid,
+            uuid.as_bytes().to_vec(),
+        ));
+
+        unique.check(&document, Some(offset))?;
+
+        let (mut row, moved) = self.encode_row(&document)?;
+        if row.len() as u64 > old_len {
+            // Still holding the unique index, so no other update or delete of the old row gets
+            // in between appending the new document and marking the old one deleted
+            self.discard_overflow(moved);
+            let (new_offset, id) =
+                self.append_locked(&document, uuid, Some(offset), Some(&mut unique))?;
+            if let Err(e) = self.delete_locked(offset, &mut unique) {
+                // Take the appended document back, so the old one stays the only one stored
+                let _ = self.delete_locked(new_offset as u64, &mut unique);
+                unique.insert(&old, offset);
+                return Err(e);
+            }
+            return Ok((new_offset as u64, id));
+        }
+
+        // Pad the row to the old length, so rows following it are still found
+        let flags = RowHeader::from_raw(LittleEndian::read_u64(&row)).flags;
+        row.resize(old_len as usize, 0);
+        LittleEndian::write_u64(&mut row, RowHeader::new(old_len, flags).to_raw());
+
+        let res = match self.writer_thread.as_ref() {
+            Some(_) if !self.config.synchronous => self.push_write(QueuedWriteInformation {
+                seek: (offset, offset + old_len),
+                len: row.len(),
+                bytes: row,
+                overwrite: true,
+                documents: 0,
+            }),
+            _ => self.writer.lock().overwrite(offset, &row).map_err(|e| e.into()),
+        };
+        if let Err(e) = res {
+            self.discard_overflow(moved);
+            return Err(e);
+        }
+
+        unique.remove(&old, offset);
+        unique.insert(&document, offset);
+        for index in self.indexes.lock().iter_mut() {
+            index.remove(&old, offset)?;
+            index.insert(&document, offset)?;
+        }
+
+        Ok((offset, pack_id(uuid, offset)))
+    }
+
+    /// Prepares the bucket for a bulk load of about `rows` documents taking up `bytes` bytes
+    ///
+    /// Preallocates the page file past the current offset, grows the unique index and grows a
+    /// bounded write queue to hold `rows` writes, which raises the memory it may take up, see
+    /// `BucketConfiguration::queue_capacity`. It's only a hint, inserting more or less than
+    /// reserved is fine.
+    ///
+    /// Room is made for at most `MAX_RESERVE_FACTOR` times the queue's capacity in rows, the
+    /// default capacity for unbounded queues. Errors with `ErrorKind::InvalidInput` if `bytes`
+    /// reach past the largest possible file.
+    pub fn reserve(&mut self, rows: usize, bytes: u64) -> Result<(), NonaneError> {
+        let offset = self.atomic_offset.load(Ordering::SeqCst) as u64;
+        let end = offset.checked_add(bytes).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, "reserved bytes reach past the largest file")
+        })?;
+
+        let rows = rows.min(
+            self.config
+                .queue_capacity
+                .unwrap_or(MAX_ITEMS_IN_QUEUE)
+                .saturating_mul(MAX_RESERVE_FACTOR),
+        );
+        self.unique.lock().reserve(rows);
+        if let Some(writer_thread) = &self.writer_thread {
+            writer_thread.q.grow(rows);
+        }
+
+        let mut writer = self.writer.lock();
+        let file = writer.borrow_file();
+        if file.metadata()?.len() < end {
+            file.allocate(end)?;
+        }
+
+        Ok(())
+    }
+
+    /// Amount of writes waiting in the queue for the writer thread
+    ///
+    /// Always zero for synchronous buckets, which don't queue their writes.
+    pub fn queue_depth(&self) -> usize {
+        self.writer_thread.as_ref().map_or(0, |w| w.q.len())
+    }
+
+    /// Fraction of the write queue in use, from `0.0` (empty) to `1.0` (full)
+    ///
+    /// Writes to a full queue wait for room for up to `BucketConfiguration::queue_full_wait` and
+    /// error with `NonaneError::QueueFull` if it's still full after that, producers can use this
+    /// to slow down before they start waiting.
+    /// Always zero for an unbounded queue.
+    pub fn queue_pressure(&self) -> f32 {
+        self.writer_thread.as_ref().map_or(0.0, |w| match w.q.capacity() {
+            Some(capacity) => w.q.len() as f32 / capacity as f32,
+            None => 0.0,
+        })
+    }
+
+    /// Copies `document`, moving every `Bytes` field larger than
+    /// `BucketConfiguration::overflow_min_bytes` to the overflow file
+    ///
+    /// Returns the copy along with the region of the overflow file spanning every moved field,
+    /// or `None` if no field has to be moved. Encrypted buckets keep every field in its row, the
+    /// overflow file isn't encrypted.
+    fn move_to_overflow(
+        &self,
+        document: &Document,
+    ) -> Result<Option<(Document, OverflowRef)>, NonaneError> {
+        let min = match self.config.overflow_min_bytes {
+            Some(min) if self.config.encryption_key.is_none() => min,
+            _ => return Ok(None),
+        };
+
+        let is_large = |f: &Field| *f.get_type() == FieldType::Bytes && f.get_data().len() > min;
+        let large: Vec<&[u8]> = document
+            .get_fields()
+            .iter()
+            .filter(|f| is_large(f))
+            .map(|f| f.get_data())
+            .collect();
+        if large.is_empty() {
+            return Ok(None);
+        }
+
+        // The fields are written back to back, so a failed insert can drop them all at once
+        let references = self.overflow.append_each(&large)?;
+        let region = OverflowRef {
+            offset: references[0].offset,
+            len: large.iter().map(|l| l.len() as u64).sum(),
+        };
+        let mut references = references.into_iter();
+
+        let fields = document
+            .get_fields()
+            .iter()
+            .map(|f| {
+                if !is_large(f) {
+                    return f.clone();
+                }
+
+                Field::from_raw(
+                    f.get_key().to_owned(),
+                    FieldType::Overflow,
+                    references.next().unwrap().to_bytes(),
+                )
+            })
+            .collect();
+
+        Ok(Some((Document::new(fields), region)))
+    }
+
+    /// Inserts a document and returns once it has been synced to disk
+    ///
+    /// The document is queued like any other insert, so the offsets stay coordinated with the
+    /// writer thread, then the queue is drained up to it and the bucket file is synced. Slower
+    /// than `insert`, as every write queued before it has to be written as well.
+    pub fn insert_durable(&mut self, document: &Document) -> Result<RowId, NonaneError> {
+        let id = self.insert(document)?;
+        self.checkpoint()?;
+        Ok(id)
+    }
+
+    /// Pushes a write to the writer thread's queue, erroring if it stays full
+    ///
+    /// A full queue is waited on for up to `BucketConfiguration::queue_full_wait`.
+    fn push_write(&self, info: QueuedWriteInformation) -> Result<(), NonaneError> {
+        // Push it to the queue or error if it's full
+        // (not very effiecent, however exceeding X amount of inserts per second might be a problem, time to add a new cluster)
+        // Or I guess, if you're cool, add more ram
+        let wrt_thrd = self.writer_thread.as_ref().unwrap();
+        if wrt_thrd.is_stopped() {
+            return Err(NonaneError::BucketClosed {
+                name: self.name.to_string(),
+            });
+        }
+
+        // The log is held until the write is queued, so its entries are in the order they're
+        // popped by the writer thread
+        let mut wal = self.wal.as_ref().map(|w| w.lock());
+        let logged = match wal.as_mut() {
+            Some(wal) => Some(wal.append(&info)?),
+            None => None,
+        };
+
+        let res = match self.config.queue_full_wait {
+            Some(wait) => wrt_thrd.push_waiting(info, wait),
+            None => wrt_thrd.push(info),
+        };
+
+        if let (Err(_), Some(wal), Some(len)) = (res.as_ref(), wal.as_mut(), logged) {
+            wal.discard_from(len)?;
+        }
+
+        res.map_err(|_| NonaneError::QueueFull)
+    }
+
+    /// Waits for every queued write to be written and syncs the bucket file to disk
+    ///
+    /// The offset of the next document is written along with every chunk, so once synced a
+    /// reopened bucket will find every document written before the checkpoint.
+    pub fn checkpoint(&self) -> Result<(), NonaneError> {
+        if let Some(writer_thread) = self.writer_thread.as_ref() {
+            writer_thread.wait_for_writes()?;
+        }
+
+        self.writer.lock().borrow_file().sync_all()?;
+        for index in self.indexes.lock().iter() {
+            index.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Blocks every change to the bucket, through this and any clone of it, until the returned
+    /// guard is dropped
+    ///
+    /// Changes in progress finish first, then the queued writes are written and the bucket is
+    /// synced, see `checkpoint`. The bucket's files can be copied as they are while the guard is
+    /// held, reads aren't blocked. Inserts, updates, deletes and changes to the descriptor or
+    /// indexes wait for the guard, on this thread they'd wait forever.
+    pub fn hold_writes(&self) -> Result<RwLockWriteGuard<'_, ()>, NonaneError> {
+        let held = self.changing.write();
+        self.checkpoint()?;
+        Ok(held)
+    }
+
+    /// Blocks until the writer thread has written every queued write, then syncs the bucket to
+    /// disk so the documents are durable, see `checkpoint`
+    ///
+    /// Waits on the writer's progress instead of spinning, meant to be called before shutdown.
+    pub fn flush(&self) -> Result<(), NonaneError> {
+        self.checkpoint()
+    }
+
+    /// Writes everything queued, stops the writer thread and syncs the bucket to disk
+    ///
+    /// Writes to the bucket, or any clone of it, error with `NonaneError::BucketClosed`
+    /// afterwards. Synchronous buckets have no writer thread and keep taking writes.
+    pub fn close(&self) -> Result<(), NonaneError> {
+        if let Some(writer_thread) = self.writer_thread.as_ref() {
+            writer_thread.stop()?;
+        }
+
+        self.checkpoint()
+    }
+
+    pub(crate) fn checkpoint_handle(&self) -> Result<CheckpointHandle, NonaneError> {
+        CheckpointHandle::new(&self.path, self.writer_thread.clone())
+    }
+
+    /// Waits for the queued writes if the row at `offset` may still change through them
+    ///
+    /// Queued writes may hold the row itself, or an overwrite replacing it or marking it as
+    /// deleted. Rows already on disk are left to be read without waiting while no overwrite is
+    /// queued.
+    fn wait_for_row(&self, offset: u64) -> Result<(), NonaneError> {
+        if let Some(writer_thread) = self.writer_thread.as_ref() {
+            if offset >= writer_thread.stored_offset.load(Ordering::SeqCst)
+                || writer_thread.is_overwriting()
+            {
+                writer_thread.wait_for_writes()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the document stored at `offset`, `None` if the offset is past the last insert or
+    /// the document was deleted
+    ///
+    /// An offset which isn't the start of a row errors with `NonaneError::NoDocumentAt`, as does
+    /// a document which is still queued for the writer thread.
+    pub fn get_at(&self, offset: u64) -> Result<Option<Document>, NonaneError> {
+        if offset >= self.atomic_offset.load(Ordering::SeqCst) as u64 {
+            return Ok(None);
+        }
+
+        if offset < self.data_start {
+            return Err(NonaneError::NoDocumentAt { offset });
+        }
+
+        let (document, _) = self
+            .readers
+            .as_ref()
+            .unwrap()
+            .pull()
+            .as_mut_ref()
+            .read_document_entry(offset)?;
+
+        Ok(document)
+    }
+
+    /// Reads the document with the id returned by `insert`, `None` if the bucket holds no
+    /// document with its uuid
+    ///
+    /// The document stored at the offset of the id is returned if it holds the id's uuid.
+    /// Otherwise the document was moved by `update_at` or `compact` and is looked up by its uuid,
+    /// through the index of `ID_FIELD` if one was created with `create_index`, or else by
+    /// scanning the documents. A document already on disk is read without waiting, otherwise the
+    /// writes queued before the call are waited for.
+    pub fn get_by_id(&self, id: &[u8; 24]) -> Result<Option<Document>, NonaneError> {
+        Ok(self.locate_id(id)?.map(|(_, document)| document))
+    }
+
+    /// Finds the document with the id returned by `insert` along with the offset it's stored at
+    /// now, see `get_by_id`
+    pub(crate) fn locate_id(&self, id: &[u8; 24]) -> Result<Option<(u64, Document)>, NonaneError> {
+        let (uuid, offset) = unpack_id(id);
+        let has_uuid = |document: &Document| document.get_uuid(ID_FIELD) == Some(uuid);
+
+        self.wait_for_row(offset)?;
+
+        // After a compaction the offset may point into another row, which can fail to parse in
+        // any number of ways, errors reading the bucket surface in the lookup by uuid instead
+        if offset >= self.data_start && offset < self.atomic_offset.load(Ordering::SeqCst) as u64 {
+            if let Ok(Some(document)) = self.get_at(offset) {
+                if has_uuid(&document) {
+                    return Ok(Some((offset, document)));
+                }
+            }
+        }
+
+        // The document moved, the row it moved to may still be queued
+        if let Some(writer_thread) = self.writer_thread.as_ref() {
+            writer_thread.wait_for_writes()?;
+        }
+
+        let offsets = match self.find_by_index(ID_FIELD, uuid.as_bytes()) {
+            Ok(offsets) => offsets,
+            Err(NonaneError::IndexNotFound { .. }) => {
+                let mut found = None;
+                self.for_each_document(|offset, document| {
+                    if found.is_none() && has_uuid(document) {
+                        found = Some((offset, document.clone()));
+                    }
+                })?;
+
+                return Ok(found);
+            }
+            Err(e) => return Err(e),
+        };
+
+        for offset in offsets {
+            if let Some(document) = self.get_at(offset)? {
+                if has_uuid(&document) {
+                    return Ok(Some((offset, document)));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads a single field of the document stored at `offset` without reading the other fields
+    pub fn read_field_at(&self, offset: u64, field: &str) -> Result<Option<Field>, NonaneError> {
+        self.readers
+            .as_ref()
+            .unwrap()
+            .pull()
+            .as_mut_ref()
+            .read_field_at(offset, field)
+    }
+
+    /// Appends `extra` to the `Bytes` field named `field` of the document stored at `offset`
+    ///
+    /// The first append moves the field's bytes to the bucket's overflow file and stores a
+    /// reference in its place, later appends extend the overflow file and only update the length
+    /// of the reference, leaving the rest of the row untouched. The changed bytes are written
+    /// like the rows of `update_at`, queued writes of the row are waited for first and the
+    /// indexes are updated to the appended value.
+    ///
+    /// Errors if the field doesn't exist or isn't a `Bytes` field, if the row is compressed or
+    /// stores its field names by position, or if the row (including its padding) is too small to
+    /// fit the reference in place of the field's bytes.
+    pub fn append_to_field(
+        &mut self,
+        offset: u64,
+        field: &str,
+        extra: &[u8],
+    ) -> Result<(), NonaneError> {
+        let changing = self.changing.clone();
+        let _changing = changing.read_recursive();
+
+        // Hold the unique index, the row isn't changed by deletes and updates in the meantime
+        let mut unique = self.unique.lock();
+
+        self.wait_for_row(offset)?;
+        let (header, row, old) = {
+            let mut reader = self.readers.as_ref().unwrap().pull();
+            let reader = reader.as_mut_ref();
+            let (header, row) = reader.read_raw_row(offset)?;
+            if header.is_deleted() {
+                return Err(NonaneError::NoDocumentAt { offset });
+            }
+
+            let (old, _) = reader.read_document_entry(offset)?;
+            (header, row, old.ok_or(NonaneError::NoDocumentAt { offset })?)
+        };
+
+        if header.is_compressed() || header.is_interned() || header.is_encrypted() {
+            return Err(NonaneError::Io(Error::new(
+                ErrorKind::InvalidInput,
+                "fields of compressed, interned or encrypted rows can't be appended to",
+            )));
+        }
+
+        let raw = raw::find_field(&row, field)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "field does not exist"))?;
+
+        // The document as it reads after the append, checked before anything is written
+        let mut document = old.clone();
+        let appended = old
+            .read_field(field)
+            .and_then(|f| f.get_value::<&[u8]>())
+            .map(|bytes| [bytes, extra].concat())
+            .and_then(|bytes| Field::new(field, bytes))
+            .ok_or_else(|| {
+                Error::new(ErrorKind::InvalidInput, "field is not an appendable bytes field")
+            })?;
+        document.set_field(appended);
+        unique.check(&document, Some(offset))?;
+
+        // Position of the document within the row
+        let start = offset + std::mem::size_of::<u64>() as u64;
+
+        let (at, bytes, moved) = match raw.field_type {
+            FieldType::Overflow => {
+                let reference = OverflowRef::from_bytes(&row[raw.data.clone()]).ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidData, "malformed overflow reference")
+                })?;
+                let reference = self.overflow.extend(reference, extra)?;
+
+                (raw.data.start, reference.to_bytes(), None)
+            }
+            FieldType::Bytes => {
+                let end = raw::fields(&row)
+                    .and_then(|f| f.last().map(|f| f.data.end))
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "malformed document"))?;
+
+                // The reference has to fit within the row's current padded length
+                let len = end - raw.data.len() + OverflowRef::SIZE;
+                if len > row.len() {
+                    return Err(NonaneError::Io(Error::new(
+                        ErrorKind::InvalidInput,
+                        "row is too small to hold an overflow reference",
+                    )));
+                }
+
+                let reference = self.overflow.append(&[&row[raw.data.clone()], extra])?;
+
+                // Rewrite everything from the field type onwards as the following fields move
+                let mut bytes = bincode::serialize(&FieldType::Overflow)?;
+                bytes.write_u64::<LittleEndian>(OverflowRef::SIZE as u64)?;
+                bytes.append(&mut reference.to_bytes());
+                bytes.extend_from_slice(&row[raw.data.end..end]);
+                bytes.resize(row.len() - raw.type_at, 0);
+
+                (raw.type_at, bytes, Some(reference))
+            }
+            _ => {
+                return Err(NonaneError::Io(Error::new(
+                    ErrorKind::InvalidInput,
+                    "field is not an appendable bytes field",
+                )))
+            }
+        };
+
+        // Written like the rows of `update_at`, through the writer thread unless synchronous
+        let at = start + at as u64;
+        let res = match self.writer_thread.as_ref() {
+            Some(_) if !self.config.synchronous => self.push_write(QueuedWriteInformation {
+                seek: (at, at + bytes.len() as u64),
+                len: bytes.len(),
+                bytes,
+                overwrite: true,
+                documents: 0,
+            }),
+            _ => self.writer.lock().overwrite(at, &bytes).map_err(|e| e.into()),
+        };
+        if let Err(e) = res {
+            self.discard_overflow(moved);
+            return Err(e);
+        }
+
+        unique.remove(&old, offset);
+        unique.insert(&document, offset);
+        for index in self.indexes.lock().iter_mut() {
+            index.remove(&old, offset)?;
+            index.insert(&document, offset)?;
+        }
+
+        Ok(())
+    }
+
+    /// Bytes used by the bucket's descriptor and rows, including rows still queued for writing
+    ///
+    /// Unlike `file_size` this doesn't count preallocated space, it's the usage checked against
+    /// `BucketConfiguration::max_bucket_bytes`.
+    pub fn disk_usage(&self) -> u64 {
+        self.atomic_offset.load(Ordering::SeqCst) as u64
+    }
+
+    /// Size of the bucket file on disk, including preallocated space
+    pub fn file_size(&self) -> Result<u64, NonaneError> {
+        Ok(std::fs::metadata(self.path.as_ref())?.len())
+    }
+
+    /// Measures the size of the bucket and the space compacting it would reclaim, see `compact`
+    ///
+    /// Walks every row written to disk once. Documents still queued for the writer thread aren't
+    /// counted.
+    pub fn stats(&self) -> Result<BucketStats, NonaneError> {
+        let mut reader = self.readers.as_ref().unwrap().pull();
+        let reader = reader.as_mut_ref();
+        let end = reader.get_stored_offset()?;
+
+        let mut stats = BucketStats {
+            file_size: reader.borrow_file().metadata()?.len(),
+            offset: end,
+            documents: 0,
+            tombstones: 0,
+            tombstone_bytes: 0,
+            padding_bytes: 0,
+        };
+
+        let mut offset = self.data_start;
+        while offset < end {
+            let (header, data) = reader.read_raw_row(offset)?;
+            if header.is_deleted() {
+                stats.tombstones += 1;
+                stats.tombstone_bytes += header.len;
+            } else {
+                // Compressed and encrypted payloads are prefixed by their length, documents are
+                // parsed to find their end
+                let prefix = std::mem::size_of::<u64>();
+                let used = if header.is_encrypted() || header.is_compressed() {
+                    let len = data.get(..prefix).map(LittleEndian::read_u64).ok_or_else(|| {
+                        Error::new(ErrorKind::InvalidData, "malformed row at offset")
+                    })?;
+                    prefix as u64 + len
+                } else {
+                    let mut rest = data.as_slice();
+                    if header.is_interned() {
+                        Document::deserialize_interned(&mut rest, &self.schema.read().names)?;
+                    } else {
+                        Document::deserialize_from(&mut rest)?;
+                    }
+                    (data.len() - rest.len()) as u64
+                };
+
+                stats.documents += 1;
+                stats.padding_bytes += (data.len() as u64).saturating_sub(used);
+            }
+
+            offset += header.len;
+        }
+
+        Ok(stats)
+    }
+
+    /// Describes where the descriptor and rows are stored within the bucket file
+    pub fn layout_info(&self) -> Result<LayoutInfo, NonaneError> {
+        let mut reader = self.readers.as_ref().unwrap().pull();
+        let reader = reader.as_mut_ref();
+        let stored_offset = reader.get_stored_offset()?;
+
+        let (format_version, descriptor_len) = {
+            let mut file = reader.borrow_file();
+            read_file_header(&mut file)?
+        };
+        let (descriptor_start, data_start) = descriptor_layout(format_version, descriptor_len);
+
+        Ok(LayoutInfo {
+            page_size: page_size::get() as u64,
+            descriptor_start,
+            descriptor_len,
+            data_start,
+            stored_offset,
+            format_version,
+            row_alignment: if self.config.pad_rows { 8 } else { 1 },
+            compression: self.schema.read().compression,
+        })
+    }
+
+    /// Finds the end of the stored rows by scanning them and stores it as the bucket's offset
+    ///
+    /// Repairs a stored offset which doesn't match the data, the scan stops at the first row
+    /// which is missing, truncated or can't be read. Queued writes are written first. Returns the
+    /// recovered offset.
+    pub fn recompute_offset(&mut self) -> Result<u64, NonaneError> {
+        let changing = self.changing.clone();
+        let _changing = changing.read_recursive();
+        if let Some(writer_thread) = self.writer_thread.as_ref() {
+            writer_thread.wait_for_writes()?;
+        }
+
+        let mut wrt = self.writer.lock();
+        let (end, count) = {
+            let mut reader = self.readers.as_ref().unwrap().pull();
+            let reader = reader.as_mut_ref();
+            let file_len = reader.borrow_file().metadata()?.len();
+
+            let mut offset = self.data_start;
+            let mut count = 0;
+            while offset + row::HEADER_SIZE <= file_len {
+                match reader.read_document_entry(offset) {
+                    Ok((document, len)) if offset + len <= file_len => {
+                        count += document.is_some() as u64;
+                        offset += len;
+                    }
+                    _ => break,
+                }
+            }
+
+            (offset, count)
+        };
+
+        wrt.set_last_write(end)?;
+        wrt.set_offset(end)?;
+        wrt.set_count(count)?;
+        wrt.borrow_file().sync_data()?;
+        self.atomic_offset.store(end as usize, Ordering::SeqCst);
+        if let Some(writer_thread) = self.writer_thread.as_ref() {
+            writer_thread.reset_stored(end, count);
+        }
+
+        Ok(end)
+    }
+
+    /// Rewrites the bucket with its documents back to back, dropping deleted rows and the space
+    /// left behind by documents replaced in place
+    ///
+    /// The documents are written to a file next to the bucket, which is renamed once it's
+    /// complete and then copied over the bucket file, a crash after the rename finishes the
+    /// compaction when the bucket is reopened. Documents keep their order and their ids, but
+    /// most are stored at another offset afterwards. Offsets held on to are stale and ids are
+    /// looked up by their uuid, see `get_by_id`, the indexes are rebuilt. Queued writes are
+    /// written first and inserts wait until the compaction is done. The overflow file isn't
+    /// compacted.
+    pub fn compact(&mut self) -> Result<CompactionStats, NonaneError> {
+        let changing = self.changing.clone();
+        let _changing = changing.read_recursive();
+        // Hold the unique index so no insert or delete is queued while the rows move
+        let mut unique = self.unique.lock();
+        if let Some(writer_thread) = self.writer_thread.as_ref() {
+            writer_thread.wait_for_writes()?;
+        }
+
+        // Logged writes point at the old offsets, they're all written by now
+        if let Some(wal) = self.wal.as_ref() {
+            wal.lock().clear()?;
+        }
+
+        let compacting = self.path.with_extension(COMPACTING_EXTENSION);
+        let (old_len, end, count, documents_moved) = {
+            let mut reader = self.readers.as_ref().unwrap().pull();
+            let reader = reader.as_mut_ref();
+            let stored = reader.get_stored_offset()?;
+            let old_len = reader.borrow_file().metadata()?.len();
+
+            // The file header and descriptor are kept as they are
+            let mut out = BufWriter::new(File::create(&compacting)?);
+            let mut head = File::open(self.path.as_ref())?;
+            std::io::copy(&mut (&mut head).take(self.data_start), &mut out)?;
+
+            let mut offset = self.data_start;
+            let mut end = self.data_start;
+            let mut count = 0;
+            let mut moved = 0;
+            while offset < stored {
+                let (header, _) = reader.read_raw_row(offset)?;
+                if !header.is_deleted() {
+                    let payload = match reader.read_row_entry(offset)?.contents {
+                        Some((payload, _)) => payload,
+                        None => unreachable!("row was read as not deleted"),
+                    };
+
+                    let row = self.frame_row(payload, header.flags & row::INTERNED)?;
+                    out.write_all(&row)?;
+                    moved += (end != offset) as usize;
+                    count += 1;
+                    end += row.len() as u64;
+                }
+                offset += header.len;
+            }
+
+            // The copied tail holds where the last write started in the old layout of the rows
+            let mut out = out.into_inner().map_err(|e| e.into_error())?;
+            if self.stores_last_write.load(Ordering::SeqCst) {
+                out.seek(SeekFrom::Start(last_write_location()))?;
+                out.write_u64::<LittleEndian>(self.data_start)?;
+            }
+            out.seek(SeekFrom::Start(offset_location()))?;
+            out.write_u64::<LittleEndian>(end)?;
+            out.seek(SeekFrom::Start(count_location()))?;
+            out.write_u64::<LittleEndian>(count)?;
+            out.sync_all()?;
+
+            (old_len, end, count, moved)
+        };
+
+        // Once renamed the compaction is finished even if it's interrupted
+        fs::rename(&compacting, self.path.with_extension(COMPACTED_EXTENSION))?;
+        self.resume_compaction()?;
+
+        self.atomic_offset.store(end as usize, Ordering::SeqCst);
+        if let Some(writer_thread) = self.writer_thread.as_ref() {
+            writer_thread.reset_stored(end, count);
+        }
+
+        let mut index = UniqueIndex::new(&self.get_description());
+        if !index.is_empty() {
+            self.for_each_document(|offset, document| index.insert(document, offset))?;
+        }
+        *unique = index;
+        drop(unique);
+        self.finish_compaction()?;
+
+        info!("Compacted bucket {} from {} to {} bytes", self.name, old_len, end);
+        Ok(CompactionStats {
+            bytes_reclaimed: old_len.saturating_sub(end),
+            documents_moved,
+        })
+    }
+
+    /// Copies a compacted bucket over the bucket file, returning false if no compaction was
+    /// complete, see `compact`
+    ///
+    /// A compaction which wasn't complete is discarded.
+    fn resume_compaction(&self) -> Result<bool, NonaneError> {
+        let compacting = self.path.with_extension(COMPACTING_EXTENSION);
+        if compacting.exists() {
+            fs::remove_file(&compacting)?;
+        }
+
+        let compacted = self.path.with_extension(COMPACTED_EXTENSION);
+        if !compacted.exists() {
+            return Ok(false);
+        }
+
+        let mut wrt = self.writer.lock();
+        let _guard = WriteGuard::new(self.will_write.clone());
+        let mut src = File::open(&compacted)?;
+        let file = wrt.borrow_file();
+        file.seek(SeekFrom::Start(0))?;
+        let len = std::io::copy(&mut src, file)?;
+        file.set_len(len)?;
+        file.sync_all()?;
+
+        Ok(true)
+    }
+
+    /// Rebuilds the indexes from the compacted rows and removes the compacted bucket, see
+    /// `compact`
+    fn finish_compaction(&self) -> Result<(), NonaneError> {
+        let mut indexes = self.indexes.lock();
+        for index in indexes.iter_mut() {
+            index.clear()?;
+        }
+
+        self.try_for_each_document(|offset, document| {
+            for index in indexes.iter_mut() {
+                index.insert(document, offset)?;
+            }
+            Ok(())
+        })?;
+
+        for index in indexes.iter() {
+            index.sync()?;
+        }
+
+        fs::remove_file(self.path.with_extension(COMPACTED_EXTENSION))?;
+        Ok(())
+    }
+
+    /// Counts the documents written to disk
+    ///
+    /// Reads the count stored next to the offset for next document, which is kept up to date as
+    /// rows are written and deleted. See `count_documents_slow` to count the rows themselves.
+    pub fn count_documents(&mut self) -> Result<usize, NonaneError> {
+        let mut reader = self.readers.as_ref().unwrap().pull();
+        Ok(reader.as_mut_ref().get_stored_count()? as usize)
+    }
+
+    /// Counts the documents written to disk by walking every row up to the stored offset
+    ///
+    /// A row which runs past the end of the file errors with `NonaneError::TruncatedRow` instead
+    /// of being treated as the end of the data, an empty row ends the data, see `for_each_row`.
+    pub fn count_documents_slow(&self) -> Result<usize, NonaneError> {
+        let mut count = 0;
+        self.for_each_row(|_, _| count += 1)?;
+
+        Ok(count)
+    }
+
+    /// Returns the offsets of every document written to disk, in the order they were inserted
+    ///
+    /// Only the row headers are read, which makes this a cheap starting point for building
+    /// external indexes.
+    pub fn offsets(&self) -> Result<Vec<usize>, NonaneError> {
+        let mut offsets = Vec::new();
+        self.for_each_row(|offset, _| offsets.push(offset as usize))?;
+
+        Ok(offsets)
+    }
+
+    /// Calls `f` with the offset and header of every row up to the stored offset, deleted rows
+    /// are skipped
+    ///
+    /// A row which runs past the end of the file errors with `NonaneError::TruncatedRow`. A row
+    /// whose length doesn't reach past its header, like zeroed space left behind a crash, marks
+    /// the end of the data, just as it does when the tail is repaired on open.
+    fn for_each_row<F: FnMut(u64, RowHeader)>(&self, mut f: F) -> Result<(), NonaneError> {
+        // Borrow a reader
+        let mut reader = self.readers.as_ref().unwrap().pull();
+        let reader = reader.as_mut_ref();
+        let end = reader.get_stored_offset()?;
+        let mut file = reader.read_guard()?;
+        let file_len = file.metadata()?.len();
+
+        let mut offset = self.data_start;
+        while offset < end {
+            file.seek(SeekFrom::Start(offset))?;
+
+            let header = match file.read_u64::<LittleEndian>() {
+                Ok(s) => RowHeader::from_raw(s),
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                    return Err(NonaneError::TruncatedRow { offset });
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            if header.ends_data() {
+                break;
+            }
+
+            if offset + header.len > file_len {
+                return Err(NonaneError::TruncatedRow { offset });
+            }
+
+            if !header.is_deleted() {
+                f(offset, header);
+            }
+            offset += header.len;
+        }
+
+        Ok(())
+    }
+}
+
+unsafe impl<'a> Send for Bucket<'a> {}
+unsafe impl<'a> Sync for Bucket<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Seek, SeekFrom, Write},
+        sync::atomic::Ordering,
+        thread,
+    };
+
+    use crate::{
+        database::{error::NonaneError, Database},
+        utils::testing::{int_description, int_document, temp_path},
+    };
+
+    use super::{
+        config::{BucketConfiguration, DriveType},
+        descriptor::BucketDescription,
+        document::{
+            field::{descriptor::FieldDescriptor, fieldtype::FieldType, Field},
+            Document,
+        },
+        row, unpack_id,
+        writer::{last_write_location, offset_location},
+        Bucket, FORMAT_MAGIC, FORMAT_VERSION,
+    };
+
+    /// Writes are queued for a while before the writer drains them
+    fn slow_writer() -> BucketConfiguration {
+        BucketConfiguration {
+            drive_type: DriveType::HDD,
+            ..Default::default()
+        }
+    }
+
+    fn text_document(text: &str) -> Document {
+        Document::new(vec![Field::new("text", text.to_string()).unwrap()])
+    }
+
+    #[test]
+    fn concurrent_inserts_on_clones_get_distinct_offsets() {
+        let mut db = Database::open(temp_path("bucket-concurrent-inserts")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let bucket = db.get_bucket("numbers").unwrap().clone();
+
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let mut bucket = bucket.clone();
+                thread::spawn(move || {
+                    (0..50)
+                        .map(|i| bucket.insert(&int_document(t * 50 + i)).unwrap().0)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        let mut offsets: Vec<usize> = threads
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        assert_eq!(offsets.len(), 200);
+
+        bucket.flush().unwrap();
+        assert_eq!(bucket.count_documents_slow().unwrap(), 200);
+    }
+
+    #[test]
+    fn zeroed_row_header_ends_the_data() {
+        let mut db = Database::open(temp_path("bucket-zeroed-row")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        let first = bucket.insert(&int_document(1)).unwrap().0;
+        let second = bucket.insert(&int_document(2)).unwrap().0;
+        bucket.flush().unwrap();
+
+        // Zeroed space behind the stored offset, as a crash mid-write leaves it
+        let end = bucket.disk_usage();
+        {
+            let mut wrt = bucket.writer.lock();
+            wrt.borrow_file().set_len(end + 64).unwrap();
+            wrt.set_offset(end + 64).unwrap();
+        }
+
+        assert_eq!(bucket.offsets().unwrap(), vec![first, second]);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 2);
+    }
+
+    #[test]
+    fn get_by_id_reads_stored_rows_without_waiting() {
+        let mut db = Database::open(temp_path("bucket-get-by-id-stored")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), Some(slow_writer())).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        let (_, stored) = bucket.insert(&int_document(1)).unwrap();
+        bucket.flush().unwrap();
+
+        let (_, queued) = bucket.insert(&int_document(2)).unwrap();
+        let document = bucket.get_by_id(&stored).unwrap().unwrap();
+        assert_eq!(document.get_i32("n"), Some(1));
+        assert_eq!(bucket.queue_depth(), 1);
+
+        let document = bucket.get_by_id(&queued).unwrap().unwrap();
+        assert_eq!(document.get_i32("n"), Some(2));
+        assert_eq!(bucket.queue_depth(), 0);
+    }
+
+    #[test]
+    fn get_by_id_follows_a_relocated_document() {
+        let description =
+            BucketDescription::new(vec![FieldDescriptor::new("text", FieldType::Text).unwrap()]);
+        let mut db = Database::open(temp_path("bucket-get-by-id-relocated")).unwrap();
+        db.open_bucket("texts", Some(description), Some(slow_writer())).unwrap();
+        let mut bucket = db.get_bucket_mut("texts").unwrap();
+        let (offset, id) = bucket.insert(&text_document("short")).unwrap();
+        bucket.flush().unwrap();
+
+        // Growing the document appends it elsewhere and queues the mark deleting the old row
+        let (moved, _) = bucket
+            .update_at(offset as u64, &text_document("a lot longer than it was before"))
+            .unwrap();
+        assert_ne!(moved, unpack_id(&id).1);
+
+        let document = bucket.get_by_id(&id).unwrap().unwrap();
+        assert_eq!(
+            document.read_field("text").unwrap().get_value::<&str>(),
+            Some("a lot longer than it was before")
+        );
+    }
+
+    #[test]
+    fn failed_insert_gives_back_its_offset() {
+        let config = BucketConfiguration {
+            drive_type: DriveType::HDD,
+            queue_capacity: Some(1),
+            queue_full_wait: None,
+            ..Default::default()
+        };
+        let mut db = Database::open(temp_path("bucket-failed-insert")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), Some(config)).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+
+        // The writer sleeps before draining the queue, so it's still full for the second insert
+        let first = bucket.insert(&int_document(1)).unwrap().0;
+        let end = bucket.disk_usage();
+        assert!(matches!(bucket.insert(&int_document(2)), Err(NonaneError::QueueFull)));
+        assert_eq!(bucket.disk_usage(), end);
+
+        bucket.flush().unwrap();
+        let second = bucket.insert(&int_document(3)).unwrap().0;
+        assert_eq!(second as u64, end);
+
+        bucket.flush().unwrap();
+        assert_eq!(bucket.count_documents_slow().unwrap(), 2);
+        assert_eq!(bucket.get_at(first as u64).unwrap().unwrap().get_i32("n"), Some(1));
+        assert_eq!(bucket.get_at(second as u64).unwrap().unwrap().get_i32("n"), Some(3));
+    }
+
+    #[test]
+    fn appending_to_a_bytes_field_twice_reads_back_concatenated() {
+        let description = BucketDescription::new(vec![
+            FieldDescriptor::new("data", FieldType::Bytes).unwrap(),
+            FieldDescriptor::new("n", FieldType::Int32).unwrap(),
+        ]);
+        let mut db = Database::open(temp_path("bucket-append-to-field")).unwrap();
+        db.open_bucket("logs", Some(description), None).unwrap();
+        let mut bucket = db.get_bucket_mut("logs").unwrap();
+        let document = Document::new(vec![
+            Field::new("data", vec![1u8; 32]).unwrap(),
+            Field::new("n", 7i32).unwrap(),
+        ]);
+        let offset = bucket.insert(&document).unwrap().0 as u64;
+        bucket.flush().unwrap();
+
+        bucket.append_to_field(offset, "data", &[2, 3]).unwrap();
+        bucket.append_to_field(offset, "data", &[4]).unwrap();
+        bucket.flush().unwrap();
+        let document = bucket.get_at(offset).unwrap().unwrap();
+        let data = document.read_field("data").unwrap().get_value::<&[u8]>().unwrap();
+        assert_eq!(data, [&[1u8; 32][..], &[2, 3, 4]].concat().as_slice());
+        assert_eq!(document.get_i32("n"), Some(7));
+
+        assert!(bucket.append_to_field(offset, "n", &[5]).is_err());
+        assert!(bucket.append_to_field(offset, "missing", &[5]).is_err());
+    }
+
+    #[test]
+    fn row_cut_short_is_reported_as_truncated() {
+        let mut db = Database::open(temp_path("bucket-truncated-row")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        bucket.insert(&int_document(1)).unwrap();
+        let second = bucket.insert(&int_document(2)).unwrap().0 as u64;
+        bucket.flush().unwrap();
+        assert_eq!(bucket.count_documents_slow().unwrap(), 2);
+
+        // Cut the file mid-row, the stored offset still reaches past the end
+        {
+            let mut wrt = bucket.writer.lock();
+            let file = wrt.borrow_file();
+            let len = file.metadata().unwrap().len();
+            file.set_len(len - 5).unwrap();
+        }
+
+        assert!(matches!(
+            bucket.count_documents_slow(),
+            Err(NonaneError::TruncatedRow { offset }) if offset == second
+        ));
+    }
+
+    #[test]
+    fn synchronous_inserts_are_counted_without_a_flush() {
+        let config = BucketConfiguration {
+            synchronous: true,
+            ..Default::default()
+        };
+        let mut db = Database::open(temp_path("bucket-synchronous")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), Some(config)).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        assert!(bucket.writer_thread.is_none());
+
+        for n in 0..10 {
+            bucket.insert(&int_document(n)).unwrap();
+            assert_eq!(bucket.count_documents().unwrap(), n as usize + 1);
+        }
+        assert_eq!(bucket.count_documents_slow().unwrap(), 10);
+    }
+
+    #[test]
+    fn reserve_preallocates_the_file() {
+        let mut db = Database::open(temp_path("bucket-reserve")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        let start = bucket.atomic_offset.load(Ordering::SeqCst) as u64;
+        bucket.reserve(1000, 1024 * 1024).unwrap();
+
+        for n in 0..10 {
+            bucket.insert(&int_document(n)).unwrap();
+        }
+        bucket.flush().unwrap();
+
+        let len = bucket.writer.lock().borrow_file().metadata().unwrap().len();
+        assert!(len >= start + 1024 * 1024);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 10);
+    }
+
+    #[test]
+    fn oversized_reservations_error_or_are_capped() {
+        let config = BucketConfiguration {
+            queue_capacity: Some(4),
+            ..slow_writer()
+        };
+        let mut db = Database::open(temp_path("bucket-reserve-oversized")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), Some(config)).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+
+        assert!(matches!(
+            bucket.reserve(usize::MAX, u64::MAX),
+            Err(NonaneError::Io(e)) if e.kind() == std::io::ErrorKind::InvalidInput
+        ));
+        assert_eq!(bucket.writer_thread.as_ref().unwrap().q.capacity(), Some(4));
+
+        bucket.reserve(usize::MAX, 0).unwrap();
+        assert_eq!(
+            bucket.writer_thread.as_ref().unwrap().q.capacity(),
+            Some(4 * super::MAX_RESERVE_FACTOR)
+        );
+        bucket.insert(&int_document(1)).unwrap();
+        bucket.flush().unwrap();
+        assert_eq!(bucket.count_documents_slow().unwrap(), 1);
+    }
+
+    #[test]
+    fn reserve_grows_a_bounded_write_queue() {
+        let config = BucketConfiguration {
+            queue_capacity: Some(4),
+            queue_full_wait: None,
+            ..slow_writer()
+        };
+        let mut db = Database::open(temp_path("bucket-reserve-queue")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), Some(config)).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        for n in 0..4 {
+            bucket.insert(&int_document(n)).unwrap();
+        }
+
+        bucket.reserve(20, 0).unwrap();
+        assert_eq!(bucket.writer_thread.as_ref().unwrap().q.capacity(), Some(20));
+        bucket.reserve(2, 0).unwrap();
+        assert_eq!(bucket.writer_thread.as_ref().unwrap().q.capacity(), Some(20));
+
+        // Room for the whole load without waiting, and the writes queued before it are kept
+        for n in 4..20 {
+            bucket.insert(&int_document(n)).unwrap();
+        }
+        bucket.flush().unwrap();
+        let mut values: Vec<i32> = bucket
+            .scan()
+            .unwrap()
+            .iter()
+            .map(|d| d.get_i32("n").unwrap())
+            .collect();
+        values.sort_unstable();
+        assert_eq!(values, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn offsets_lists_live_rows_in_order() {
+        let mut db = Database::open(temp_path("bucket-offsets")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        let mut inserted: Vec<usize> = (0..6)
+            .map(|n| bucket.insert(&int_document(n)).unwrap().0)
+            .collect();
+        bucket.flush().unwrap();
+
+        let deleted = inserted.remove(2);
+        assert!(bucket.delete_at(deleted as u64).unwrap());
+        bucket.flush().unwrap();
+
+        assert_eq!(bucket.offsets().unwrap(), inserted);
+    }
+
+    #[test]
+    fn queue_pressure_is_the_fraction_of_the_queue_in_use() {
+        let config = BucketConfiguration {
+            queue_capacity: Some(10),
+            ..slow_writer()
+        };
+        let mut db = Database::open(temp_path("bucket-queue-pressure")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), Some(config)).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        assert_eq!(bucket.queue_pressure(), 0.0);
+
+        for n in 0..4 {
+            bucket.insert(&int_document(n)).unwrap();
+        }
+        assert_eq!(bucket.queue_depth(), 4);
+        assert!((bucket.queue_pressure() - 0.4).abs() < f32::EPSILON);
+
+        bucket.flush().unwrap();
+        assert_eq!(bucket.queue_depth(), 0);
+        assert_eq!(bucket.queue_pressure(), 0.0);
+    }
+
+    #[test]
+    fn descriptor_spanning_several_pages_is_read_past() {
+        let names: Vec<String> = (0..400)
+            .map(|i| format!("a_rather_long_field_name_{}", i))
+            .collect();
+        let fields = names
+            .iter()
+            .map(|name| FieldDescriptor::new(name, FieldType::Int32).unwrap())
+            .collect();
+        let document = Document::new(
+            names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| Field::new(name, i as i32).unwrap())
+                .collect(),
+        );
+        let path = temp_path("bucket-wide-descriptor");
+
+        let offset = {
+            let mut db = Database::open(path).unwrap();
+            db.open_bucket("wide", Some(BucketDescription::new(fields)), None).unwrap();
+            let mut bucket = db.get_bucket_mut("wide").unwrap();
+            assert!(bucket.data_start > page_size::get() as u64);
+
+            let offset = bucket.insert(&document).unwrap().0 as u64;
+            bucket.flush().unwrap();
+            assert!(offset >= bucket.data_start);
+            offset
+        };
+
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("wide", None, None).unwrap();
+        let bucket = db.get_bucket("wide").unwrap();
+        assert_eq!(bucket.count_documents_slow().unwrap(), 1);
+        let read = bucket.get_at(offset).unwrap().unwrap();
+        assert_eq!(read.get_i32("a_rather_long_field_name_399"), Some(399));
+    }
+
+    #[test]
+    fn layout_info_reports_the_data_start_and_stored_offset() {
+        let mut db = Database::open(temp_path("bucket-layout-info")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+
+        let layout = bucket.layout_info().unwrap();
+        assert_eq!(layout.data_start, layout.page_size);
+        assert_eq!(layout.stored_offset, layout.data_start);
+        assert_eq!(layout.row_alignment, 8);
+
+        let mut last = layout.stored_offset;
+        for n in 0..3 {
+            let offset = bucket.insert(&int_document(n)).unwrap().0 as u64;
+            bucket.flush().unwrap();
+            assert_eq!(offset, last);
+
+            let stored = bucket.layout_info().unwrap().stored_offset;
+            assert!(stored > last);
+            assert_eq!(stored % layout.row_alignment, 0);
+            last = stored;
+        }
+        assert!(bucket.file_size().unwrap() >= last);
+    }
+
+    #[test]
+    fn metadata_survives_a_reopen() {
+        let path = temp_path("bucket-metadata");
+        {
+            let mut db = Database::open(path).unwrap();
+            db.open_bucket("numbers", Some(int_description()), None).unwrap();
+            let mut bucket = db.get_bucket_mut("numbers").unwrap();
+            bucket.insert(&int_document(1)).unwrap();
+            bucket.set_meta("owner", "ada").unwrap();
+            bucket.set_meta("schema_version", "1").unwrap();
+            bucket.set_meta("schema_version", "2").unwrap();
+            bucket.flush().unwrap();
+        }
+
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("numbers", None, None).unwrap();
+        let bucket = db.get_bucket("numbers").unwrap();
+        assert_eq!(bucket.get_meta("owner").as_deref(), Some("ada"));
+        assert_eq!(bucket.get_meta("schema_version").as_deref(), Some("2"));
+        assert_eq!(bucket.get_meta("last_import_id"), None);
+        assert_eq!(bucket.list_meta().len(), 2);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 1);
+    }
+
+    #[test]
+    fn inserts_past_the_quota_error_and_keep_earlier_ones() {
+        let limit = page_size::get() as u64 + 512;
+        let config = BucketConfiguration {
+            max_bucket_bytes: Some(limit),
+            ..Default::default()
+        };
+        let mut db = Database::open(temp_path("bucket-quota")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), Some(config)).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        bucket.reserve(0, 4096).unwrap();
+
+        let mut inserted = 0;
+        let err = loop {
+            match bucket.insert(&int_document(inserted)) {
+                Ok(_) => inserted += 1,
+                Err(e) => break e,
+            }
+        };
+        assert!(matches!(err, NonaneError::BucketQuotaExceeded { limit: l } if l == limit));
+        assert!(inserted > 0);
+        assert!(bucket.disk_usage() <= limit);
+
+        bucket.flush().unwrap();
+        assert_eq!(bucket.count_documents_slow().unwrap(), inserted as usize);
+    }
+
+    #[test]
+    fn recompute_offset_repairs_a_corrupted_offset() {
+        let mut db = Database::open(temp_path("bucket-recompute-offset")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        let offsets: Vec<usize> = (0..3)
+            .map(|n| bucket.insert(&int_document(n)).unwrap().0)
+            .collect();
+        bucket.flush().unwrap();
+        let end = bucket.disk_usage();
+
+        // Point the offset back into the middle of the data
+        let corrupted = offsets[1] as u64;
+        bucket.writer.lock().set_offset(corrupted).unwrap();
+        bucket.atomic_offset.store(corrupted as usize, Ordering::SeqCst);
+
+        assert_eq!(bucket.recompute_offset().unwrap(), end);
+        assert_eq!(bucket.disk_usage(), end);
+
+        let next = bucket.insert(&int_document(3)).unwrap().0;
+        bucket.flush().unwrap();
+        assert_eq!(next as u64, end);
+        assert_eq!(bucket.offsets().unwrap(), [offsets, vec![next]].concat());
+        for (n, offset) in bucket.offsets().unwrap().into_iter().enumerate() {
+            let read = bucket.get_at(offset as u64).unwrap().unwrap();
+            assert_eq!(read.get_i32("n"), Some(n as i32));
+        }
+    }
+
+    #[test]
+    fn files_in_an_unknown_format_are_refused_untouched() {
+        let path = temp_path("bucket-legacy-format");
+        Database::create(path).unwrap().close().unwrap();
+
+        // Written before the format was stamped, the rows follow a bare descriptor length
+        let mut legacy = vec![0; page_size::get() * 2];
+        legacy[..8].copy_from_slice(&16u64.to_le_bytes());
+        let legacy_path = std::path::Path::new(path).join("legacy.page");
+        std::fs::write(&legacy_path, &legacy).unwrap();
+
+        // Written by a newer build
+        let mut newer = legacy.clone();
+        newer[..4].copy_from_slice(&FORMAT_MAGIC);
+        newer[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(std::path::Path::new(path).join("newer.page"), &newer).unwrap();
+
+        let mut db = Database::open(path).unwrap();
+        assert!(matches!(
+            db.open_bucket("legacy", Some(int_description()), None),
+            Err(NonaneError::UnsupportedFormat { version: 0 })
+        ));
+        assert!(matches!(
+            db.open_bucket("newer", Some(int_description()), None),
+            Err(NonaneError::UnsupportedFormat { version }) if version == FORMAT_VERSION + 1
+        ));
+        assert_eq!(std::fs::read(&legacy_path).unwrap(), legacy);
+    }
+
+    #[test]
+    fn primary_key_is_only_set_over_unique_values() {
+        let mut db = Database::open(temp_path("bucket-primary-key")).unwrap();
+        db.open_bucket("unique", Some(int_description()), None).unwrap();
+        db.open_bucket("duplicated", Some(int_description()), None).unwrap();
+
+        let mut bucket = db.get_bucket_mut("unique").unwrap();
+        for n in 0..5 {
+            bucket.insert(&int_document(n)).unwrap();
+        }
+        bucket.set_primary_key("n").unwrap();
+        assert!(matches!(
+            bucket.insert(&int_document(3)),
+            Err(NonaneError::UniqueViolation { .. })
+        ));
+        bucket.insert(&int_document(5)).unwrap();
+        bucket.flush().unwrap();
+        assert_eq!(bucket.count_documents_slow().unwrap(), 6);
+        drop(bucket);
+
+        let mut bucket = db.get_bucket_mut("duplicated").unwrap();
+        for n in &[1, 2, 1] {
+            bucket.insert(&int_document(*n)).unwrap();
+        }
+        assert!(matches!(
+            bucket.set_primary_key("n"),
+            Err(NonaneError::DuplicateKey { field }) if field == "n"
+        ));
+        bucket.insert(&int_document(2)).unwrap();
+        assert!(matches!(
+            bucket.set_primary_key("missing"),
+            Err(NonaneError::FieldNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn scan_both_copies_unchanged_rows_by_their_bytes() {
+        let mut db = Database::open(temp_path("bucket-scan-both")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        for n in 0..6 {
+            bucket.insert(&int_document(n)).unwrap();
+        }
+        bucket.flush().unwrap();
+
+        // Odd numbers are doubled, even ones are passed through as stored
+        let mut copied = 0;
+        let mut output = Vec::new();
+        bucket
+            .scan_both(|_, raw, document| {
+                let n = document.get_i32("n").unwrap();
+                if n % 2 == 0 {
+                    assert_eq!(raw, &document.serialize().unwrap()[..]);
+                    output.push(raw.to_vec());
+                    copied += 1;
+                } else {
+                    let mut doubled = document.clone();
+                    doubled.set_field(Field::new("n", n * 2).unwrap());
+                    output.push(doubled.serialize().unwrap());
+                }
+            })
+            .unwrap();
+
+        assert_eq!(copied, 3);
+        let numbers: Vec<i32> = output
+            .iter()
+            .map(|row| Document::deserialize(row).unwrap().get_i32("n").unwrap())
+            .collect();
+        assert_eq!(numbers, [0, 2, 2, 6, 4, 10]);
+    }
+
+    #[test]
+    fn get_at_reads_back_documents_by_their_offset() {
+        let mut db = Database::open(temp_path("bucket-get-at")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        let offsets: Vec<u64> = [10, 20, 30]
+            .iter()
+            .map(|n| bucket.insert(&int_document(*n)).unwrap().0 as u64)
+            .collect();
+        bucket.flush().unwrap();
+
+        for (offset, n) in offsets.iter().rev().zip([30, 20, 10].iter()) {
+            let read = bucket.get_at(*offset).unwrap().unwrap();
+            assert_eq!(read.get_i32("n"), Some(*n));
+        }
+        assert_eq!(bucket.get_at(bucket.disk_usage()).unwrap(), None);
+        assert!(matches!(
+            bucket.get_at(8),
+            Err(NonaneError::NoDocumentAt { offset: 8 })
+        ));
+    }
+
+    #[test]
+    fn saturated_queue_waits_instead_of_dropping_writes() {
+        let config = BucketConfiguration {
+            queue_capacity: Some(4),
+            queue_full_wait: Some(std::time::Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let mut db = Database::open(temp_path("bucket-queue-saturated")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), Some(config)).unwrap();
+        let bucket = db.get_bucket("numbers").unwrap().clone();
+
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let mut bucket = bucket.clone();
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        bucket.insert(&int_document(t * 100 + i)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+
+        bucket.flush().unwrap();
+        assert_eq!(bucket.count_documents_slow().unwrap(), 400);
+    }
+
+    #[test]
+    fn stored_count_agrees_with_a_scan() {
+        let path = temp_path("bucket-stored-count");
+        {
+            let mut db = Database::open(path).unwrap();
+            db.open_bucket("numbers", Some(int_description()), None).unwrap();
+            let mut bucket = db.get_bucket_mut("numbers").unwrap();
+            let offsets: Vec<u64> = (0..20)
+                .map(|n| bucket.insert(&int_document(n)).unwrap().0 as u64)
+                .collect();
+            bucket.flush().unwrap();
+            for offset in offsets.iter().step_by(4) {
+                assert!(bucket.delete_at(*offset).unwrap());
+            }
+            bucket.flush().unwrap();
+
+            assert_eq!(bucket.count_documents().unwrap(), 15);
+            assert_eq!(bucket.count_documents_slow().unwrap(), 15);
+        }
+
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("numbers", None, None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        assert_eq!(bucket.count_documents().unwrap(), 15);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 15);
+    }
+
+    #[test]
+    fn find_page_skips_deleted_documents_and_ends_short() {
+        let mut db = Database::open(temp_path("bucket-find-page")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        let offsets: Vec<u64> = (0..10)
+            .map(|n| bucket.insert(&int_document(n)).unwrap().0 as u64)
+            .collect();
+        bucket.flush().unwrap();
+        assert!(bucket.delete_at(offsets[1]).unwrap());
+        assert!(bucket.delete_at(offsets[4]).unwrap());
+        bucket.flush().unwrap();
+
+        let page = |skip: usize, take: usize| -> Vec<i32> {
+            bucket
+                .find_page(skip, take)
+                .unwrap()
+                .iter()
+                .map(|d| d.get_i32("n").unwrap())
+                .collect()
+        };
+        assert_eq!(page(0, 3), [0, 2, 3]);
+        assert_eq!(page(3, 3), [5, 6, 7]);
+        assert_eq!(page(6, 3), [8, 9]);
+        assert!(page(8, 3).is_empty());
+        assert!(page(100, 3).is_empty());
+        assert!(page(0, 0).is_empty());
+    }
+
+    #[test]
+    fn torn_final_record_is_cut_off_on_reopen() {
+        let path = temp_path("bucket-torn-record");
+        let end = {
+            let mut db = Database::open(path).unwrap();
+            db.open_bucket("numbers", Some(int_description()), None).unwrap();
+            let mut bucket = db.get_bucket_mut("numbers").unwrap();
+            for n in 0..3 {
+                bucket.insert(&int_document(n)).unwrap();
+            }
+            bucket.flush().unwrap();
+            let end = bucket.disk_usage();
+
+            // A row whose header made it to disk but whose body didn't, with the offset past it
+            let mut torn = 1000u64.to_le_bytes().to_vec();
+            torn.extend_from_slice(&[0xAB; 20]);
+            let mut wrt = bucket.writer.lock();
+            wrt.borrow_file().seek(SeekFrom::Start(end)).unwrap();
+            wrt.borrow_file().write_all(&torn).unwrap();
+            wrt.set_offset(end + torn.len() as u64).unwrap();
+            wrt.set_count(4).unwrap();
+            end
+        };
+
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("numbers", None, None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        assert_eq!(bucket.disk_usage(), end);
+        assert_eq!(bucket.file_size().unwrap(), end);
+        assert_eq!(bucket.count_documents().unwrap(), 3);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 3);
+
+        assert_eq!(bucket.insert(&int_document(3)).unwrap().0 as u64, end);
+        bucket.flush().unwrap();
+        assert_eq!(bucket.get_at(end).unwrap().unwrap().get_i32("n"), Some(3));
+    }
+
+    #[test]
+    fn compaction_shrinks_the_file_and_keeps_ids_working() {
+        let mut db = Database::open(temp_path("bucket-compact")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        let ids: Vec<[u8; 24]> = (0..20)
+            .map(|n| bucket.insert(&int_document(n)).unwrap().1)
+            .collect();
+        bucket.flush().unwrap();
+        for id in ids.iter().step_by(2) {
+            let (_, offset) = unpack_id(id);
+            assert!(bucket.delete_at(offset).unwrap());
+        }
+        bucket.flush().unwrap();
+        let size = bucket.file_size().unwrap();
+
+        let stats = bucket.compact().unwrap();
+        assert!(stats.bytes_reclaimed > 0);
+        assert_eq!(stats.documents_moved, 10);
+        assert_eq!(bucket.file_size().unwrap(), size - stats.bytes_reclaimed);
+        assert_eq!(bucket.count_documents().unwrap(), 10);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 10);
+
+        for (n, id) in ids.iter().enumerate() {
+            let read = bucket.get_by_id(id).unwrap();
+            match n % 2 {
+                0 => assert!(read.is_none()),
+                _ => assert_eq!(read.unwrap().get_i32("n"), Some(n as i32)),
+            }
+        }
+    }
+
+    #[test]
+    fn stats_add_up_for_a_known_set_of_rows() {
+        let mut db = Database::open(temp_path("bucket-stats")).unwrap();
+        let description =
+            BucketDescription::new(vec![FieldDescriptor::new("text", FieldType::Text).unwrap()]);
+        db.open_bucket("texts", Some(description), None).unwrap();
+        let mut bucket = db.get_bucket_mut("texts").unwrap();
+        let offsets: Vec<u64> = ["a", "bb", "ccc", "dddd", "eeeee"]
+            .iter()
+            .map(|text| bucket.insert(&text_document(text)).unwrap().0 as u64)
+            .collect();
+        bucket.flush().unwrap();
+
+        // Expected numbers, measured row by row
+        let mut padding = 0;
+        let mut tombstone_bytes = 0;
+        for (i, offset) in offsets.iter().enumerate() {
+            let serialized = bucket.get_at(*offset).unwrap().unwrap().serialize().unwrap();
+            let (header, _) = {
+                let mut reader = bucket.readers.as_ref().unwrap().pull();
+                reader.as_mut_ref().read_raw_row(*offset).unwrap()
+            };
+            if i % 2 == 0 {
+                padding += header.len - row::HEADER_SIZE - serialized.len() as u64;
+            } else {
+                tombstone_bytes += header.len;
+                assert!(bucket.delete_at(*offset).unwrap());
+            }
+        }
+        bucket.flush().unwrap();
+        assert!(padding > 0);
+
+        let stats = bucket.stats().unwrap();
+        assert_eq!(stats.documents, 3);
+        assert_eq!(stats.tombstones, 2);
+        assert_eq!(stats.tombstone_bytes, tombstone_bytes);
+        assert_eq!(stats.padding_bytes, padding);
+        assert_eq!(stats.offset, bucket.disk_usage());
+        assert_eq!(stats.file_size, bucket.file_size().unwrap());
+    }
+
+    #[test]
+    fn descriptor_past_64_kib_spans_pages_up_to_the_rows() {
+        let names: Vec<String> = (0..3000)
+            .map(|i| format!("field_with_a_long_name_{}", i))
+            .collect();
+        let fields = names
+            .iter()
+            .map(|name| FieldDescriptor::new(name, FieldType::Int32).unwrap())
+            .collect();
+        let path = temp_path("bucket-huge-descriptor");
+
+        let layout = {
+            let mut db = Database::open(path).unwrap();
+            db.open_bucket("wide", Some(BucketDescription::new(fields)), None).unwrap();
+            let bucket = db.get_bucket("wide").unwrap();
+            bucket.layout_info().unwrap()
+        };
+        assert!(layout.descriptor_len > u16::MAX as u64);
+        assert_eq!(layout.data_start % layout.page_size, 0);
+        let descriptor_end = layout.descriptor_start + layout.descriptor_len;
+        assert!(layout.data_start >= descriptor_end);
+        assert!(layout.data_start - layout.page_size < descriptor_end);
+
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("wide", None, None).unwrap();
+        let mut bucket = db.get_bucket_mut("wide").unwrap();
+        assert_eq!(bucket.get_description().field_description.len(), 3000);
+        let document = Document::new(
+            names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| Field::new(name, i as i32).unwrap())
+                .collect(),
+        );
+        let offset = bucket.insert(&document).unwrap().0 as u64;
+        bucket.flush().unwrap();
+        assert_eq!(offset, layout.data_start);
+        let read = bucket.get_at(offset).unwrap().unwrap();
+        assert_eq!(read.get_i32("field_with_a_long_name_2999"), Some(2999));
+    }
+
+    #[test]
+    fn descriptor_page_tail_is_zeroed() {
+        let mut db = Database::open(temp_path("bucket-descriptor-zeroed")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+
+        let tail_is_zeroed = |bucket: &Bucket| {
+            let layout = bucket.layout_info().unwrap();
+            let file = std::fs::read(bucket.path()).unwrap();
+            let start = (layout.descriptor_start + layout.descriptor_len) as usize;
+            file[start..last_write_location() as usize].iter().all(|b| *b == 0)
+        };
+        assert!(tail_is_zeroed(&bucket));
+
+        // A shorter descriptor leaves nothing of the longer one behind
+        bucket.set_meta("note", &"x".repeat(1000)).unwrap();
+        assert!(tail_is_zeroed(&bucket));
+        bucket.set_meta("note", "short").unwrap();
+        assert!(tail_is_zeroed(&bucket));
+    }
+
+    #[test]
+    fn deleting_twice_only_uncounts_the_document_once() {
+        let mut db = Database::open(temp_path("bucket-double-delete")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), Some(slow_writer())).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        let first = bucket.insert(&int_document(1)).unwrap().0 as u64;
+        bucket.insert(&int_document(2)).unwrap();
+        bucket.flush().unwrap();
+
+        // The first mark is still queued when the row is deleted again
+        assert!(bucket.delete_at(first).unwrap());
+        assert!(!bucket.delete_at(first).unwrap());
+
+        bucket.flush().unwrap();
+        assert_eq!(bucket.count_documents().unwrap(), 1);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 1);
+    }
+
+    #[test]
+    fn back_to_back_updates_replace_each_other() {
+        let mut db = Database::open(temp_path("bucket-back-to-back-updates")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), Some(slow_writer())).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        bucket.set_primary_key("n").unwrap();
+        let offset = bucket.insert(&int_document(1)).unwrap().0 as u64;
+        bucket.flush().unwrap();
+
+        // The second update reads the row the first one rewrote, not the one still on disk
+        assert_eq!(bucket.update_at(offset, &int_document(2)).unwrap().0, offset);
+        assert_eq!(bucket.update_at(offset, &int_document(3)).unwrap().0, offset);
+        bucket.flush().unwrap();
+
+        assert_eq!(bucket.get_at(offset).unwrap().unwrap().get_i32("n"), Some(3));
+        assert_eq!(bucket.count_documents().unwrap(), 1);
+
+        // Only the latest value is held by the unique index
+        bucket.insert(&int_document(1)).unwrap();
+        bucket.insert(&int_document(2)).unwrap();
+        assert!(bucket.insert(&int_document(3)).is_err());
+    }
+
+    #[test]
+    fn concurrent_growing_updates_of_a_row_leave_one_document() {
+        let description =
+            BucketDescription::new(vec![FieldDescriptor::new("text", FieldType::Text).unwrap()]);
+        let mut db = Database::open(temp_path("bucket-concurrent-grow")).unwrap();
+        db.open_bucket("texts", Some(description), None).unwrap();
+        let bucket = db.get_bucket("texts").unwrap().clone();
+
+        for _ in 0..20 {
+            let offset = bucket.clone().insert(&text_document("short")).unwrap().0 as u64;
+            let threads: Vec<_> = (0..2)
+                .map(|t| {
+                    let mut bucket = bucket.clone();
+                    thread::spawn(move || {
+                        let text = format!("grown past the old row by thread {}", t);
+                        bucket.update_at(offset, &text_document(&text))
+                    })
+                })
+                .collect();
+            let updated: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+
+            // The update which came second finds the old row already deleted
+            let moved = updated.iter().find_map(|u| u.as_ref().ok()).unwrap().0;
+            for u in updated.iter().filter(|u| u.is_err()) {
+                assert!(matches!(u, Err(NonaneError::NoDocumentAt { .. })));
+            }
+            bucket.flush().unwrap();
+            assert_eq!(bucket.count_documents_slow().unwrap(), 1);
+            assert!(bucket.clone().delete_at(moved).unwrap());
+            bucket.flush().unwrap();
+        }
+    }
+
+    #[test]
+    fn update_after_a_queued_delete_leaves_the_row_deleted() {
+        let mut db = Database::open(temp_path("bucket-delete-then-update")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), Some(slow_writer())).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        let offset = bucket.insert(&int_document(1)).unwrap().0 as u64;
+        bucket.flush().unwrap();
+
+        assert!(bucket.delete_at(offset).unwrap());
+        assert!(matches!(
+            bucket.update_at(offset, &int_document(2)),
+            Err(NonaneError::NoDocumentAt { .. })
+        ));
+
+        bucket.flush().unwrap();
+        assert_eq!(bucket.get_at(offset).unwrap(), None);
+        assert_eq!(bucket.count_documents().unwrap(), 0);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 0);
+    }
+
+    #[test]
+    fn appending_to_a_queued_row_waits_for_it_and_updates_the_unique_index() {
+        let description = BucketDescription::new(vec![
+            FieldDescriptor::new("data", FieldType::Bytes).unwrap().unique(),
+        ]);
+        let mut db = Database::open(temp_path("bucket-append-to-queued")).unwrap();
+        db.open_bucket("logs", Some(description), Some(slow_writer())).unwrap();
+        let mut bucket = db.get_bucket_mut("logs").unwrap();
+        let document = Document::new(vec![Field::new("data", vec![1u8; 32]).unwrap()]);
+        let offset = bucket.insert(&document).unwrap().0 as u64;
+
+        // The row is still queued, it's waited for instead of erroring
+        bucket.append_to_field(offset, "data", &[2]).unwrap();
+        bucket.flush().unwrap();
+
+        let appended = [&[1u8; 32][..], &[2]].concat();
+        let document = bucket.get_at(offset).unwrap().unwrap();
+        assert_eq!(document.read_field("data").unwrap().get_value::<&[u8]>(), Some(&appended[..]));
+
+        // The old value is free again, the appended one is taken
+        let old = Document::new(vec![Field::new("data", vec![1u8; 32]).unwrap()]);
+        bucket.insert(&old).unwrap();
+        let taken = Document::new(vec![Field::new("data", appended).unwrap()]);
+        assert!(matches!(bucket.insert(&taken), Err(NonaneError::DuplicateKey { .. })));
+    }
+
+    #[test]
+    fn preallocated_space_survives_a_reopen() {
+        let path = temp_path("bucket-reserve-reopen");
+        let (end, size) = {
+            let mut db = Database::open(path).unwrap();
+            db.open_bucket("numbers", Some(int_description()), None).unwrap();
+            let mut bucket = db.get_bucket_mut("numbers").unwrap();
+            bucket.insert(&int_document(1)).unwrap();
+            bucket.flush().unwrap();
+            bucket.reserve(100, 1 << 20).unwrap();
+            (bucket.disk_usage(), bucket.file_size().unwrap())
+        };
+        assert!(size >= end + (1 << 20));
+
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("numbers", None, None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        assert_eq!(bucket.file_size().unwrap(), size);
+        assert_eq!(bucket.disk_usage(), end);
+        assert_eq!(bucket.count_documents().unwrap(), 1);
+
+        let offset = bucket.insert(&int_document(2)).unwrap().0 as u64;
+        assert_eq!(offset, end);
+        bucket.flush().unwrap();
+        assert_eq!(bucket.count_documents_slow().unwrap(), 2);
+    }
+
+    #[test]
+    fn header_only_row_ends_the_rows_on_reopen() {
+        let path = temp_path("bucket-header-only-row");
+        let end = {
+            let mut db = Database::open(path).unwrap();
+            db.open_bucket("numbers", Some(int_description()), None).unwrap();
+            let mut bucket = db.get_bucket_mut("numbers").unwrap();
+            bucket.insert(&int_document(1)).unwrap();
+            bucket.flush().unwrap();
+            let end = bucket.disk_usage();
+
+            // Scans stop at a row no longer than its header, so the repair on open does as well
+            let mut wrt = bucket.writer.lock();
+            wrt.borrow_file().seek(SeekFrom::Start(end)).unwrap();
+            wrt.borrow_file().write_all(&row::HEADER_SIZE.to_le_bytes()).unwrap();
+            wrt.set_offset(end + row::HEADER_SIZE).unwrap();
+            end
+        };
+
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("numbers", None, None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        assert_eq!(bucket.disk_usage(), end);
+        assert_eq!(bucket.count_documents().unwrap(), 1);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 1);
+    }
+
+    #[test]
+    fn rows_in_front_of_the_last_write_are_not_walked_on_open() {
+        let path = temp_path("bucket-tail-from-last-write");
+        let (first, size) = {
+            let mut db = Database::open(path).unwrap();
+            db.open_bucket("numbers", Some(int_description()), None).unwrap();
+            let mut bucket = db.get_bucket_mut("numbers").unwrap();
+            let first = bucket.insert(&int_document(1)).unwrap().0 as u64;
+            bucket.flush().unwrap();
+            let last = bucket.insert(&int_document(2)).unwrap().0 as u64;
+            bucket.flush().unwrap();
+
+            let mut wrt = bucket.writer.lock();
+            assert_eq!(wrt.get_last_write().unwrap(), Some(last));
+
+            // A walk from the first row would stop here and cut off the second one
+            wrt.borrow_file().seek(SeekFrom::Start(first)).unwrap();
+            wrt.borrow_file().write_all(&[0; 8]).unwrap();
+            (first, bucket.file_size().unwrap())
+        };
+
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("numbers", None, None).unwrap();
+        let bucket = db.get_bucket("numbers").unwrap();
+        assert_eq!(bucket.file_size().unwrap(), size);
+        assert!(bucket.disk_usage() > first);
+    }
+
+    #[test]
+    fn file_without_the_last_write_is_repaired_and_upgraded() {
+        let path = temp_path("bucket-tail-legacy");
+        let end = {
+            let mut db = Database::open(path).unwrap();
+            db.open_bucket("numbers", Some(int_description()), None).unwrap();
+            let mut bucket = db.get_bucket_mut("numbers").unwrap();
+            for n in 0..3 {
+                bucket.insert(&int_document(n)).unwrap();
+            }
+            bucket.flush().unwrap();
+            let end = bucket.disk_usage();
+
+            // Written as the previous version, which keeps zeroes in front of the offsets
+            let mut wrt = bucket.writer.lock();
+            let file = wrt.borrow_file();
+            file.seek(SeekFrom::Start(4)).unwrap();
+            file.write_all(&(FORMAT_VERSION - 1).to_le_bytes()).unwrap();
+            file.seek(SeekFrom::Start(last_write_location())).unwrap();
+            file.write_all(&[0; 8]).unwrap();
+
+            // A torn record following the rows
+            file.seek(SeekFrom::Start(end)).unwrap();
+            file.write_all(&1000u64.to_le_bytes()).unwrap();
+            wrt.set_offset(end + 8).unwrap();
+            end
+        };
+
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("numbers", None, None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        assert_eq!(bucket.disk_usage(), end);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 3);
+        assert_eq!(bucket.layout_info().unwrap().format_version, FORMAT_VERSION);
+
+        bucket.insert(&int_document(3)).unwrap();
+        bucket.flush().unwrap();
+        assert_eq!(bucket.writer.lock().get_last_write().unwrap(), Some(end));
+    }
+
+    #[test]
+    fn missing_bucket_file_errors_instead_of_panicking() {
+        let path = std::path::Path::new(temp_path("bucket-missing-file")).to_path_buf();
+        std::fs::create_dir_all(&path).unwrap();
+        let file = std::fs::File::create(path.join("other.page")).unwrap();
+
+        let bucket = Bucket::new(
+            "missing",
+            file,
+            path.join("missing.page"),
+            false,
+            None,
+            BucketConfiguration::default(),
+        );
+        assert!(matches!(bucket, Err(NonaneError::Io(_))));
+    }
+}
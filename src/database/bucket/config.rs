@@ -1,16 +1,188 @@
+use std::time::Duration;
+
+use super::{encryption::EncryptionKey, MAX_ITEMS_IN_QUEUE};
+
+/// Most readers pooled for a bucket by default, regardless of the amount of cores
+pub const DEFAULT_MAX_READERS: usize = 8;
+
+/// How long a write waits for room in a full queue by default
+pub const DEFAULT_QUEUE_FULL_WAIT: Duration = Duration::from_secs(1);
+
 #[derive(Clone, Copy, Debug, PartialEq, Hash)]
 pub struct BucketConfiguration {
-    drive_type: DriveType,
+    pub drive_type: DriveType,
+    /// Most readers (and descriptor copies) pooled for the bucket
+    ///
+    /// A single reader is opened up front, more are opened while all of them are in use.
+    /// Defaults to the amount of cores, capped at `DEFAULT_MAX_READERS`.
+    pub readers: usize,
+    /// Maximum amount of writes that can be queued for the writer thread, `None` for no limit
+    ///
+    /// Every queued write owns the bytes of its row until it's written, so a full queue holds
+    /// about `queue_capacity` times the size of a row in memory, 50000 rows of 1 MiB take up
+    /// close to 50 GiB. Without a limit inserts never error with `NonaneError::QueueFull`, but
+    /// the queue grows without bound while writes are queued faster than the disk takes them.
+    pub queue_capacity: Option<usize>,
+    /// How long a write waits for room when the queue is full, `None` errors right away
+    ///
+    /// The write is retried each time the writer thread finishes a batch, it errors with
+    /// `NonaneError::QueueFull` if the queue is still full once the wait is over.
+    pub queue_full_wait: Option<Duration>,
+    /// When the writer thread syncs written chunks to disk
+    pub sync_policy: SyncPolicy,
+    /// Write documents on the inserting thread instead of queueing them for a writer thread
+    ///
+    /// An insert returns once the document has been synced to disk, which makes inserts slower
+    /// but deterministic. No writer thread is spawned for the bucket.
+    pub synchronous: bool,
+    /// Compress documents whose serialized size exceeds this many bytes, `None` never compresses
+    ///
+    /// A document is stored uncompressed if compressing it doesn't make it any smaller. The codec
+    /// is chosen in the bucket's descriptor, see `BucketDescription::with_compression`.
+    pub compress_min_bytes: Option<usize>,
+    /// Store field names as their position within the bucket's fields instead of in full
+    ///
+    /// Saves the space of repeating every field name in every row. Documents holding a field
+    /// which isn't part of the bucket's fields are stored with their full names.
+    pub intern_field_names: bool,
+    /// Pad rows to a multiple of 8 bytes
+    ///
+    /// Keeps rows aligned, disabling it stores every row at its exact length. Rows without
+    /// padding have no room for an overflow reference, see `Bucket::append_to_field`.
+    pub pad_rows: bool,
+    /// Store `Bytes` fields larger than this many bytes in the bucket's overflow file, `None`
+    /// keeps every field in its row
+    ///
+    /// Only a reference to the bytes is kept in the row, which keeps the bucket file dense.
+    /// Reads fetch the bytes from the overflow file transparently.
+    pub overflow_min_bytes: Option<usize>,
+    /// Most bytes the bucket's descriptor and rows may take up, `None` for no limit
+    ///
+    /// Inserts which would grow the bucket past it error with
+    /// `NonaneError::BucketQuotaExceeded`. Preallocated space and the overflow file aren't
+    /// counted, see `Bucket::disk_usage`.
+    pub max_bucket_bytes: Option<u64>,
+    /// Encrypt the rows of the bucket with this key, `None` stores them as they are
+    ///
+    /// The key is never written to disk, it has to be supplied every time the bucket is opened.
+    /// Reading an encrypted row without it, or with another key, errors with
+    /// `NonaneError::DecryptionFailed`. The descriptor and the files of value indexes aren't
+    /// encrypted, and `Bytes` fields are kept in their row instead of the overflow file.
+    pub encryption_key: Option<EncryptionKey>,
+    /// Read rows from a memory mapping of the bucket's file instead of seeking and reading it
+    ///
+    /// Point reads like `Bucket::get_at` copy the row out of the mapping without a syscall, which
+    /// speeds up random reads of large buckets. Every pooled reader maps the file on its first
+    /// read and maps it again once it reads past the end of its mapping. The file must not be
+    /// truncated by another process while it's mapped.
+    pub memory_mapped: bool,
+    /// Log every queued write to the bucket's write-ahead log before queueing it
+    ///
+    /// Writes still queued when the process crashes are otherwise lost, the log keeps them on
+    /// disk until the writer thread has written and synced them. Writes left in the log are
+    /// written to the bucket when it's opened again. Every write is written twice and every
+    /// batch of the writer thread is synced, which slows inserts down. The log isn't synced by
+    /// itself, it covers crashes of the process but not of the machine. Synchronous buckets have
+    /// no queue and no log, see `WriteAheadLog`.
+    pub write_ahead_log: bool,
 }
 
 impl BucketConfiguration {
     pub fn new(drive_type: DriveType) -> BucketConfiguration {
-        BucketConfiguration { drive_type }
+        BucketConfiguration {
+            drive_type,
+            ..Default::default()
+        }
     }
 }
 
+impl Default for BucketConfiguration {
+    fn default() -> Self {
+        BucketConfiguration {
+            drive_type: DriveType::SSD,
+            readers: default_readers(num_cpus::get()),
+            queue_capacity: Some(MAX_ITEMS_IN_QUEUE),
+            queue_full_wait: Some(DEFAULT_QUEUE_FULL_WAIT),
+            sync_policy: SyncPolicy::Never,
+            synchronous: false,
+            compress_min_bytes: None,
+            intern_field_names: false,
+            pad_rows: true,
+            overflow_min_bytes: None,
+            max_bucket_bytes: None,
+            encryption_key: None,
+            memory_mapped: false,
+            write_ahead_log: false,
+        }
+    }
+}
+
+/// Kind of drive a bucket is stored on, tunes how the writer thread batches writes
 #[derive(Clone, Copy, Debug, PartialEq, Hash)]
 pub enum DriveType {
+    /// Spinning disk, writes are gathered into few large sequential chunks to avoid seeking
     HDD,
+    /// Solid state drive, writes are flushed in small batches shortly after being queued
     SSD,
 }
+
+impl DriveType {
+    /// How long the writer thread sleeps before draining the queue
+    pub fn writer_sleep(&self) -> Duration {
+        match self {
+            DriveType::HDD => Duration::from_millis(100),
+            DriveType::SSD => Duration::from_millis(1),
+        }
+    }
+
+    /// Fewest writes the writer thread pops from the queue at once
+    ///
+    /// Popped writes are sorted by offset before being chunked, so a larger batch makes for
+    /// longer sequential chunks.
+    pub fn writer_batch(&self) -> usize {
+        match self {
+            DriveType::HDD => 1024,
+            DriveType::SSD => 25,
+        }
+    }
+}
+
+/// When the writer thread syncs written chunks to disk, trading insert throughput for how many
+/// written documents a crash of the machine can lose
+///
+/// A process which crashes loses only the writes still queued, written chunks are in the
+/// operating system's cache. `checkpoint`, `flush` and `close` of a bucket always sync it.
+#[derive(Clone, Copy, Debug, PartialEq, Hash)]
+pub enum SyncPolicy {
+    /// Leave flushing to the operating system, the fastest but anything written since its last
+    /// flush can be lost
+    Never,
+    /// Call `sync_data` after every written chunk, the slowest but only queued writes can be
+    /// lost
+    EachChunk,
+    /// Call `sync_data` once `chunks` chunks were written since the last sync, or `interval`
+    /// after the first chunk written since then, whichever comes first
+    ///
+    /// Bounds the writes a crash can lose to those of the last few chunks at a fraction of the
+    /// cost of syncing every chunk.
+    Batch { chunks: usize, interval: Duration },
+}
+
+/// Amount of readers pooled by default on a machine with `cpus` cores
+pub fn default_readers(cpus: usize) -> usize {
+    cpus.clamp(1, DEFAULT_MAX_READERS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_readers, DEFAULT_MAX_READERS};
+
+    #[test]
+    fn default_readers_are_capped_regardless_of_cores() {
+        assert_eq!(default_readers(0), 1);
+        assert_eq!(default_readers(2), 2);
+        assert_eq!(default_readers(DEFAULT_MAX_READERS), DEFAULT_MAX_READERS);
+        assert_eq!(default_readers(64), DEFAULT_MAX_READERS);
+        assert_eq!(default_readers(256), DEFAULT_MAX_READERS);
+    }
+}
@@ -1,10 +1,227 @@
-use super::document::field::descriptor::FieldDescriptor;
+use std::{collections::BTreeMap, convert::Infallible, ffi::CString};
+
+use crate::utils::pool::Factory;
+
+use super::{
+    document::field::{
+        descriptor::{FieldDescriptor, FieldDescriptorV5, LegacyFieldDescriptor},
+        Field,
+    },
+    row::Compression,
+    ID_FIELD,
+};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BucketDescription {
     pub(crate) field_description: Vec<FieldDescriptor>,
+    pub(crate) unique_constraints: Vec<UniqueConstraint>,
+    /// Arbitrary key/value pairs attached to the bucket, see `Bucket::set_meta`
+    pub(crate) metadata: BTreeMap<String, String>,
+    /// Every name a field of the bucket has had, see `Schema`
+    pub(crate) field_names: Vec<CString>,
+    /// Values of fields added after documents were stored, see `Bucket::add_field`
+    pub(crate) defaults: Vec<Field>,
+    /// Codec compressed rows are stored with
+    pub(crate) compression: Compression,
+}
+
+impl BucketDescription {
+    pub fn new(field_description: Vec<FieldDescriptor>) -> BucketDescription {
+        let field_names = initial_field_names(&field_description);
+        BucketDescription {
+            field_description,
+            unique_constraints: Vec::new(),
+            metadata: BTreeMap::new(),
+            field_names,
+            defaults: Vec::new(),
+            compression: Compression::default(),
+        }
+    }
+
+    /// Stores the bucket's compressed rows with `compression`, see `Compression`
+    ///
+    /// Can only be chosen when the bucket is created, rows are compressed once they exceed
+    /// `BucketConfiguration::compress_min_bytes`.
+    pub fn with_compression(mut self, compression: Compression) -> BucketDescription {
+        self.compression = compression;
+        self
+    }
+
+    pub fn get_compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Requires the combination of `fields` to be unique across all documents in the bucket
+    pub fn with_unique(mut self, name: &str, fields: &[&str]) -> BucketDescription {
+        self.unique_constraints.push(UniqueConstraint {
+            name: name.to_owned(),
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+        });
+        self
+    }
+
+    pub fn get_unique_constraints(&self) -> &Vec<UniqueConstraint> {
+        &self.unique_constraints
+    }
+
+    pub fn get_metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// Names of the fields which aren't described the same way by both descriptions
+    ///
+    /// A field is listed if only one of them holds it or if they give it different types, the
+    /// order the fields are described in doesn't matter.
+    pub fn mismatched_fields(&self, other: &BucketDescription) -> Vec<String> {
+        let mut fields: Vec<String> = Vec::new();
+        let differing = self
+            .field_description
+            .iter()
+            .filter(|f| !other.field_description.contains(f))
+            .chain(
+                other
+                    .field_description
+                    .iter()
+                    .filter(|f| !self.field_description.contains(f)),
+            );
+
+        for f in differing {
+            let name = f.get_name().to_string_lossy().into_owned();
+            if !fields.contains(&name) {
+                fields.push(name);
+            }
+        }
+
+        fields
+    }
+}
+
+/// Names of the fields of a new bucket, followed by the id every stored document holds
+fn initial_field_names(field_description: &[FieldDescriptor]) -> Vec<CString> {
+    field_description
+        .iter()
+        .map(|f| f.get_name().to_owned())
+        .chain(std::iter::once(CString::new(ID_FIELD).unwrap()))
+        .collect()
+}
+
+/// Descriptor as stored by format versions before 3, without field names and defaults
+#[derive(Deserialize)]
+pub(crate) struct LegacyBucketDescription {
+    field_description: Vec<LegacyFieldDescriptor>,
+    unique_constraints: Vec<UniqueConstraint>,
+    metadata: BTreeMap<String, String>,
+}
+
+impl From<LegacyBucketDescription> for BucketDescription {
+    fn from(legacy: LegacyBucketDescription) -> Self {
+        let field_description = upgrade_fields(legacy.field_description);
+        BucketDescription {
+            field_names: initial_field_names(&field_description),
+            field_description,
+            unique_constraints: legacy.unique_constraints,
+            metadata: legacy.metadata,
+            defaults: Vec::new(),
+            compression: Compression::Snappy,
+        }
+    }
+}
+
+/// Descriptor as stored by format version 3, without the compression codec
+#[derive(Deserialize)]
+pub(crate) struct BucketDescriptionV3 {
+    field_description: Vec<LegacyFieldDescriptor>,
+    unique_constraints: Vec<UniqueConstraint>,
+    metadata: BTreeMap<String, String>,
+    field_names: Vec<CString>,
+    defaults: Vec<Field>,
 }
 
+impl From<BucketDescriptionV3> for BucketDescription {
+    fn from(v3: BucketDescriptionV3) -> Self {
+        BucketDescription {
+            field_description: upgrade_fields(v3.field_description),
+            unique_constraints: v3.unique_constraints,
+            metadata: v3.metadata,
+            field_names: v3.field_names,
+            defaults: v3.defaults,
+            compression: Compression::Snappy,
+        }
+    }
+}
+
+/// Descriptor as stored by format version 4, its fields have no maximum length
+#[derive(Deserialize)]
+pub(crate) struct BucketDescriptionV4 {
+    field_description: Vec<LegacyFieldDescriptor>,
+    unique_constraints: Vec<UniqueConstraint>,
+    metadata: BTreeMap<String, String>,
+    field_names: Vec<CString>,
+    defaults: Vec<Field>,
+    compression: Compression,
+}
+
+impl From<BucketDescriptionV4> for BucketDescription {
+    fn from(v4: BucketDescriptionV4) -> Self {
+        BucketDescription {
+            field_description: upgrade_fields(v4.field_description),
+            unique_constraints: v4.unique_constraints,
+            metadata: v4.metadata,
+            field_names: v4.field_names,
+            defaults: v4.defaults,
+            compression: v4.compression,
+        }
+    }
+}
+
+/// Descriptor as stored by format version 5, its fields can't be unique
+#[derive(Deserialize)]
+pub(crate) struct BucketDescriptionV5 {
+    field_description: Vec<FieldDescriptorV5>,
+    unique_constraints: Vec<UniqueConstraint>,
+    metadata: BTreeMap<String, String>,
+    field_names: Vec<CString>,
+    defaults: Vec<Field>,
+    compression: Compression,
+}
+
+impl From<BucketDescriptionV5> for BucketDescription {
+    fn from(v5: BucketDescriptionV5) -> Self {
+        BucketDescription {
+            field_description: upgrade_fields(v5.field_description),
+            unique_constraints: v5.unique_constraints,
+            metadata: v5.metadata,
+            field_names: v5.field_names,
+            defaults: v5.defaults,
+            compression: v5.compression,
+        }
+    }
+}
+
+fn upgrade_fields<T: Into<FieldDescriptor>>(fields: Vec<T>) -> Vec<FieldDescriptor> {
+    fields.into_iter().map(T::into).collect()
+}
+
+impl Factory<BucketDescription> for BucketDescription {
+    type Error = Infallible;
+
+    fn create(&self) -> Result<BucketDescription, Infallible> {
+        Ok(self.clone())
+    }
+}
+
+/// A named set of fields whose combined values must be unique within a bucket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UniqueConstraint {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+/// Implemented by types which describe the bucket they're stored in, see
+/// `Database::open_bucket_for`
+///
+/// Usually implemented next to `DocumentConvert`, the described fields should match the fields
+/// of the documents the type converts to.
 pub trait BucketDesriptor {
-    fn get_description(&self) -> BucketDescription;
-}
\ No newline at end of file
+    fn get_description() -> BucketDescription;
+}
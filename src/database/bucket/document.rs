@@ -1,7 +1,17 @@
+pub mod csv;
 pub mod field;
+pub mod json;
+pub mod patch;
+pub mod raw;
+pub mod structs;
 use std::ffi::{CStr, CString};
 
-use field::Field;
+use bincode::Options;
+
+use field::{fieldtype::FieldType, Field};
+use patch::DocumentPatch;
+
+use crate::database::error::NonaneError;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Document {
@@ -13,8 +23,9 @@ impl Document {
         Self { fields }
     }
 
+    /// Finds a field by name, `None` if there's none or the name holds a nul byte
     pub fn read_field(&self, key: &str) -> Option<&Field> {
-        let key = CString::new(key).expect("Failed to parse to CStr for read_field within document");
+        let key = CString::new(key).ok()?;
 
         for f in self.fields.iter() {
             if f.get_key() == key.as_c_str() {
@@ -25,17 +36,288 @@ impl Document {
         None
     }
 
+    /// Finds a field by name, if it holds a value of `field_type`
+    fn read_typed_field(&self, key: &str, field_type: FieldType) -> Option<&Field> {
+        self.read_field(key).filter(|f| *f.get_type() == field_type)
+    }
+
+    /// Reads a uuid field, `None` if it's missing or of another type
+    pub fn get_uuid(&self, key: &str) -> Option<uuid::Uuid> {
+        self.read_typed_field(key, FieldType::Uuid)
+            .and_then(|f| f.get_value::<uuid::Uuid>())
+    }
+
+    /// Reads a bytes field, `None` if it's missing or of another type
+    pub fn get_bytes(&self, key: &str) -> Option<&[u8]> {
+        self.read_typed_field(key, FieldType::Bytes)
+            .and_then(|f| f.get_value::<&[u8]>())
+    }
+
+    /// Reads a text field, `None` if it's missing or of another type
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.read_typed_field(key, FieldType::Text)
+            .and_then(|f| f.get_value::<&str>())
+    }
+
+    /// Reads a `i8` field, `None` if it's missing or of another type
+    pub fn get_i8(&self, key: &str) -> Option<i8> {
+        self.read_typed_field(key, FieldType::Int8)
+            .and_then(|f| f.get_value::<i8>())
+    }
+
+    /// Reads a `i16` field, `None` if it's missing or of another type
+    pub fn get_i16(&self, key: &str) -> Option<i16> {
+        self.read_typed_field(key, FieldType::Int16)
+            .and_then(|f| f.get_value::<i16>())
+    }
+
+    /// Reads a `i32` field, `None` if it's missing or of another type
+    pub fn get_i32(&self, key: &str) -> Option<i32> {
+        self.read_typed_field(key, FieldType::Int32)
+            .and_then(|f| f.get_value::<i32>())
+    }
+
+    /// Reads a `i64` field, `None` if it's missing or of another type
+    pub fn get_i64(&self, key: &str) -> Option<i64> {
+        self.read_typed_field(key, FieldType::Int64)
+            .and_then(|f| f.get_value::<i64>())
+    }
+
+    /// Reads a `u8` field, `None` if it's missing or of another type
+    pub fn get_u8(&self, key: &str) -> Option<u8> {
+        self.read_typed_field(key, FieldType::UInt8)
+            .and_then(|f| f.get_value::<u8>())
+    }
+
+    /// Reads a `u16` field, `None` if it's missing or of another type
+    pub fn get_u16(&self, key: &str) -> Option<u16> {
+        self.read_typed_field(key, FieldType::UInt16)
+            .and_then(|f| f.get_value::<u16>())
+    }
+
+    /// Reads a `u32` field, `None` if it's missing or of another type
+    pub fn get_u32(&self, key: &str) -> Option<u32> {
+        self.read_typed_field(key, FieldType::UInt32)
+            .and_then(|f| f.get_value::<u32>())
+    }
+
+    /// Reads a `u64` field, `None` if it's missing or of another type
+    pub fn get_u64(&self, key: &str) -> Option<u64> {
+        self.read_typed_field(key, FieldType::UInt64)
+            .and_then(|f| f.get_value::<u64>())
+    }
+
+    /// Reads a `f32` field, `None` if it's missing or of another type
+    pub fn get_f32(&self, key: &str) -> Option<f32> {
+        self.read_typed_field(key, FieldType::Float32)
+            .and_then(|f| f.get_value::<f32>())
+    }
+
+    /// Reads a `f64` field, `None` if it's missing or of another type
+    pub fn get_f64(&self, key: &str) -> Option<f64> {
+        self.read_typed_field(key, FieldType::Float64)
+            .and_then(|f| f.get_value::<f64>())
+    }
+
+    /// Reads a `char` field, `None` if it's missing or of another type
+    pub fn get_char(&self, key: &str) -> Option<char> {
+        self.read_typed_field(key, FieldType::Char)
+            .and_then(|f| f.get_value::<char>())
+    }
+
+    /// Reads a `bool` field, `None` if it's missing or of another type
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.read_typed_field(key, FieldType::Bool)
+            .and_then(|f| f.get_value::<bool>())
+    }
+
+    /// Reads a timestamp field, `None` if it's missing or of another type
+    pub fn get_datetime(&self, key: &str) -> Option<field::fieldtype::DateTime> {
+        self.read_typed_field(key, FieldType::DateTime)
+            .and_then(|f| f.get_value::<field::fieldtype::DateTime>())
+    }
+
+    /// Reads a JSON field, `None` if it's missing, of another type or doesn't parse
+    pub fn get_json(&self, key: &str) -> Option<serde_json::Value> {
+        self.read_typed_field(key, FieldType::Json)
+            .and_then(|f| f.get_value::<serde_json::Value>())
+    }
+
     pub fn get_fields(&self) -> &Vec<Field> {
         &self.fields
     }
 
-    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    pub(crate) fn get_fields_mut(&mut self) -> &mut Vec<Field> {
+        &mut self.fields
+    }
+
+    /// Removes the field named `key`, returning it
+    pub fn remove_field(&mut self, key: &str) -> Option<Field> {
+        let position = self
+            .fields
+            .iter()
+            .position(|f| f.get_key().to_bytes() == key.as_bytes())?;
+
+        Some(self.fields.remove(position))
+    }
+
+    /// Replaces the field with the same name as `field` in place, or appends it
+    pub fn set_field(&mut self, field: Field) {
+        match self.fields.iter_mut().find(|f| f.get_key() == field.get_key()) {
+            Some(f) => *f = field,
+            None => self.fields.push(field),
+        }
+    }
+
+    /// Lists the changes required to turn this document into `other`
+    pub fn diff(&self, other: &Document) -> DocumentPatch {
+        let mut patch = DocumentPatch::default();
+
+        for f in other.fields.iter() {
+            match self.fields.iter().find(|s| s.get_key() == f.get_key()) {
+                Some(s) if s == f => {}
+                Some(_) => patch.changed.push(f.clone()),
+                None => patch.added.push(f.clone()),
+            }
+        }
+
+        for s in self.fields.iter() {
+            if !other.fields.iter().any(|f| f.get_key() == s.get_key()) {
+                patch.removed.push(s.get_key().to_owned());
+            }
+        }
+
+        patch
+    }
+
+    /// Applies the changes of a patch created with `diff`
+    ///
+    /// Changed and added fields replace any field with the same name, or are appended.
+    pub fn apply(&mut self, patch: &DocumentPatch) {
+        self.fields
+            .retain(|f| !patch.removed.iter().any(|r| r.as_c_str() == f.get_key()));
+
+        for f in patch.changed.iter().chain(patch.added.iter()) {
+            match self.fields.iter_mut().find(|s| s.get_key() == f.get_key()) {
+                Some(s) => *s = f.clone(),
+                None => self.fields.push(f.clone()),
+            }
+        }
+    }
+
+    /// Combines this document with the fields of `updates`
+    ///
+    /// A field present in both documents takes its value from `updates` and keeps its position,
+    /// fields only present in `updates` are appended after the fields of this document.
+    pub fn merge(&self, updates: &Document) -> Document {
+        let mut merged = self.clone();
+        for f in updates.fields.iter() {
+            match merged.fields.iter_mut().find(|s| s.get_key() == f.get_key()) {
+                Some(s) => *s = f.clone(),
+                None => merged.fields.push(f.clone()),
+            }
+        }
+
+        merged
+    }
+
+    /// Sorts the fields by name, so documents holding the same fields are stored identically
+    pub fn canonicalize(&mut self) {
+        self.fields.sort_by(|a, b| a.get_key().cmp(b.get_key()));
+    }
+
+    /// Converts a struct deriving `Serialize` to a document, as an alternative to
+    /// `DocumentConvert`
+    ///
+    /// Members become fields of the matching type, see `structs` for how they're mapped.
+    pub fn from_serialize<T: serde::Serialize>(value: &T) -> Result<Document, NonaneError> {
+        structs::to_document(value)
+    }
+
+    /// Converts the document to a struct deriving `Deserialize`, the inverse of `from_serialize`
+    pub fn to_deserialize<T: serde::de::DeserializeOwned>(&self) -> Result<T, NonaneError> {
+        structs::from_document(self)
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>, NonaneError> {
         Ok(bincode::serialize(&self)?)
     }
 
-    pub fn deserialize(bytes: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+    /// Serializes the document as if it had been canonicalized, without reordering its fields
+    pub fn serialize_canonical(&self) -> Result<Vec<u8>, NonaneError> {
+        let mut fields: Vec<&Field> = self.fields.iter().collect();
+        fields.sort_by(|a, b| a.get_key().cmp(b.get_key()));
+
+        // A document is serialized exactly like its list of fields
+        Ok(bincode::serialize(&fields)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, NonaneError> {
         Ok(bincode::deserialize(bytes)?)
     }
+
+    /// Deserializes a document from the front of `bytes`, advancing it past the document
+    ///
+    /// Reading is bounded by the length of `bytes`, so a corrupt length can't allocate more than
+    /// is actually stored.
+    pub(crate) fn deserialize_from(bytes: &mut &[u8]) -> Result<Self, NonaneError> {
+        Ok(bounded_options(bytes.len()).deserialize_from(bytes)?)
+    }
+
+    /// Serializes the document storing every field name as its position within `names`
+    ///
+    /// Returns `None` if the document holds a field which isn't part of `names`.
+    pub(crate) fn serialize_interned(
+        &self,
+        names: &[CString],
+    ) -> Result<Option<Vec<u8>>, NonaneError> {
+        let mut fields = Vec::with_capacity(self.fields.len());
+        for f in self.fields.iter() {
+            let index = match names.iter().position(|n| n.as_c_str() == f.get_key()) {
+                Some(i) => i as u32,
+                None => return Ok(None),
+            };
+
+            fields.push((index, f.get_type(), f.get_data()));
+        }
+
+        Ok(Some(bincode::serialize(&fields)?))
+    }
+
+    /// Deserializes a document serialized by `serialize_interned` with the same `names`
+    ///
+    /// The document is read from the front of `bytes`, which is advanced past it.
+    pub(crate) fn deserialize_interned(
+        bytes: &mut &[u8],
+        names: &[CString],
+    ) -> Result<Document, NonaneError> {
+        let fields: Vec<(u32, FieldType, Vec<u8>)> =
+            bounded_options(bytes.len()).deserialize_from(bytes)?;
+
+        let fields = fields
+            .into_iter()
+            .map(|(index, field_type, data)| {
+                let name = names.get(index as usize).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "field index is not part of the bucket's fields",
+                    )
+                })?;
+
+                Ok(Field::from_raw(name.clone(), field_type, data))
+            })
+            .collect::<Result<Vec<Field>, NonaneError>>()?;
+
+        Ok(Document { fields })
+    }
+}
+
+/// Options matching `bincode::deserialize`, limited to reading `limit` bytes
+fn bounded_options(limit: usize) -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit as u64)
 }
 
 pub trait DocumentConvert {
@@ -44,3 +326,96 @@ pub trait DocumentConvert {
     fn convert_to(self) -> Option<Document>;
     fn convert_from(doc: &Document) -> Option<Self::ConvertFrom>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{field::Field, Document};
+
+    #[test]
+    fn canonical_serialization_ignores_field_order() {
+        let mut first = Document::new(vec![
+            Field::new("b", 2i32).unwrap(),
+            Field::new("a", "one".to_string()).unwrap(),
+            Field::new("c", true).unwrap(),
+        ]);
+        let mut second = Document::new(vec![
+            Field::new("c", true).unwrap(),
+            Field::new("b", 2i32).unwrap(),
+            Field::new("a", "one".to_string()).unwrap(),
+        ]);
+        assert_ne!(first.serialize().unwrap(), second.serialize().unwrap());
+        assert_eq!(first.serialize_canonical().unwrap(), second.serialize_canonical().unwrap());
+
+        first.canonicalize();
+        second.canonicalize();
+        assert_eq!(first.serialize().unwrap(), second.serialize().unwrap());
+        assert_eq!(first.serialize().unwrap(), first.serialize_canonical().unwrap());
+        assert_eq!(first.get_str("a"), Some("one"));
+        assert_eq!(first.get_i32("b"), Some(2));
+    }
+
+    #[test]
+    fn merge_lets_updates_win_and_keeps_every_field() {
+        let base = Document::new(vec![
+            Field::new("name", "Ada".to_string()).unwrap(),
+            Field::new("age", 35i32).unwrap(),
+        ]);
+        let updates = Document::new(vec![
+            Field::new("age", 36i32).unwrap(),
+            Field::new("city", "London".to_string()).unwrap(),
+        ]);
+
+        let merged = base.merge(&updates);
+        assert_eq!(merged.fields.len(), 3);
+        assert_eq!(merged.get_str("name"), Some("Ada"));
+        assert_eq!(merged.get_i32("age"), Some(36));
+        assert_eq!(merged.get_str("city"), Some("London"));
+        assert_eq!(base.get_i32("age"), Some(35));
+    }
+
+    #[test]
+    fn typed_accessors_decode_matching_fields_only() {
+        let id = uuid::Uuid::new_v4();
+        let document = Document::new(vec![
+            Field::new("id", id).unwrap(),
+            Field::new("name", "Ada".to_string()).unwrap(),
+            Field::new("data", &[1u8, 2, 3][..]).unwrap(),
+            Field::new("age", 36i32).unwrap(),
+            Field::new("score", 0.5f64).unwrap(),
+            Field::new("admin", true).unwrap(),
+        ]);
+
+        assert_eq!(document.get_uuid("id"), Some(id));
+        assert_eq!(document.get_str("name"), Some("Ada"));
+        assert_eq!(document.get_bytes("data"), Some(&[1u8, 2, 3][..]));
+        assert_eq!(document.get_i32("age"), Some(36));
+        assert_eq!(document.get_f64("score"), Some(0.5));
+        assert_eq!(document.get_bool("admin"), Some(true));
+
+        assert_eq!(document.get_i64("age"), None);
+        assert_eq!(document.get_bytes("name"), None);
+        assert_eq!(document.get_str("data"), None);
+        assert_eq!(document.get_f32("score"), None);
+        assert_eq!(document.get_i32("missing"), None);
+    }
+
+    #[test]
+    fn removing_and_setting_fields_keeps_names_unique() {
+        let mut document = Document::new(vec![
+            Field::new("name", "Ada".to_string()).unwrap(),
+            Field::new("age", 36i32).unwrap(),
+        ]);
+
+        let removed = document.remove_field("age").unwrap();
+        assert_eq!(removed.get_value::<i32>(), Some(36));
+        assert!(document.read_field("age").is_none());
+        assert!(document.remove_field("age").is_none());
+
+        document.set_field(Field::new("city", "London".to_string()).unwrap());
+        assert_eq!(document.get_str("city"), Some("London"));
+
+        document.set_field(Field::new("name", "Grace".to_string()).unwrap());
+        assert_eq!(document.get_str("name"), Some("Grace"));
+        assert_eq!(document.fields.len(), 2);
+    }
+}
@@ -0,0 +1,163 @@
+//! Conversion of documents to CSV records, formatting every field according to its type
+
+use std::{ffi::CStr, io::Write};
+
+use super::{
+    field::{
+        fieldtype::{DateTime, FieldType},
+        Field,
+    },
+    Document,
+};
+
+/// Writes a header record holding the names of `columns`
+///
+/// Names are only quoted if they hold a separator, a quote or a line break.
+pub fn write_header<W: Write>(writer: &mut W, columns: &[&CStr]) -> std::io::Result<()> {
+    let cells: Vec<String> = columns
+        .iter()
+        .map(|c| {
+            let name = c.to_string_lossy();
+            if name.contains([',', '"', '\r', '\n']) {
+                quote(&name)
+            } else {
+                name.into_owned()
+            }
+        })
+        .collect();
+
+    write_record(writer, &cells)
+}
+
+/// Writes a record holding the fields of `document` named by `columns`, in their order
+///
+/// Fields the document doesn't hold are written as empty cells.
+pub fn write_document<W: Write>(
+    writer: &mut W,
+    document: &Document,
+    columns: &[&CStr],
+) -> std::io::Result<()> {
+    let cells: Vec<String> = columns
+        .iter()
+        .map(|c| {
+            document
+                .get_fields()
+                .iter()
+                .find(|f| f.get_key() == *c)
+                .map_or_else(String::new, field_to_csv)
+        })
+        .collect();
+
+    write_record(writer, &cells)
+}
+
+/// Formats a field as a CSV cell
+///
+/// Text, chars and JSON are quoted, numbers and booleans are written bare. Bytes are base64
+/// encoded, uuids are written hyphenated and timestamps as epoch milliseconds, like
+/// `json::field_to_json`. Malformed data is written as an empty cell.
+pub fn field_to_csv(field: &Field) -> String {
+    let cell = match field.get_type() {
+        FieldType::Uuid => field
+            .get_value::<uuid::Uuid>()
+            .map(|u| u.to_hyphenated().to_string()),
+        FieldType::Bytes | FieldType::Overflow => Some(base64::encode(field.get_data())),
+        FieldType::Text => field.get_value::<String>().map(|s| quote(&s)),
+        FieldType::Int8 => field.get_value::<i8>().map(|n| n.to_string()),
+        FieldType::Int16 => field.get_value::<i16>().map(|n| n.to_string()),
+        FieldType::Int32 => field.get_value::<i32>().map(|n| n.to_string()),
+        FieldType::Int64 => field.get_value::<i64>().map(|n| n.to_string()),
+        FieldType::UInt8 => field.get_value::<u8>().map(|n| n.to_string()),
+        FieldType::UInt16 => field.get_value::<u16>().map(|n| n.to_string()),
+        FieldType::UInt32 => field.get_value::<u32>().map(|n| n.to_string()),
+        FieldType::UInt64 => field.get_value::<u64>().map(|n| n.to_string()),
+        FieldType::Float32 => field.get_value::<f32>().map(|n| n.to_string()),
+        FieldType::Float64 => field.get_value::<f64>().map(|n| n.to_string()),
+        FieldType::Char => field.get_value::<char>().map(|c| quote(&c.to_string())),
+        FieldType::Bool => field.get_value::<bool>().map(|b| b.to_string()),
+        FieldType::DateTime => field.get_value::<DateTime>().map(|t| t.millis().to_string()),
+        FieldType::Json => field
+            .get_value::<serde_json::Value>()
+            .map(|v| quote(&v.to_string())),
+    };
+
+    cell.unwrap_or_default()
+}
+
+/// Wraps `value` in quotes, doubling the quotes within it
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+fn write_record<W: Write>(writer: &mut W, cells: &[String]) -> std::io::Result<()> {
+    writer.write_all(cells.join(",").as_bytes())?;
+    writer.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+
+    use crate::{
+        database::{
+            bucket::{
+                descriptor::BucketDescription,
+                document::{
+                    field::{descriptor::FieldDescriptor, fieldtype::FieldType, Field},
+                    Document,
+                },
+            },
+            Database,
+        },
+        utils::testing::temp_path,
+    };
+
+    #[test]
+    fn export_follows_the_described_column_order() {
+        let description = BucketDescription::new(vec![
+            FieldDescriptor::new("name", FieldType::Text).unwrap(),
+            FieldDescriptor::new("n", FieldType::Int32).unwrap(),
+            FieldDescriptor::new("data", FieldType::Bytes).unwrap(),
+        ]);
+        let mut db = Database::open(temp_path("csv-export")).unwrap();
+        db.open_bucket("things", Some(description), None).unwrap();
+        let mut bucket = db.get_bucket_mut("things").unwrap();
+        bucket
+            .insert(&Document::new(vec![
+                Field::new("name", "plain".to_string()).unwrap(),
+                Field::new("n", 1).unwrap(),
+                Field::new("data", &b"hi"[..]).unwrap(),
+            ]))
+            .unwrap();
+        bucket
+            .insert(&Document::new(vec![
+                Field::new("data", &[0xffu8][..]).unwrap(),
+                Field::new("n", -2).unwrap(),
+                Field::new("name", "say \"hi\", then".to_string()).unwrap(),
+            ]))
+            .unwrap();
+        bucket.flush().unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(bucket.export_csv(&mut out).unwrap(), 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "name,n,data\n\"plain\",1,aGk=\n\"say \"\"hi\"\", then\",-2,/w==\n"
+        );
+    }
+
+    #[test]
+    fn missing_fields_are_left_empty() {
+        let names = [&b"name\0"[..], b"n\0", b"data\0"];
+        let columns: Vec<&CStr> = names
+            .iter()
+            .map(|n| CStr::from_bytes_with_nul(n).unwrap())
+            .collect();
+        let document = Document::new(vec![Field::new("n", 7).unwrap()]);
+
+        let mut out = Vec::new();
+        super::write_header(&mut out, &columns).unwrap();
+        super::write_document(&mut out, &document, &columns).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "name,n,data\n,7,\n");
+    }
+}
@@ -13,26 +13,29 @@ pub struct Field {
 }
 
 impl<'a> Field {
+    /// Creates a field holding `data`, `None` if the value can't be serialized or the name holds
+    /// a nul byte
     pub fn new<T: ConvertFieldType<'a, T>>(name: &'a str, data: T) -> Option<Field> {
         let field_type = data.get_type();
-        let data = data.serialize();
-
-        match data {
-            Some(data) => Some(Self {
-                name: CString::new(name).expect("Failed to parse Field name to CString, bytes incorrect"),
-                field_type,
-                data,
-            }),
-            None => None,
-        }
+        let data = data.serialize()?;
+
+        Some(Self {
+            name: CString::new(name).ok()?,
+            field_type,
+            data,
+        })
     }
 
+    /// Creates a bytes field taking the contents of `bytes`, `None` if the name holds a nul byte
+    ///
+    /// `bytes` is left untouched when the name is rejected.
     pub fn new_bytes(name: &'a str, mut bytes: &mut Vec<u8>) -> Option<Field> {
+        let name = CString::new(name).ok()?;
         let mut data = Vec::new();
         std::mem::swap(&mut data, &mut bytes);
 
         Some(Self {
-            name: CString::new(name).expect("Failed to parse Field name to CString, bytes incorrect"),
+            name,
             field_type: FieldType::Bytes,
             data,
         })
@@ -42,13 +45,114 @@ impl<'a> Field {
         self.name.as_c_str()
     }
 
+    /// Decodes the value of the field, `None` if the field holds another type than `T`
     pub fn get_value<T: ConvertFieldType<'a, T>>(
         &'a self,
     ) -> Option<<T as ConvertFieldType<'a, T>>::Output> {
+        if self.field_type != T::FIELD_TYPE {
+            return None;
+        }
+
         T::deserialize(&self.data)
     }
 
     pub fn get_type(&self) -> &FieldType {
         &self.field_type
     }
+
+    /// Raw serialized value of the field
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Compares the type and value of two fields, ignoring their names
+    ///
+    /// Fields of different types are never equal, even when their bytes are. Floats are compared
+    /// by value, so `0.0` equals `-0.0` and `NaN` equals nothing.
+    pub fn value_eq(&self, other: &Field) -> bool {
+        if self.field_type != other.field_type {
+            return false;
+        }
+
+        match self.field_type {
+            FieldType::Float32 => f32::deserialize(&self.data) == f32::deserialize(&other.data),
+            FieldType::Float64 => f64::deserialize(&self.data) == f64::deserialize(&other.data),
+            _ => self.data == other.data,
+        }
+    }
+
+    pub(crate) fn from_raw(name: CString, field_type: FieldType, data: Vec<u8>) -> Field {
+        Self {
+            name,
+            field_type,
+            data,
+        }
+    }
+
+    /// Replaces the type and data of the field, returning the old data
+    pub(crate) fn replace_data(&mut self, field_type: FieldType, data: Vec<u8>) -> Vec<u8> {
+        self.field_type = field_type;
+        std::mem::replace(&mut self.data, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::error::NonaneError;
+
+    use super::{descriptor::FieldDescriptor, fieldtype::FieldType, Field};
+
+    #[test]
+    fn value_eq_requires_matching_types() {
+        let text = Field::new("a", "abc".to_string()).unwrap();
+        let bytes = Field::new("b", &b"abc"[..]).unwrap();
+        assert_eq!(text.get_data(), bytes.get_data());
+        assert!(!text.value_eq(&bytes));
+        assert!(!bytes.value_eq(&text));
+
+        assert!(text.value_eq(&Field::new("b", "abc".to_string()).unwrap()));
+        assert!(!text.value_eq(&Field::new("a", "abd".to_string()).unwrap()));
+    }
+
+    #[test]
+    fn value_eq_compares_floats_by_value() {
+        let zero = Field::new("x", 0.0f64).unwrap();
+        let negative_zero = Field::new("x", -0.0f64).unwrap();
+        assert_ne!(zero.get_data(), negative_zero.get_data());
+        assert!(zero.value_eq(&negative_zero));
+
+        let nan = Field::new("x", f64::NAN).unwrap();
+        assert!(!nan.value_eq(&nan));
+    }
+
+    #[test]
+    fn get_value_of_another_type_is_none() {
+        let text = Field::new("text", "12345678".to_string()).unwrap();
+        assert_eq!(text.get_data().len(), 8);
+        assert_eq!(text.get_value::<i64>(), None);
+        assert_eq!(text.get_value::<f64>(), None);
+        assert_eq!(text.get_value::<&[u8]>(), None);
+        assert_eq!(text.get_value::<&str>(), Some("12345678"));
+
+        let number = Field::new("n", 1i32).unwrap();
+        assert_eq!(number.get_value::<u32>(), None);
+        assert_eq!(number.get_value::<f32>(), None);
+        assert_eq!(number.get_value::<i32>(), Some(1));
+    }
+
+    #[test]
+    fn names_holding_a_nul_byte_are_refused() {
+        assert!(Field::new("bad\0name", 1i32).is_none());
+        assert!(matches!(
+            FieldDescriptor::new("bad\0name", FieldType::Int32),
+            Err(NonaneError::InvalidFieldName { name }) if name == "bad\0name"
+        ));
+
+        let mut bytes = vec![1, 2, 3];
+        assert!(Field::new_bytes("bad\0name", &mut bytes).is_none());
+        assert_eq!(bytes, [1, 2, 3]);
+
+        assert!(Field::new("good_name", 1i32).is_some());
+        assert!(FieldDescriptor::new("good_name", FieldType::Int32).is_ok());
+    }
 }
@@ -1,19 +1,68 @@
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 
 use super::{Field, fieldtype::FieldType};
+use crate::database::error::NonaneError;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FieldDescriptor {
     name: CString,
     field_type: FieldType,
+    /// Most bytes a value of the field may take up, see `with_max_len`
+    max_len: Option<usize>,
+    /// No two documents may hold the same value for the field, see `unique`
+    unique: bool,
 }
 
 impl FieldDescriptor {
-    pub fn new(name: &str, field_type: FieldType) -> FieldDescriptor {
-        FieldDescriptor {
-            name: CString::new(name).expect("Couldn't parse name, bytes incorrect for FieldDescriptor"),
-            field_type
-        }
+    /// Describes a field named `name`, a name holding a nul byte errors with
+    /// `NonaneError::InvalidFieldName`
+    pub fn new(name: &str, field_type: FieldType) -> Result<FieldDescriptor, NonaneError> {
+        let name = CString::new(name).map_err(|_| NonaneError::InvalidFieldName {
+            name: name.to_owned(),
+        })?;
+
+        Ok(FieldDescriptor {
+            name,
+            field_type,
+            max_len: None,
+            unique: false,
+        })
+    }
+
+    /// Limits values of the field to `max_len` bytes, inserting a longer value errors with
+    /// `NonaneError::FieldTooLong`
+    ///
+    /// Meant for `Text` and `Bytes` fields, the length of a text is counted in bytes.
+    pub fn with_max_len(mut self, max_len: usize) -> FieldDescriptor {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// Requires every document in the bucket to hold a different value for the field, inserting
+    /// a value another document holds errors with `NonaneError::DuplicateKey`
+    ///
+    /// The values are kept in the bucket's unique index, see `UniqueIndex`. A document is checked
+    /// and added to the index before its write is queued, so concurrent inserts of the same value
+    /// can't both pass even though the rows are written later.
+    pub fn unique(mut self) -> FieldDescriptor {
+        self.unique = true;
+        self
+    }
+
+    pub fn get_name(&self) -> &CStr {
+        self.name.as_c_str()
+    }
+
+    pub fn get_field_type(&self) -> FieldType {
+        self.field_type
+    }
+
+    pub fn get_max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.unique
     }
 
     pub fn is_match(&self, field: &Field) -> bool {
@@ -23,4 +72,41 @@ impl FieldDescriptor {
             false
         }
     }
+}
+
+/// Field descriptor as stored by format versions before 5, without a maximum length
+#[derive(Deserialize)]
+pub(crate) struct LegacyFieldDescriptor {
+    name: CString,
+    field_type: FieldType,
+}
+
+impl From<LegacyFieldDescriptor> for FieldDescriptor {
+    fn from(legacy: LegacyFieldDescriptor) -> Self {
+        FieldDescriptor {
+            name: legacy.name,
+            field_type: legacy.field_type,
+            max_len: None,
+            unique: false,
+        }
+    }
+}
+
+/// Field descriptor as stored by format version 5, without the unique flag
+#[derive(Deserialize)]
+pub(crate) struct FieldDescriptorV5 {
+    name: CString,
+    field_type: FieldType,
+    max_len: Option<usize>,
+}
+
+impl From<FieldDescriptorV5> for FieldDescriptor {
+    fn from(v5: FieldDescriptorV5) -> Self {
+        FieldDescriptor {
+            name: v5.name,
+            field_type: v5.field_type,
+            max_len: v5.max_len,
+            unique: false,
+        }
+    }
 }
\ No newline at end of file
@@ -16,17 +16,60 @@ pub enum FieldType {
     UInt64 = 0xA,
     Float32 = 0xB,
     Float64 = 0xC,
+    Char = 0xD,
+    /// Bytes which have been moved to the bucket's overflow file, stored as an offset and length
+    Overflow = 0xE,
+    /// Stored as a single byte, 0 or 1
+    ///
+    /// New variants go last, stored descriptors refer to variants by their position.
+    Bool = 0xF,
+    /// Milliseconds since the unix epoch, stored like an `Int64`
+    DateTime = 0x10,
+    /// A JSON value stored as its UTF-8 text, unlike `Text` it's known to parse as JSON
+    Json = 0x11,
+}
+
+/// Point in time as milliseconds since the unix epoch, stored as a `FieldType::DateTime` field
+///
+/// Stored exactly like an `i64`, the distinct field type keeps a timestamp field from matching a
+/// descriptor of a plain `Int64` field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime(i64);
+
+impl DateTime {
+    pub fn from_millis(millis: i64) -> DateTime {
+        DateTime(millis)
+    }
+
+    /// The current time, times before the epoch are negative
+    pub fn now() -> DateTime {
+        let millis = match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+            Ok(d) => d.as_millis() as i64,
+            Err(e) => -(e.duration().as_millis() as i64),
+        };
+
+        DateTime(millis)
+    }
+
+    pub fn millis(&self) -> i64 {
+        self.0
+    }
 }
 
 /// Implemented on data types to convert them to bytes
 pub trait ConvertFieldType<'a, T> {
     type Output;
 
+    /// Type of the fields holding values of this type
+    const FIELD_TYPE: FieldType;
+
     fn get_size(&self) -> usize {
         std::mem::size_of_val(self)
     }
 
-    fn get_type(&self) -> FieldType;
+    fn get_type(&self) -> FieldType {
+        Self::FIELD_TYPE
+    }
     fn serialize(&self) -> Option<Vec<u8>>;
     fn deserialize(d: &'a Vec<u8>) -> Option<Self::Output>;
 }
@@ -34,9 +77,7 @@ pub trait ConvertFieldType<'a, T> {
 impl<'a> ConvertFieldType<'a, uuid::Uuid> for uuid::Uuid {
     type Output = uuid::Uuid;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::Uuid
-    }
+    const FIELD_TYPE: FieldType = FieldType::Uuid;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         Some(self.as_bytes().to_vec())
@@ -53,9 +94,7 @@ impl<'a> ConvertFieldType<'a, uuid::Uuid> for uuid::Uuid {
 impl<'a> ConvertFieldType<'a, &'a [u8]> for &[u8] {
     type Output = &'a [u8];
 
-    fn get_type(&self) -> FieldType {
-        FieldType::Bytes
-    }
+    const FIELD_TYPE: FieldType = FieldType::Bytes;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         Some(self.to_vec())
@@ -69,9 +108,7 @@ impl<'a> ConvertFieldType<'a, &'a [u8]> for &[u8] {
 impl<'a> ConvertFieldType<'a, &Vec<u8>> for &Vec<u8> {
     type Output = &'a [u8];
 
-    fn get_type(&self) -> FieldType {
-        FieldType::Bytes
-    }
+    const FIELD_TYPE: FieldType = FieldType::Bytes;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         Some(self.to_vec())
@@ -85,9 +122,7 @@ impl<'a> ConvertFieldType<'a, &Vec<u8>> for &Vec<u8> {
 impl<'a> ConvertFieldType<'a, Vec<u8>> for Vec<u8> {
     type Output = &'a [u8];
 
-    fn get_type(&self) -> FieldType {
-        FieldType::Bytes
-    }
+    const FIELD_TYPE: FieldType = FieldType::Bytes;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         Some(self.to_vec())
@@ -101,9 +136,7 @@ impl<'a> ConvertFieldType<'a, Vec<u8>> for Vec<u8> {
 impl<'a> ConvertFieldType<'a, &'a [u8]> for &Vec<u8> {
     type Output = Vec<u8>;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::Bytes
-    }
+    const FIELD_TYPE: FieldType = FieldType::Bytes;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         Some(self.to_vec())
@@ -117,9 +150,7 @@ impl<'a> ConvertFieldType<'a, &'a [u8]> for &Vec<u8> {
 impl<'a> ConvertFieldType<'a, &'a str> for &str {
     type Output = &'a str;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::Text
-    }
+    const FIELD_TYPE: FieldType = FieldType::Text;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         Some(self.as_bytes().to_vec())
@@ -136,9 +167,7 @@ impl<'a> ConvertFieldType<'a, &'a str> for &str {
 impl<'a> ConvertFieldType<'a, String> for String {
     type Output = String;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::Text
-    }
+    const FIELD_TYPE: FieldType = FieldType::Text;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         Some(self.as_bytes().to_vec())
@@ -155,9 +184,7 @@ impl<'a> ConvertFieldType<'a, String> for String {
 impl<'a> ConvertFieldType<'a, String> for &String {
     type Output = String;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::Text
-    }
+    const FIELD_TYPE: FieldType = FieldType::Text;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         Some(self.as_bytes().to_vec())
@@ -174,9 +201,7 @@ impl<'a> ConvertFieldType<'a, String> for &String {
 impl<'a> ConvertFieldType<'a, Self> for i8 {
     type Output = i8;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::Int8
-    }
+    const FIELD_TYPE: FieldType = FieldType::Int8;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         let mut buf = Vec::new();
@@ -197,9 +222,7 @@ impl<'a> ConvertFieldType<'a, Self> for i8 {
 impl<'a> ConvertFieldType<'a, Self> for i16 {
     type Output = i16;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::Int16
-    }
+    const FIELD_TYPE: FieldType = FieldType::Int16;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         let mut buf = Vec::new();
@@ -220,9 +243,7 @@ impl<'a> ConvertFieldType<'a, Self> for i16 {
 impl<'a> ConvertFieldType<'a, Self> for i32 {
     type Output = i32;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::Int32
-    }
+    const FIELD_TYPE: FieldType = FieldType::Int32;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         let mut buf = Vec::new();
@@ -243,9 +264,7 @@ impl<'a> ConvertFieldType<'a, Self> for i32 {
 impl<'a> ConvertFieldType<'a, Self> for i64 {
     type Output = i64;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::Int64
-    }
+    const FIELD_TYPE: FieldType = FieldType::Int64;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         let mut buf = Vec::new();
@@ -266,9 +285,7 @@ impl<'a> ConvertFieldType<'a, Self> for i64 {
 impl<'a> ConvertFieldType<'a, Self> for u8 {
     type Output = u8;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::UInt8
-    }
+    const FIELD_TYPE: FieldType = FieldType::UInt8;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         let mut buf = Vec::new();
@@ -289,9 +306,7 @@ impl<'a> ConvertFieldType<'a, Self> for u8 {
 impl<'a> ConvertFieldType<'a, Self> for u16 {
     type Output = u16;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::UInt16
-    }
+    const FIELD_TYPE: FieldType = FieldType::UInt16;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         let mut buf = Vec::new();
@@ -312,9 +327,7 @@ impl<'a> ConvertFieldType<'a, Self> for u16 {
 impl<'a> ConvertFieldType<'a, Self> for u32 {
     type Output = u32;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::UInt32
-    }
+    const FIELD_TYPE: FieldType = FieldType::UInt32;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         let mut buf = Vec::new();
@@ -335,9 +348,7 @@ impl<'a> ConvertFieldType<'a, Self> for u32 {
 impl<'a> ConvertFieldType<'a, Self> for u64 {
     type Output = u64;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::UInt64
-    }
+    const FIELD_TYPE: FieldType = FieldType::UInt64;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         let mut buf = Vec::new();
@@ -358,9 +369,7 @@ impl<'a> ConvertFieldType<'a, Self> for u64 {
 impl<'a> ConvertFieldType<'a, Self> for f32 {
     type Output = f32;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::Float32
-    }
+    const FIELD_TYPE: FieldType = FieldType::Float32;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         let mut buf = Vec::new();
@@ -381,9 +390,7 @@ impl<'a> ConvertFieldType<'a, Self> for f32 {
 impl<'a> ConvertFieldType<'a, Self> for f64 {
     type Output = f64;
 
-    fn get_type(&self) -> FieldType {
-        FieldType::Float64
-    }
+    const FIELD_TYPE: FieldType = FieldType::Float64;
 
     fn serialize(&self) -> Option<Vec<u8>> {
         let mut buf = Vec::new();
@@ -399,4 +406,161 @@ impl<'a> ConvertFieldType<'a, Self> for f64 {
             Err(_) => None,
         }
     }
-}
\ No newline at end of file
+}
+
+impl<'a> ConvertFieldType<'a, Self> for char {
+    type Output = char;
+
+    const FIELD_TYPE: FieldType = FieldType::Char;
+
+    fn serialize(&self) -> Option<Vec<u8>> {
+        let mut buf = Vec::new();
+        match buf.write_u32::<LittleEndian>(*self as u32) {
+            Ok(_) => Some(buf),
+            Err(_) => None
+        }
+    }
+
+    fn deserialize(d: &Vec<u8>) -> Option<Self::Output> {
+        // Rejects values outside of the unicode scalar range (e.g. surrogates)
+        match d.as_slice().read_u32::<LittleEndian>() {
+            Ok(scalar) => std::char::from_u32(scalar),
+            Err(_) => None,
+        }
+    }
+}
+
+impl<'a> ConvertFieldType<'a, Self> for bool {
+    type Output = bool;
+
+    const FIELD_TYPE: FieldType = FieldType::Bool;
+
+    fn serialize(&self) -> Option<Vec<u8>> {
+        Some(vec![*self as u8])
+    }
+
+    fn deserialize(d: &Vec<u8>) -> Option<Self::Output> {
+        match d.as_slice() {
+            [0] => Some(false),
+            [1] => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> ConvertFieldType<'a, Self> for DateTime {
+    type Output = DateTime;
+
+    const FIELD_TYPE: FieldType = FieldType::DateTime;
+
+    fn serialize(&self) -> Option<Vec<u8>> {
+        Some(self.0.to_le_bytes().to_vec())
+    }
+
+    fn deserialize(d: &Vec<u8>) -> Option<Self::Output> {
+        let mut bytes = [0; 8];
+        if d.len() != bytes.len() {
+            return None;
+        }
+
+        bytes.copy_from_slice(d);
+        Some(DateTime(i64::from_le_bytes(bytes)))
+    }
+}
+
+impl<'a> ConvertFieldType<'a, Self> for serde_json::Value {
+    type Output = serde_json::Value;
+
+    const FIELD_TYPE: FieldType = FieldType::Json;
+
+    fn serialize(&self) -> Option<Vec<u8>> {
+        serde_json::to_vec(self).ok()
+    }
+
+    /// `None` if the data isn't valid JSON
+    fn deserialize(d: &Vec<u8>) -> Option<Self::Output> {
+        serde_json::from_slice(d).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        database::{
+            bucket::{
+                descriptor::BucketDescription,
+                document::{field::descriptor::FieldDescriptor, Document},
+            },
+            Database,
+        },
+        utils::testing::temp_path,
+    };
+
+    use super::{super::Field, ConvertFieldType, FieldType};
+
+    #[test]
+    fn char_round_trips_as_a_scalar_value() {
+        for c in ['a', '€', '🦀'] {
+            let data = c.serialize().unwrap();
+            assert_eq!(data.len(), 4);
+            assert_eq!(char::deserialize(&data), Some(c));
+        }
+        assert_eq!('a'.get_type(), FieldType::Char);
+    }
+
+    #[test]
+    fn char_rejects_invalid_scalar_values() {
+        for scalar in [0xD800u32, 0x110000, u32::MAX] {
+            assert_eq!(char::deserialize(&scalar.to_le_bytes().to_vec()), None);
+        }
+        assert_eq!(char::deserialize(&vec![0x61]), None);
+    }
+
+    #[test]
+    fn bool_round_trips_through_a_field() {
+        for flag in [true, false] {
+            let field = Field::new("flag", flag).unwrap();
+            assert_eq!(field.get_type(), &FieldType::Bool);
+            assert_eq!(field.get_data(), &[flag as u8]);
+            assert_eq!(field.get_value::<bool>(), Some(flag));
+        }
+    }
+
+    #[test]
+    fn bool_rejects_bytes_other_than_zero_or_one() {
+        assert_eq!(bool::deserialize(&vec![2]), None);
+        assert_eq!(bool::deserialize(&vec![0xFF]), None);
+        assert_eq!(bool::deserialize(&vec![]), None);
+        assert_eq!(bool::deserialize(&vec![1, 0]), None);
+    }
+
+    #[test]
+    fn nested_json_round_trips_through_a_bucket() {
+        let description =
+            BucketDescription::new(vec![FieldDescriptor::new("meta", FieldType::Json).unwrap()]);
+        let mut db = Database::open(temp_path("fieldtype-json")).unwrap();
+        db.open_bucket("things", Some(description), None).unwrap();
+        let mut bucket = db.get_bucket_mut("things").unwrap();
+
+        let meta = serde_json::json!({
+            "tags": ["a", "b"],
+            "owner": { "name": "Ada", "age": 36 },
+            "active": true,
+        });
+        let document = Document::new(vec![Field::new("meta", meta.clone()).unwrap()]);
+        let offset = bucket.insert(&document).unwrap().0 as u64;
+        bucket.flush().unwrap();
+
+        let read = bucket.get_at(offset).unwrap().unwrap();
+        let field = read.read_field("meta").unwrap();
+        assert_eq!(field.get_type(), &FieldType::Json);
+        assert_eq!(field.get_value::<serde_json::Value>(), Some(meta));
+        assert_eq!(field.get_value::<String>(), None);
+    }
+
+    #[test]
+    fn json_rejects_invalid_bytes() {
+        assert_eq!(serde_json::Value::deserialize(&b"{\"a\": ".to_vec()), None);
+        assert_eq!(serde_json::Value::deserialize(&vec![0xff, 0xfe]), None);
+    }
+}
@@ -0,0 +1,64 @@
+//! Conversion of documents to JSON, decoding every field according to its type
+
+use serde_json::{Map, Number, Value};
+
+use super::{
+    field::{
+        fieldtype::{DateTime, FieldType},
+        Field,
+    },
+    Document,
+};
+
+/// Converts a document to a JSON object keyed by field name
+///
+/// Fields whose name isn't valid UTF-8 are skipped.
+pub fn document_to_json(document: &Document) -> Value {
+    let mut object = Map::new();
+    for f in document.get_fields().iter() {
+        if let Ok(name) = f.get_key().to_str() {
+            object.insert(name.to_owned(), field_to_json(f));
+        }
+    }
+
+    Value::Object(object)
+}
+
+/// Decodes a field to its JSON value
+///
+/// Bytes are base64 encoded, uuids are written hyphenated and timestamps as epoch milliseconds.
+/// JSON fields are embedded as the value they hold.
+/// Malformed data, along with floats which JSON can't represent, are written as `null`.
+pub fn field_to_json(field: &Field) -> Value {
+    let value = match field.get_type() {
+        FieldType::Uuid => field
+            .get_value::<uuid::Uuid>()
+            .map(|u| Value::String(u.to_hyphenated().to_string())),
+        FieldType::Bytes | FieldType::Overflow => {
+            Some(Value::String(base64::encode(field.get_data())))
+        }
+        FieldType::Text => field.get_value::<String>().map(Value::String),
+        FieldType::Int8 => field.get_value::<i8>().map(Value::from),
+        FieldType::Int16 => field.get_value::<i16>().map(Value::from),
+        FieldType::Int32 => field.get_value::<i32>().map(Value::from),
+        FieldType::Int64 => field.get_value::<i64>().map(Value::from),
+        FieldType::UInt8 => field.get_value::<u8>().map(Value::from),
+        FieldType::UInt16 => field.get_value::<u16>().map(Value::from),
+        FieldType::UInt32 => field.get_value::<u32>().map(Value::from),
+        FieldType::UInt64 => field.get_value::<u64>().map(Value::from),
+        FieldType::Float32 => field
+            .get_value::<f32>()
+            .and_then(|f| Number::from_f64(f as f64))
+            .map(Value::Number),
+        FieldType::Float64 => field
+            .get_value::<f64>()
+            .and_then(Number::from_f64)
+            .map(Value::Number),
+        FieldType::Char => field.get_value::<char>().map(|c| Value::String(c.to_string())),
+        FieldType::Bool => field.get_value::<bool>().map(Value::Bool),
+        FieldType::DateTime => field.get_value::<DateTime>().map(|t| Value::from(t.millis())),
+        FieldType::Json => field.get_value::<Value>(),
+    };
+
+    value.unwrap_or(Value::Null)
+}
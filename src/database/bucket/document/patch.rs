@@ -0,0 +1,73 @@
+use std::ffi::CString;
+
+use super::field::Field;
+
+/// Field level changes turning one document into another
+///
+/// Created with `Document::diff` and applied with `Document::apply`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DocumentPatch {
+    pub(crate) added: Vec<Field>,
+    pub(crate) removed: Vec<CString>,
+    pub(crate) changed: Vec<Field>,
+}
+
+impl DocumentPatch {
+    /// Fields which only exist in the target document
+    pub fn get_added(&self) -> &Vec<Field> {
+        &self.added
+    }
+
+    /// Names of the fields which only exist in the source document
+    pub fn get_removed(&self) -> &Vec<CString> {
+        &self.removed
+    }
+
+    /// Fields whose type or value differ, as they are in the target document
+    pub fn get_changed(&self) -> &Vec<Field> {
+        &self.changed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>, bincode::Error> {
+        bincode::serialize(&self)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, bincode::Error> {
+        bincode::deserialize(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::bucket::document::{field::Field, Document};
+
+    use super::DocumentPatch;
+
+    #[test]
+    fn serialized_patch_turns_the_original_into_the_target() {
+        let mut original = Document::new(vec![
+            Field::new("name", "Ada".to_string()).unwrap(),
+            Field::new("age", 36i32).unwrap(),
+            Field::new("city", "London".to_string()).unwrap(),
+        ]);
+        let target = Document::new(vec![
+            Field::new("name", "Ada".to_string()).unwrap(),
+            Field::new("age", 37i32).unwrap(),
+            Field::new("email", "ada@example.com".to_string()).unwrap(),
+        ]);
+
+        let patch = original.diff(&target);
+        assert_eq!(patch.get_added().len(), 1);
+        assert_eq!(patch.get_removed().len(), 1);
+        assert_eq!(patch.get_changed().len(), 1);
+
+        let patch = DocumentPatch::deserialize(&patch.serialize().unwrap()).unwrap();
+        original.apply(&patch);
+        assert_eq!(original, target);
+        assert!(original.diff(&target).is_empty());
+    }
+}
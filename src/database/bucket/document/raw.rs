@@ -0,0 +1,199 @@
+//! Reads the layout of a serialized `Document` without deserializing it
+//!
+//! A document is serialized by bincode as the amount of fields (`u64`) followed by every field,
+//! where a field is its name (`u64` length + bytes), its type (`u32` variant index) and
+//! its data (`u64` length + bytes).
+
+use std::{
+    convert::TryInto,
+    io::{Error, ErrorKind, Read, Seek, SeekFrom},
+    ops::Range,
+};
+
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+
+use super::field::fieldtype::FieldType;
+
+const LEN_SIZE: usize = std::mem::size_of::<u64>();
+const TYPE_SIZE: usize = std::mem::size_of::<u32>();
+
+/// Position of a single field within a serialized document
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawField {
+    pub name: Range<usize>,
+    pub field_type: FieldType,
+    /// Position of the serialized field type
+    pub type_at: usize,
+    /// Position of the field's data, excluding its length
+    pub data: Range<usize>,
+}
+
+/// Locates every field within a serialized document
+///
+/// Returns `None` if the bytes don't describe a complete document.
+pub fn fields(bytes: &[u8]) -> Option<Vec<RawField>> {
+    let mut at = 0;
+    let count = read_len(bytes, &mut at)?;
+
+    let mut fields = Vec::new();
+    for _ in 0..count {
+        fields.push(read_field(bytes, &mut at)?);
+    }
+
+    Some(fields)
+}
+
+/// Locates a single field by name, skipping over the data of every other field
+pub fn find_field(bytes: &[u8], key: &str) -> Option<RawField> {
+    let mut at = 0;
+    let count = read_len(bytes, &mut at)?;
+
+    for _ in 0..count {
+        let field = read_field(bytes, &mut at)?;
+        if &bytes[field.name.clone()] == key.as_bytes() {
+            return Some(field);
+        }
+    }
+
+    None
+}
+
+/// Reads a single field by name from a serialized document of `len` bytes at the current
+/// position of `file`, seeking past the data of every other field instead of reading it
+pub fn read_field_from<R: Read + Seek>(
+    file: &mut R,
+    len: u64,
+    key: &str,
+) -> std::io::Result<Option<(FieldType, Vec<u8>)>> {
+    let mut remaining = len;
+    let mut consume = |amount: u64| -> std::io::Result<()> {
+        remaining = remaining.checked_sub(amount).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, "field runs past the end of the document")
+        })?;
+        Ok(())
+    };
+
+    consume(LEN_SIZE as u64)?;
+    let count = file.read_u64::<LittleEndian>()?;
+
+    for _ in 0..count {
+        consume(LEN_SIZE as u64)?;
+        let name_len = file.read_u64::<LittleEndian>()?;
+        consume(name_len)?;
+        let mut name = vec![0; name_len as usize];
+        file.read_exact(&mut name)?;
+
+        consume(TYPE_SIZE as u64)?;
+        let mut field_type = [0; TYPE_SIZE];
+        file.read_exact(&mut field_type)?;
+
+        consume(LEN_SIZE as u64)?;
+        let data_len = file.read_u64::<LittleEndian>()?;
+        consume(data_len)?;
+
+        if name != key.as_bytes() {
+            file.seek(SeekFrom::Current(data_len as i64))?;
+            continue;
+        }
+
+        let field_type = bincode::deserialize(&field_type)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "unknown field type"))?;
+        let mut data = vec![0; data_len as usize];
+        file.read_exact(&mut data)?;
+
+        return Ok(Some((field_type, data)));
+    }
+
+    Ok(None)
+}
+
+fn read_field(bytes: &[u8], at: &mut usize) -> Option<RawField> {
+    let name_len = read_len(bytes, at)?;
+    let name = take(bytes, at, name_len)?;
+
+    let type_at = *at;
+    let field_type = bincode::deserialize(bytes.get(type_at..type_at + TYPE_SIZE)?).ok()?;
+    *at += TYPE_SIZE;
+
+    let data_len = read_len(bytes, at)?;
+    let data = take(bytes, at, data_len)?;
+
+    Some(RawField {
+        name,
+        field_type,
+        type_at,
+        data,
+    })
+}
+
+fn read_len(bytes: &[u8], at: &mut usize) -> Option<usize> {
+    let len = LittleEndian::read_u64(bytes.get(*at..*at + LEN_SIZE)?);
+    *at += LEN_SIZE;
+    len.try_into().ok()
+}
+
+fn take(bytes: &[u8], at: &mut usize, len: usize) -> Option<Range<usize>> {
+    let end = at.checked_add(len)?;
+    if end > bytes.len() {
+        return None;
+    }
+
+    let range = *at..end;
+    *at = end;
+    Some(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+
+    use crate::database::bucket::document::{
+        field::{fieldtype::FieldType, Field},
+        Document,
+    };
+
+    use super::read_field_from;
+
+    /// Counts the bytes read through it, seeking doesn't count
+    struct Counting<R> {
+        inner: R,
+        read: usize,
+    }
+
+    impl<R: Read> Read for Counting<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let read = self.inner.read(buf)?;
+            self.read += read;
+            Ok(read)
+        }
+    }
+
+    impl<R: Seek> Seek for Counting<R> {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn single_field_is_read_without_the_blob_before_it() {
+        let document = Document::new(vec![
+            Field::new("data", vec![7u8; 1 << 20]).unwrap(),
+            Field::new("first_name", "Anton".to_string()).unwrap(),
+        ]);
+        let bytes = document.serialize().unwrap();
+        let mut file = Counting {
+            inner: Cursor::new(&bytes),
+            read: 0,
+        };
+
+        let (field_type, data) = read_field_from(&mut file, bytes.len() as u64, "first_name")
+            .unwrap()
+            .unwrap();
+        assert_eq!(field_type, FieldType::Text);
+        assert_eq!(data, b"Anton");
+        assert!(file.read < 1024);
+
+        file.seek(SeekFrom::Start(0)).unwrap();
+        assert!(read_field_from(&mut file, bytes.len() as u64, "last_name").unwrap().is_none());
+    }
+}
@@ -0,0 +1,698 @@
+//! Conversion of documents to and from structs implementing serde's traits, see
+//! `Document::from_serialize` and `Document::to_deserialize`
+//!
+//! Every member of a struct becomes a field named after it. Scalars map to the field type of the
+//! same name, strings to `Text` and sequences of `u8` to `Bytes`. Uuids serialize as strings and
+//! are stored as `Text`, a `Uuid` field still deserializes to a uuid. A `None` member is left out
+//! of the document, and unit enum variants are stored as their name. Nested structs, maps and
+//! other sequences have no field type to be stored as and are rejected.
+
+use std::fmt::Display;
+
+use serde::{
+    de::{self, value::SeqDeserializer, DeserializeOwned, IntoDeserializer, MapAccess, Visitor},
+    forward_to_deserialize_any,
+    ser::{self, Impossible, Serialize},
+};
+
+use super::{
+    field::{
+        fieldtype::{ConvertFieldType, DateTime, FieldType},
+        Field,
+    },
+    Document,
+};
+use crate::database::error::NonaneError;
+
+impl ser::Error for NonaneError {
+    fn custom<T: Display>(msg: T) -> Self {
+        NonaneError::Conversion {
+            reason: msg.to_string(),
+        }
+    }
+}
+
+impl de::Error for NonaneError {
+    fn custom<T: Display>(msg: T) -> Self {
+        NonaneError::Conversion {
+            reason: msg.to_string(),
+        }
+    }
+}
+
+fn unsupported(what: &str) -> NonaneError {
+    NonaneError::Conversion {
+        reason: format!("{} can't be stored as a field", what),
+    }
+}
+
+/// Converts a struct, or a map keyed by strings, to a document
+pub fn to_document<T: Serialize + ?Sized>(value: &T) -> Result<Document, NonaneError> {
+    value.serialize(DocumentSerializer)
+}
+
+/// Converts a document to a struct, or a map keyed by strings
+///
+/// Fields the struct doesn't have are ignored, unless it denies unknown fields.
+pub fn from_document<T: DeserializeOwned>(document: &Document) -> Result<T, NonaneError> {
+    T::deserialize(DocumentDeserializer { document })
+}
+
+/// Serializes the top level value, which has to hold named members
+struct DocumentSerializer;
+
+/// Collects the members of a struct or map as fields
+struct FieldCollector {
+    fields: Vec<Field>,
+    /// Key of the map entry whose value is serialized next
+    key: Option<String>,
+}
+
+impl FieldCollector {
+    fn push<T: Serialize + ?Sized>(&mut self, name: &str, value: &T) -> Result<(), NonaneError> {
+        if let Some(field) = value.serialize(FieldSerializer { name })? {
+            self.fields.push(field);
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Document {
+        Document::new(self.fields)
+    }
+}
+
+macro_rules! reject_top_level {
+    ($($method:ident($($arg:ty),*)),* $(,)?) => {
+        $(
+            fn $method(self, $(_: $arg),*) -> Result<Document, NonaneError> {
+                Err(NonaneError::Conversion {
+                    reason: "only structs and maps can be converted to documents".to_owned(),
+                })
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for DocumentSerializer {
+    type Ok = Document;
+    type Error = NonaneError;
+
+    type SerializeSeq = Impossible<Document, NonaneError>;
+    type SerializeTuple = Impossible<Document, NonaneError>;
+    type SerializeTupleStruct = Impossible<Document, NonaneError>;
+    type SerializeTupleVariant = Impossible<Document, NonaneError>;
+    type SerializeMap = FieldCollector;
+    type SerializeStruct = FieldCollector;
+    type SerializeStructVariant = Impossible<Document, NonaneError>;
+
+    reject_top_level!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_none(),
+        serialize_unit(),
+        serialize_unit_struct(&'static str),
+        serialize_unit_variant(&'static str, u32, &'static str),
+    );
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Document, NonaneError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Document, NonaneError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Document, NonaneError> {
+        Err(unsupported("an enum variant holding data"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, NonaneError> {
+        Err(unsupported("a sequence"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, NonaneError> {
+        Err(unsupported("a tuple"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, NonaneError> {
+        Err(unsupported("a tuple struct"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, NonaneError> {
+        Err(unsupported("an enum variant holding data"))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, NonaneError> {
+        Ok(FieldCollector {
+            fields: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, NonaneError> {
+        Ok(FieldCollector {
+            fields: Vec::with_capacity(len),
+            key: None,
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, NonaneError> {
+        Err(unsupported("an enum variant holding data"))
+    }
+}
+
+impl ser::SerializeStruct for FieldCollector {
+    type Ok = Document;
+    type Error = NonaneError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), NonaneError> {
+        self.push(key, value)
+    }
+
+    fn end(self) -> Result<Document, NonaneError> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeMap for FieldCollector {
+    type Ok = Document;
+    type Error = NonaneError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), NonaneError> {
+        match key.serialize(FieldSerializer { name: "" })? {
+            Some(f) if *f.get_type() == FieldType::Text => {
+                self.key = f.get_value::<String>();
+                Ok(())
+            }
+            _ => Err(NonaneError::Conversion {
+                reason: "map keys have to be strings to be used as field names".to_owned(),
+            }),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), NonaneError> {
+        let key = self.key.take().ok_or_else(|| NonaneError::Conversion {
+            reason: "map value was serialized before its key".to_owned(),
+        })?;
+
+        self.push(&key, value)
+    }
+
+    fn end(self) -> Result<Document, NonaneError> {
+        Ok(self.finish())
+    }
+}
+
+/// Serializes a member to the field named `name`, `None` leaves the member out of the document
+struct FieldSerializer<'a> {
+    name: &'a str,
+}
+
+impl<'a> FieldSerializer<'a> {
+    fn field<T: ConvertFieldType<'a, T>>(self, value: T) -> Result<Option<Field>, NonaneError> {
+        match Field::new(self.name, value) {
+            Some(f) => Ok(Some(f)),
+            None => Err(NonaneError::Conversion {
+                reason: format!("field {:?} has an invalid name or value", self.name),
+            }),
+        }
+    }
+}
+
+impl<'a> ser::Serializer for FieldSerializer<'a> {
+    type Ok = Option<Field>;
+    type Error = NonaneError;
+
+    type SerializeSeq = BytesCollector<'a>;
+    type SerializeTuple = Impossible<Option<Field>, NonaneError>;
+    type SerializeTupleStruct = Impossible<Option<Field>, NonaneError>;
+    type SerializeTupleVariant = Impossible<Option<Field>, NonaneError>;
+    type SerializeMap = Impossible<Option<Field>, NonaneError>;
+    type SerializeStruct = Impossible<Option<Field>, NonaneError>;
+    type SerializeStructVariant = Impossible<Option<Field>, NonaneError>;
+
+    fn serialize_bool(self, v: bool) -> Result<Option<Field>, NonaneError> {
+        self.field(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Option<Field>, NonaneError> {
+        self.field(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Option<Field>, NonaneError> {
+        self.field(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Option<Field>, NonaneError> {
+        self.field(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Option<Field>, NonaneError> {
+        self.field(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Option<Field>, NonaneError> {
+        self.field(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Option<Field>, NonaneError> {
+        self.field(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Option<Field>, NonaneError> {
+        self.field(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Option<Field>, NonaneError> {
+        self.field(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Option<Field>, NonaneError> {
+        self.field(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Option<Field>, NonaneError> {
+        self.field(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<Option<Field>, NonaneError> {
+        self.field(v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Option<Field>, NonaneError> {
+        self.field(v.to_owned())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Option<Field>, NonaneError> {
+        self.field(v.to_vec())
+    }
+
+    fn serialize_none(self) -> Result<Option<Field>, NonaneError> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(
+        self,
+        value: &T,
+    ) -> Result<Option<Field>, NonaneError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Option<Field>, NonaneError> {
+        Err(unsupported("a unit value"))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Option<Field>, NonaneError> {
+        Err(unsupported("a unit struct"))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Option<Field>, NonaneError> {
+        self.field(variant.to_owned())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Option<Field>, NonaneError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Option<Field>, NonaneError> {
+        Err(unsupported("an enum variant holding data"))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<BytesCollector<'a>, NonaneError> {
+        Ok(BytesCollector {
+            field: self,
+            bytes: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, NonaneError> {
+        Err(unsupported("a tuple"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, NonaneError> {
+        Err(unsupported("a tuple struct"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, NonaneError> {
+        Err(unsupported("an enum variant holding data"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, NonaneError> {
+        Err(unsupported("a nested map"))
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, NonaneError> {
+        Err(unsupported(&format!("nested struct {}", name)))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, NonaneError> {
+        Err(unsupported("an enum variant holding data"))
+    }
+}
+
+/// Collects a sequence of `u8` into a bytes field
+struct BytesCollector<'a> {
+    field: FieldSerializer<'a>,
+    bytes: Vec<u8>,
+}
+
+impl<'a> ser::SerializeSeq for BytesCollector<'a> {
+    type Ok = Option<Field>;
+    type Error = NonaneError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), NonaneError> {
+        match value.serialize(FieldSerializer { name: "" })? {
+            Some(f) if *f.get_type() == FieldType::UInt8 => {
+                self.bytes.extend_from_slice(f.get_data());
+                Ok(())
+            }
+            _ => Err(unsupported("a sequence of anything but bytes")),
+        }
+    }
+
+    fn end(self) -> Result<Option<Field>, NonaneError> {
+        self.field.field(self.bytes)
+    }
+}
+
+/// Deserializes a document as a map of its fields
+struct DocumentDeserializer<'a> {
+    document: &'a Document,
+}
+
+impl<'de, 'a> de::Deserializer<'de> for DocumentDeserializer<'a> {
+    type Error = NonaneError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, NonaneError> {
+        visitor.visit_map(FieldAccess {
+            fields: self.document.get_fields().iter(),
+            value: None,
+        })
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, NonaneError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Hands out the fields of a document as the entries of a map
+struct FieldAccess<'a> {
+    fields: std::slice::Iter<'a, Field>,
+    /// Field whose name was handed out last, its value is next
+    value: Option<&'a Field>,
+}
+
+impl<'de, 'a> MapAccess<'de> for FieldAccess<'a> {
+    type Error = NonaneError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, NonaneError> {
+        match self.fields.next() {
+            Some(f) => {
+                self.value = Some(f);
+                let name = f.get_key().to_string_lossy().into_owned();
+                seed.deserialize(name.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, NonaneError> {
+        let field = self.value.take().ok_or_else(|| NonaneError::Conversion {
+            reason: "field value was read before its name".to_owned(),
+        })?;
+
+        seed.deserialize(FieldDeserializer { field })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len())
+    }
+}
+
+/// Deserializes the value of a field according to its type
+struct FieldDeserializer<'a> {
+    field: &'a Field,
+}
+
+impl<'a> FieldDeserializer<'a> {
+    fn value<T: ConvertFieldType<'a, T>>(&self) -> Result<T::Output, NonaneError> {
+        self.field
+            .get_value::<T>()
+            .ok_or_else(|| NonaneError::Conversion {
+                reason: format!(
+                    "field {} holds malformed data",
+                    self.field.get_key().to_string_lossy()
+                ),
+            })
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for FieldDeserializer<'a> {
+    type Error = NonaneError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, NonaneError> {
+        match self.field.get_type() {
+            FieldType::Uuid => {
+                visitor.visit_string(self.value::<uuid::Uuid>()?.to_hyphenated().to_string())
+            }
+            FieldType::Bytes | FieldType::Overflow => {
+                visitor.visit_byte_buf(self.field.get_data().to_vec())
+            }
+            FieldType::Text => visitor.visit_string(self.value::<String>()?),
+            FieldType::Int8 => visitor.visit_i8(self.value::<i8>()?),
+            FieldType::Int16 => visitor.visit_i16(self.value::<i16>()?),
+            FieldType::Int32 => visitor.visit_i32(self.value::<i32>()?),
+            FieldType::Int64 => visitor.visit_i64(self.value::<i64>()?),
+            FieldType::UInt8 => visitor.visit_u8(self.value::<u8>()?),
+            FieldType::UInt16 => visitor.visit_u16(self.value::<u16>()?),
+            FieldType::UInt32 => visitor.visit_u32(self.value::<u32>()?),
+            FieldType::UInt64 => visitor.visit_u64(self.value::<u64>()?),
+            FieldType::Float32 => visitor.visit_f32(self.value::<f32>()?),
+            FieldType::Float64 => visitor.visit_f64(self.value::<f64>()?),
+            FieldType::Char => visitor.visit_char(self.value::<char>()?),
+            FieldType::Bool => visitor.visit_bool(self.value::<bool>()?),
+            FieldType::DateTime => visitor.visit_i64(self.value::<DateTime>()?.millis()),
+            FieldType::Json => self
+                .value::<serde_json::Value>()?
+                .deserialize_any(visitor)
+                .map_err(de::Error::custom),
+        }
+    }
+
+    /// A field which is present always holds a value, missing fields are `None`
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, NonaneError> {
+        visitor.visit_some(self)
+    }
+
+    /// Bytes fields are handed out as a sequence, which is what `Vec<u8>` expects
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, NonaneError> {
+        match self.field.get_type() {
+            FieldType::Bytes | FieldType::Overflow => visitor.visit_seq(SeqDeserializer::new(
+                self.field.get_data().iter().copied(),
+            )),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, NonaneError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Unit variants are stored as their name
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, NonaneError> {
+        visitor.visit_enum(self.value::<String>()?.into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{bucket::document::Document, error::NonaneError};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Role {
+        Admin,
+        Member,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Account {
+        name: String,
+        age: u8,
+        balance: i64,
+        score: f64,
+        active: bool,
+        initial: char,
+        avatar: Vec<u8>,
+        nickname: Option<String>,
+        role: Role,
+    }
+
+    #[derive(Debug, Serialize)]
+    struct Address {
+        city: String,
+    }
+
+    #[derive(Debug, Serialize)]
+    struct Person {
+        name: String,
+        address: Address,
+    }
+
+    #[test]
+    fn struct_round_trips_through_a_document() {
+        let account = Account {
+            name: "Ada".to_string(),
+            age: 36,
+            balance: -120,
+            score: 0.75,
+            active: true,
+            initial: 'A',
+            avatar: vec![1, 2, 3],
+            nickname: None,
+            role: Role::Admin,
+        };
+
+        let document = Document::from_serialize(&account).unwrap();
+        assert_eq!(document.get_str("name"), Some("Ada"));
+        assert_eq!(document.get_u8("age"), Some(36));
+        assert_eq!(document.get_bytes("avatar"), Some(&[1u8, 2, 3][..]));
+        assert_eq!(document.get_str("role"), Some("Admin"));
+        assert!(document.read_field("nickname").is_none());
+        assert_eq!(document.to_deserialize::<Account>().unwrap(), account);
+
+        let member = Account {
+            nickname: Some("Countess".to_string()),
+            role: Role::Member,
+            ..account
+        };
+        let document = Document::from_serialize(&member).unwrap();
+        assert_eq!(document.to_deserialize::<Account>().unwrap(), member);
+    }
+
+    #[test]
+    fn nested_struct_is_rejected() {
+        let person = Person {
+            name: "Ada".to_string(),
+            address: Address {
+                city: "London".to_string(),
+            },
+        };
+        assert!(matches!(
+            Document::from_serialize(&person),
+            Err(NonaneError::Conversion { reason }) if reason.contains("Address")
+        ));
+    }
+}
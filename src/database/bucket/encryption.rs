@@ -0,0 +1,154 @@
+//! Encryption of the rows of a bucket at rest, see `BucketConfiguration::encryption_key`
+//!
+//! Every encrypted row holds the length of its encrypted bytes, followed by the nonce it was
+//! encrypted with and the ChaCha20-Poly1305 ciphertext of its document. Reading a row with
+//! another key fails the authentication of the ciphertext instead of returning garbage.
+
+use std::{
+    fmt,
+    io::{Error, ErrorKind},
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Nonce,
+};
+
+use crate::database::error::NonaneError;
+
+/// Amount of bytes taken up by the nonce stored in every encrypted row
+pub const NONCE_SIZE: usize = 12;
+
+/// Key the rows of a bucket are encrypted with, it's only held in memory and never written
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    pub fn new(key: [u8; 32]) -> EncryptionKey {
+        EncryptionKey(key)
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(&self.0.into())
+    }
+}
+
+// Keeps the key out of logged configurations
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EncryptionKey(..)")
+    }
+}
+
+/// Encrypts the payload of a row with a new random nonce, the result is prefixed with its length
+/// so the row's padding can be told apart from the encrypted bytes
+pub fn encrypt(data: &[u8], key: &EncryptionKey) -> std::io::Result<Vec<u8>> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = key
+        .cipher()
+        .encrypt(&nonce, data)
+        .map_err(|_| Error::other("failed to encrypt row"))?;
+
+    let mut buf = vec![0; std::mem::size_of::<u64>()];
+    LittleEndian::write_u64(&mut buf, (NONCE_SIZE + ciphertext.len()) as u64);
+    buf.extend_from_slice(&nonce);
+    buf.extend_from_slice(&ciphertext);
+    Ok(buf)
+}
+
+/// Decrypts the payload of an encrypted row, ignoring its padding
+///
+/// Errors with `NonaneError::DecryptionFailed` without a key or if the row wasn't encrypted
+/// with `key`.
+pub fn decrypt(payload: &[u8], key: Option<&EncryptionKey>) -> Result<Vec<u8>, NonaneError> {
+    let key = key.ok_or(NonaneError::DecryptionFailed)?;
+
+    let prefix = std::mem::size_of::<u64>();
+    if payload.len() < prefix + NONCE_SIZE {
+        return Err(Error::new(ErrorKind::InvalidData, "malformed encrypted row").into());
+    }
+
+    let len = LittleEndian::read_u64(&payload[..prefix]) as usize;
+    let encrypted = payload
+        .get(prefix..prefix.saturating_add(len))
+        .filter(|e| e.len() >= NONCE_SIZE)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "malformed encrypted row"))?;
+
+    let (nonce, ciphertext) = encrypted.split_at(NONCE_SIZE);
+    key.cipher()
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| NonaneError::DecryptionFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        database::{
+            bucket::{
+                config::BucketConfiguration,
+                descriptor::BucketDescription,
+                document::{
+                    field::{descriptor::FieldDescriptor, fieldtype::FieldType, Field},
+                    Document,
+                },
+            },
+            error::NonaneError,
+            Database,
+        },
+        utils::testing::temp_path,
+    };
+
+    use super::EncryptionKey;
+
+    const SECRET: &str = "a value only the key should reveal";
+
+    fn encrypted(key: u8) -> Option<BucketConfiguration> {
+        Some(BucketConfiguration {
+            encryption_key: Some(EncryptionKey::new([key; 32])),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn encrypted_bucket_round_trips_and_refuses_other_keys() {
+        let path = temp_path("encryption-round-trip");
+        let description =
+            BucketDescription::new(vec![FieldDescriptor::new("text", FieldType::Text).unwrap()]);
+        let offset = {
+            let mut db = Database::open(path).unwrap();
+            db.open_bucket("secrets", Some(description), encrypted(1)).unwrap();
+            let mut bucket = db.get_bucket_mut("secrets").unwrap();
+            let document = Document::new(vec![Field::new("text", SECRET.to_string()).unwrap()]);
+            let offset = bucket.insert(&document).unwrap().0 as u64;
+
+            // An index would store the value unencrypted next to the bucket
+            assert!(matches!(
+                bucket.create_index("text"),
+                Err(NonaneError::EncryptedIndex { .. })
+            ));
+            bucket.flush().unwrap();
+            drop(bucket);
+            db.close().unwrap();
+            offset
+        };
+
+        for entry in std::fs::read_dir(path).unwrap() {
+            let contents = std::fs::read(entry.unwrap().path()).unwrap();
+            assert!(!contents.windows(SECRET.len()).any(|w| w == SECRET.as_bytes()));
+        }
+
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("secrets", None, encrypted(1)).unwrap();
+        let document = db.get_bucket("secrets").unwrap().get_at(offset).unwrap().unwrap();
+        assert_eq!(document.read_field("text").unwrap().get_value::<&str>(), Some(SECRET));
+        db.close().unwrap();
+
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("secrets", None, encrypted(2)).unwrap();
+        assert!(matches!(
+            db.get_bucket("secrets").unwrap().get_at(offset),
+            Err(NonaneError::DecryptionFailed)
+        ));
+    }
+}
@@ -0,0 +1,543 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryInto,
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::document::{field::fieldtype::FieldType, Document};
+
+/// Extension used for index files
+pub const INDEX_EXTENSION: &str = "idx";
+
+/// Extension used for the files of sorted indexes, see `ValueIndex::create_sorted`
+pub const SORTED_INDEX_EXTENSION: &str = "sidx";
+
+/// Bytes stored for an entry besides its key, the key length and the offset
+const ENTRY_OVERHEAD: usize = std::mem::size_of::<u32>() + std::mem::size_of::<u64>();
+
+/// Set on the offset of an entry which removes a document from the index
+const REMOVED: u64 = 1 << 63;
+
+/// Index mapping the values of a field to the offsets of the documents holding them
+///
+/// Unlike `UniqueIndex` any number of documents may share a value, see `Bucket::create_index`.
+/// Every entry is appended to a sidecar file next to the bucket as a u32 key length, the key and
+/// the u64 offset of the document, the file is read back into memory when the bucket is opened.
+/// Removing a document appends an entry with `REMOVED` set on its offset. A sorted index keeps
+/// the values of an integer field in order for range lookups, see `create_sorted`.
+#[derive(Debug)]
+pub struct ValueIndex {
+    field: String,
+    offsets: Offsets,
+    file: File,
+}
+
+/// Offsets of the indexed documents by their key
+#[derive(Debug)]
+enum Offsets {
+    /// Keyed by the serialized values
+    Hash(HashMap<Vec<u8>, Vec<u64>>),
+    /// Keyed by the integer values of a field of the type, see `sortable_key`
+    Sorted(BTreeMap<Vec<u8>, Vec<u64>>, FieldType),
+}
+
+impl Offsets {
+    fn push(&mut self, key: Vec<u8>, offset: u64) {
+        match self {
+            Offsets::Hash(offsets) => offsets.entry(key).or_default().push(offset),
+            Offsets::Sorted(offsets, _) => offsets.entry(key).or_default().push(offset),
+        }
+    }
+
+    fn remove(&mut self, key: &[u8], offset: u64) {
+        let list = match self {
+            Offsets::Hash(offsets) => offsets.get_mut(key),
+            Offsets::Sorted(offsets, _) => offsets.get_mut(key),
+        };
+
+        let empty = match list {
+            Some(list) => {
+                list.retain(|o| *o != offset);
+                list.is_empty()
+            }
+            None => false,
+        };
+
+        if empty {
+            match self {
+                Offsets::Hash(offsets) => offsets.remove(key),
+                Offsets::Sorted(offsets, _) => offsets.remove(key),
+            };
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> Option<&Vec<u64>> {
+        match self {
+            Offsets::Hash(offsets) => offsets.get(key),
+            Offsets::Sorted(offsets, _) => offsets.get(key),
+        }
+    }
+
+    fn entries(&self) -> Box<dyn Iterator<Item = (&Vec<u8>, &Vec<u64>)> + '_> {
+        match self {
+            Offsets::Hash(offsets) => Box::new(offsets.iter()),
+            Offsets::Sorted(offsets, _) => Box::new(offsets.iter()),
+        }
+    }
+}
+
+/// Encodes an integer value so the keys of a sorted index sort in the order of their values
+///
+/// Values are stored little-endian, which doesn't sort bytewise. The value is widened to an
+/// `i64` whose sign bit is flipped and stored big-endian instead, so negative values sort before
+/// positive ones. Returns `None` for a type which isn't sortable or data of the wrong length.
+pub fn sortable_key(field_type: FieldType, data: &[u8]) -> Option<[u8; 8]> {
+    let value = match field_type {
+        FieldType::Int8 => i8::from_le_bytes(data.try_into().ok()?) as i64,
+        FieldType::Int16 => i16::from_le_bytes(data.try_into().ok()?) as i64,
+        FieldType::Int32 => i32::from_le_bytes(data.try_into().ok()?) as i64,
+        FieldType::Int64 | FieldType::DateTime => i64::from_le_bytes(data.try_into().ok()?),
+        FieldType::UInt8 => u8::from_le_bytes(data.try_into().ok()?) as i64,
+        FieldType::UInt16 => u16::from_le_bytes(data.try_into().ok()?) as i64,
+        FieldType::UInt32 => u32::from_le_bytes(data.try_into().ok()?) as i64,
+        _ => return None,
+    };
+
+    Some(sortable_i64(value))
+}
+
+fn sortable_i64(value: i64) -> [u8; 8] {
+    ((value as u64) ^ (1 << 63)).to_be_bytes()
+}
+
+/// Serialized value of the field type a key was made from by `sortable_key`
+fn unsortable_key(field_type: FieldType, key: &[u8]) -> Option<Vec<u8>> {
+    let value = (u64::from_be_bytes(key.try_into().ok()?) ^ (1 << 63)).to_le_bytes();
+    let len = match field_type {
+        FieldType::Int8 | FieldType::UInt8 => 1,
+        FieldType::Int16 | FieldType::UInt16 => 2,
+        FieldType::Int32 | FieldType::UInt32 => 4,
+        FieldType::Int64 | FieldType::DateTime => 8,
+        _ => return None,
+    };
+
+    Some(value[..len].to_vec())
+}
+
+impl ValueIndex {
+    /// Path of the file holding the index of `field` for the bucket stored at `bucket_path`
+    pub fn path(bucket_path: &Path, field: &str) -> PathBuf {
+        bucket_path.with_extension(format!("{}.{}", field, INDEX_EXTENSION))
+    }
+
+    /// Path of the file holding the sorted index of `field`, see `create_sorted`
+    pub fn sorted_path(bucket_path: &Path, field: &str) -> PathBuf {
+        bucket_path.with_extension(format!("{}.{}", field, SORTED_INDEX_EXTENSION))
+    }
+
+    /// Whether a sorted index can be created for fields of the type, integer types whose values
+    /// fit an `i64` can
+    pub fn is_sortable(field_type: FieldType) -> bool {
+        matches!(
+            field_type,
+            FieldType::Int8
+                | FieldType::Int16
+                | FieldType::Int32
+                | FieldType::Int64
+                | FieldType::DateTime
+                | FieldType::UInt8
+                | FieldType::UInt16
+                | FieldType::UInt32
+        )
+    }
+
+    /// Whether `field` can be part of the name of an index file
+    pub fn is_valid_field(field: &str) -> bool {
+        !field.is_empty()
+            && field
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }
+
+    /// Creates an empty index, replacing any index file already stored at `path`
+    pub fn create(path: &Path, field: &str) -> std::io::Result<ValueIndex> {
+        ValueIndex::create_with(path, field, Offsets::Hash(HashMap::new()))
+    }
+
+    /// Creates an empty sorted index of an integer field, see `range`
+    ///
+    /// The keys are kept in order instead of hashed, see `sortable_key`. `field_type` has to be
+    /// sortable, see `is_sortable`.
+    pub fn create_sorted(
+        path: &Path,
+        field: &str,
+        field_type: FieldType,
+    ) -> std::io::Result<ValueIndex> {
+        ValueIndex::create_with(path, field, Offsets::Sorted(BTreeMap::new(), field_type))
+    }
+
+    fn create_with(path: &Path, field: &str, offsets: Offsets) -> std::io::Result<ValueIndex> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        file.set_len(0)?;
+
+        Ok(ValueIndex {
+            field: field.to_owned(),
+            offsets,
+            file,
+        })
+    }
+
+    /// Reads the index stored at `path`, dropping entries at or past `end`
+    ///
+    /// Entries past `end` belong to documents which never made it to disk, the file is rewritten
+    /// without them so their offsets can be reused, as well as without removed documents. A
+    /// partially written last entry is ignored.
+    pub fn open(path: &Path, field: &str, end: u64) -> std::io::Result<ValueIndex> {
+        ValueIndex::open_with(path, field, Offsets::Hash(HashMap::new()), end)
+    }
+
+    /// Reads the sorted index stored at `path`, like `open`
+    pub fn open_sorted(
+        path: &Path,
+        field: &str,
+        field_type: FieldType,
+        end: u64,
+    ) -> std::io::Result<ValueIndex> {
+        ValueIndex::open_with(path, field, Offsets::Sorted(BTreeMap::new(), field_type), end)
+    }
+
+    fn open_with(
+        path: &Path,
+        field: &str,
+        mut offsets: Offsets,
+        end: u64,
+    ) -> std::io::Result<ValueIndex> {
+        let file = OpenOptions::new().read(true).append(true).open(path)?;
+
+        let mut stale = false;
+        {
+            let len = file.metadata()?.len();
+            let mut read = 0;
+            let mut reader = BufReader::new(&file);
+            loop {
+                let (key, offset) = match ValueIndex::read_entry(&mut reader) {
+                    Ok(entry) => entry,
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                        // Later entries can't be appended behind a partial one
+                        stale |= read != len;
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                };
+                read += (key.len() + ENTRY_OVERHEAD) as u64;
+
+                if offset & REMOVED != 0 {
+                    offsets.remove(&key, offset & !REMOVED);
+                    stale = true;
+                } else if offset < end {
+                    offsets.push(key, offset);
+                } else {
+                    stale = true;
+                }
+            }
+        }
+
+        let mut index = ValueIndex {
+            field: field.to_owned(),
+            offsets,
+            file,
+        };
+
+        if stale {
+            index.rewrite()?;
+        }
+
+        Ok(index)
+    }
+
+    pub fn get_field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn is_sorted(&self) -> bool {
+        matches!(self.offsets, Offsets::Sorted(..))
+    }
+
+    /// Removes every document from the index
+    pub fn clear(&mut self) -> std::io::Result<()> {
+        self.offsets = match self.offsets {
+            Offsets::Hash(_) => Offsets::Hash(HashMap::new()),
+            Offsets::Sorted(_, field_type) => Offsets::Sorted(BTreeMap::new(), field_type),
+        };
+
+        self.file.set_len(0)
+    }
+
+    /// Key the serialized `value` is indexed under, `None` if it can't be part of the index
+    fn key(&self, value: &[u8]) -> Option<Vec<u8>> {
+        match self.offsets {
+            Offsets::Hash(_) => Some(value.to_vec()),
+            Offsets::Sorted(_, field_type) => sortable_key(field_type, value).map(|k| k.to_vec()),
+        }
+    }
+
+    /// Adds the document stored at `offset`, a document lacking the field isn't indexed
+    pub fn insert(&mut self, document: &Document, offset: u64) -> std::io::Result<()> {
+        let key = match document.read_field(&self.field).and_then(|f| self.key(f.get_data())) {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+
+        let mut entry = Vec::with_capacity(key.len() + ENTRY_OVERHEAD);
+        ValueIndex::write_entry(&mut entry, &key, offset)?;
+        self.file.write_all(&entry)?;
+
+        self.offsets.push(key, offset);
+        Ok(())
+    }
+
+    /// Removes the document stored at `offset`
+    pub fn remove(&mut self, document: &Document, offset: u64) -> std::io::Result<()> {
+        let key = match document.read_field(&self.field).and_then(|f| self.key(f.get_data())) {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+
+        let mut entry = Vec::with_capacity(key.len() + ENTRY_OVERHEAD);
+        ValueIndex::write_entry(&mut entry, &key, offset | REMOVED)?;
+        self.file.write_all(&entry)?;
+
+        self.offsets.remove(&key, offset);
+        Ok(())
+    }
+
+    /// Offsets of the documents whose field holds the serialized `value`, in insertion order
+    pub fn get(&self, value: &[u8]) -> &[u64] {
+        self.key(value)
+            .and_then(|key| self.offsets.get(&key))
+            .map_or(&[], |o| o.as_slice())
+    }
+
+    /// Offsets of the documents whose field's serialized value starts with `prefix`, in no
+    /// particular order
+    ///
+    /// Every value held by the index is compared, the documents themselves aren't read.
+    pub fn prefixed(&self, prefix: &[u8]) -> Vec<u64> {
+        let matches = |key: &[u8]| match self.offsets {
+            Offsets::Hash(_) => key.starts_with(prefix),
+            Offsets::Sorted(_, field_type) => {
+                unsortable_key(field_type, key).is_some_and(|v| v.starts_with(prefix))
+            }
+        };
+
+        self.offsets
+            .entries()
+            .filter(|(key, _)| matches(key))
+            .flat_map(|(_, o)| o.iter().copied())
+            .collect()
+    }
+
+    /// Offsets of the documents whose field holds a value from `low` to `high`, both included,
+    /// ordered by value and then by insertion
+    ///
+    /// Returns `None` if the index isn't sorted, see `create_sorted`.
+    pub fn range(&self, low: i64, high: i64) -> Option<Vec<u64>> {
+        let offsets = match &self.offsets {
+            Offsets::Sorted(offsets, _) => offsets,
+            Offsets::Hash(_) => return None,
+        };
+
+        if low > high {
+            return Some(Vec::new());
+        }
+
+        let range = sortable_i64(low).to_vec()..=sortable_i64(high).to_vec();
+        Some(offsets.range(range).flat_map(|(_, o)| o.iter().copied()).collect())
+    }
+
+    pub fn count(&self, value: &[u8]) -> usize {
+        self.get(value).len()
+    }
+
+    pub fn sync(&self) -> std::io::Result<()> {
+        self.file.sync_data()
+    }
+
+    /// Replaces the contents of the index file with the entries held in memory
+    fn rewrite(&mut self) -> std::io::Result<()> {
+        self.file.set_len(0)?;
+
+        let mut writer = BufWriter::new(&self.file);
+        for (key, offsets) in self.offsets.entries() {
+            for offset in offsets.iter() {
+                ValueIndex::write_entry(&mut writer, key, *offset)?;
+            }
+        }
+
+        writer.flush()?;
+        drop(writer);
+
+        self.file.sync_data()
+    }
+
+    fn write_entry<W: Write>(writer: &mut W, key: &[u8], offset: u64) -> std::io::Result<()> {
+        writer.write_u32::<LittleEndian>(key.len() as u32)?;
+        writer.write_all(key)?;
+        writer.write_u64::<LittleEndian>(offset)
+    }
+
+    fn read_entry<R: Read>(reader: &mut R) -> std::io::Result<(Vec<u8>, u64)> {
+        let len = reader.read_u32::<LittleEndian>()? as usize;
+        let mut key = vec![0; len];
+        reader.read_exact(&mut key)?;
+        let offset = reader.read_u64::<LittleEndian>()?;
+
+        Ok((key, offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        database::{
+            bucket::{
+                descriptor::BucketDescription,
+                document::{
+                    field::{descriptor::FieldDescriptor, fieldtype::FieldType, Field},
+                    Document,
+                },
+                Bucket,
+            },
+            Database,
+        },
+        utils::testing::{int_description, int_document, temp_path},
+    };
+
+    fn texts(bucket: &Bucket, prefix: &str) -> Vec<String> {
+        bucket
+            .find_prefix("text", prefix.as_bytes())
+            .unwrap()
+            .iter()
+            .map(|d| d.read_field("text").unwrap().get_value::<&str>().unwrap().to_owned())
+            .collect()
+    }
+
+    fn numbers(bucket: &Bucket, prefix: &[u8]) -> Vec<i32> {
+        bucket
+            .find_prefix("n", prefix)
+            .unwrap()
+            .iter()
+            .map(|d| d.get_i32("n").unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn find_prefix_uses_the_index_of_the_field() {
+        let description =
+            BucketDescription::new(vec![FieldDescriptor::new("text", FieldType::Text).unwrap()]);
+        let mut db = Database::open(temp_path("index-find-prefix")).unwrap();
+        db.open_bucket("texts", Some(description), None).unwrap();
+        let mut bucket = db.get_bucket_mut("texts").unwrap();
+        bucket.create_index("text").unwrap();
+        for text in ["apple", "banana", "apricot", "ap"] {
+            let document = Document::new(vec![Field::new("text", text.to_string()).unwrap()]);
+            bucket.insert(&document).unwrap();
+        }
+        bucket.flush().unwrap();
+
+        assert_eq!(texts(&bucket, "ap"), ["apple", "apricot", "ap"]);
+        assert_eq!(texts(&bucket, ""), ["apple", "banana", "apricot", "ap"]);
+        assert_eq!(texts(&bucket, "banana"), ["banana"]);
+        assert!(texts(&bucket, "bananas").is_empty());
+        assert!(texts(&bucket, "c").is_empty());
+    }
+
+    #[test]
+    fn find_prefix_through_a_sorted_index_matches_a_scan() {
+        let mut db = Database::open(temp_path("index-find-prefix-sorted")).unwrap();
+        db.open_bucket("sorted", Some(int_description()), None).unwrap();
+        db.open_bucket("scanned", Some(int_description()), None).unwrap();
+        db.get_bucket_mut("sorted").unwrap().create_sorted_index("n").unwrap();
+        for name in ["sorted", "scanned"] {
+            let mut bucket = db.get_bucket_mut(name).unwrap();
+            for n in [-1, 0, 1, 256, 257, i32::MIN, i32::MAX] {
+                bucket.insert(&int_document(n)).unwrap();
+            }
+            bucket.flush().unwrap();
+        }
+
+        let sorted = db.get_bucket("sorted").unwrap();
+        let scanned = db.get_bucket("scanned").unwrap();
+        for prefix in [&[][..], &[1], &[0], &[0xff, 0xff, 0xff, 0xff], &[0, 0, 0, 0x80], &[2]] {
+            assert_eq!(numbers(&sorted, prefix), numbers(&scanned, prefix));
+        }
+        assert_eq!(numbers(&sorted, &[1]), [1, 257]);
+        assert_eq!(numbers(&sorted, &[]).len(), 7);
+    }
+
+    #[test]
+    fn index_count_counts_the_rows_holding_a_value() {
+        let description = BucketDescription::new(vec![
+            FieldDescriptor::new("category", FieldType::Text).unwrap(),
+        ]);
+        let mut db = Database::open(temp_path("index-count")).unwrap();
+        db.open_bucket("indexed", Some(description.clone()), None).unwrap();
+        db.open_bucket("scanned", Some(description), None).unwrap();
+        db.get_bucket_mut("indexed").unwrap().create_index("category").unwrap();
+
+        for (category, amount) in [("books", 7), ("games", 3)] {
+            for _ in 0..amount {
+                let category = Field::new("category", category.to_string()).unwrap();
+                db.insert_document("indexed", 0, Document::new(vec![category.clone()]))
+                    .unwrap();
+                db.insert_document("scanned", 0, Document::new(vec![category]))
+                    .unwrap();
+            }
+        }
+        db.flush_bucket("indexed").unwrap();
+        db.flush_bucket("scanned").unwrap();
+
+        let books = Field::new("category", "books".to_string()).unwrap();
+        let missing = Field::new("category", "films".to_string()).unwrap();
+        for bucket in ["indexed", "scanned"] {
+            assert_eq!(db.index_count(bucket, "category", books.get_data()).unwrap(), 7);
+            assert_eq!(db.index_count(bucket, "category", missing.get_data()).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn find_range_orders_negative_and_positive_values() {
+        let mut db = Database::open(temp_path("index-find-range")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        bucket.create_sorted_index("n").unwrap();
+        for n in [5, -3, 100, -100, 0, 42, -1, 7, i32::MIN, i32::MAX] {
+            bucket.insert(&int_document(n)).unwrap();
+        }
+        bucket.flush().unwrap();
+
+        let range = |low: i64, high: i64| -> Vec<i32> {
+            bucket
+                .find_range("n", low, high)
+                .unwrap()
+                .into_iter()
+                .map(|offset| bucket.get_at(offset).unwrap().unwrap().get_i32("n").unwrap())
+                .collect()
+        };
+        assert_eq!(range(-5, 10), [-3, -1, 0, 5, 7]);
+        assert_eq!(range(-100, -1), [-100, -3, -1]);
+        assert_eq!(range(0, 0), [0]);
+        assert_eq!(range(8, 41), Vec::<i32>::new());
+        assert_eq!(range(i64::MIN, i64::MAX).len(), 10);
+        assert_eq!(range(i64::MIN, -101), [i32::MIN]);
+        assert!(range(10, -10).is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+use crate::{database::error::NonaneError, utils::pool::Ref};
+
+use super::{
+    document::Document,
+    reader::{Reader, ReaderFactory},
+};
+
+/// Streams the documents of a bucket one row at a time, see `Bucket::iter`
+///
+/// Holds a pooled reader until it's dropped. Deleted documents are skipped, iteration stops
+/// after the first error.
+pub struct BucketIter<'r, 'a> {
+    reader: Ref<'r, Reader<'a>, ReaderFactory<'a>>,
+    offset: u64,
+    end: u64,
+}
+
+impl<'r, 'a> BucketIter<'r, 'a> {
+    pub(crate) fn new(
+        mut reader: Ref<'r, Reader<'a>, ReaderFactory<'a>>,
+        start: u64,
+    ) -> Result<BucketIter<'r, 'a>, NonaneError> {
+        let end = reader.as_mut_ref().get_stored_offset()?;
+        Ok(BucketIter {
+            reader,
+            offset: start,
+            end,
+        })
+    }
+
+    /// Offset of the row the next call to `next` starts reading at
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl<'r, 'a> Iterator for BucketIter<'r, 'a> {
+    type Item = Result<Document, NonaneError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.offset < self.end {
+            let (document, len) = match self.reader.as_mut_ref().read_document_entry(self.offset) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    // The length of a broken row isn't known, nothing after it can be read
+                    self.offset = self.end;
+                    return Some(Err(e));
+                }
+            };
+
+            self.offset += len;
+            if let Some(document) = document {
+                return Some(Ok(document));
+            }
+        }
+
+        None
+    }
+}
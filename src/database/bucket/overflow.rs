@@ -0,0 +1,244 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Error, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use parking_lot::Mutex;
+
+/// Extension used for a bucket's overflow file
+pub static EXTENSION: &str = "blob";
+
+/// Points at a region within a bucket's overflow file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverflowRef {
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl OverflowRef {
+    /// Amount of bytes a reference takes up when stored within a field
+    pub const SIZE: usize = 16;
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0; OverflowRef::SIZE];
+        LittleEndian::write_u64(&mut buf[0..8], self.offset);
+        LittleEndian::write_u64(&mut buf[8..16], self.len);
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<OverflowRef> {
+        if bytes.len() != OverflowRef::SIZE {
+            return None;
+        }
+
+        Some(OverflowRef {
+            offset: LittleEndian::read_u64(&bytes[0..8]),
+            len: LittleEndian::read_u64(&bytes[8..16]),
+        })
+    }
+}
+
+/// Append-only file holding field data which has been moved out of a bucket's rows
+///
+/// The file is created the first time data is written to it.
+#[derive(Debug)]
+pub struct Overflow {
+    path: PathBuf,
+    file: Mutex<Option<File>>,
+}
+
+impl Overflow {
+    /// Creates an overflow handle for the bucket stored at `bucket_path`
+    pub fn new(bucket_path: &Path) -> Overflow {
+        Overflow {
+            path: bucket_path.with_extension(EXTENSION),
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Writes `parts` back to back at the end of the file
+    pub fn append(&self, parts: &[&[u8]]) -> std::io::Result<OverflowRef> {
+        let mut file = self.file.lock();
+        let file = Overflow::open(&self.path, &mut file)?;
+
+        let offset = file.seek(SeekFrom::End(0))?;
+        let mut len = 0;
+        for p in parts {
+            file.write_all(p)?;
+            len += p.len() as u64;
+        }
+
+        Ok(OverflowRef { offset, len })
+    }
+
+    /// Writes `parts` back to back at the end of the file, returning a reference to each of them
+    pub fn append_each(&self, parts: &[&[u8]]) -> std::io::Result<Vec<OverflowRef>> {
+        let mut file = self.file.lock();
+        let file = Overflow::open(&self.path, &mut file)?;
+
+        let mut offset = file.seek(SeekFrom::End(0))?;
+        let mut references = Vec::with_capacity(parts.len());
+        for p in parts {
+            file.write_all(p)?;
+            references.push(OverflowRef {
+                offset,
+                len: p.len() as u64,
+            });
+            offset += p.len() as u64;
+        }
+
+        Ok(references)
+    }
+
+    /// Drops the region `reference` points at, after the row referencing it couldn't be written
+    ///
+    /// Only a region at the end of the file is dropped, a region followed by another one is
+    /// kept as the other region's reference points past it.
+    pub fn discard(&self, reference: OverflowRef) -> std::io::Result<()> {
+        let mut file = self.file.lock();
+        let file = Overflow::open(&self.path, &mut file)?;
+
+        if file.seek(SeekFrom::End(0))? == reference.offset + reference.len {
+            file.set_len(reference.offset)?;
+        }
+
+        Ok(())
+    }
+
+    /// Extends the region `reference` points at with `extra`
+    ///
+    /// Regions at the end of the file grow in place, others are copied to the end of the file.
+    pub fn extend(&self, reference: OverflowRef, extra: &[u8]) -> std::io::Result<OverflowRef> {
+        {
+            let mut file = self.file.lock();
+            let file = Overflow::open(&self.path, &mut file)?;
+
+            let end = file.seek(SeekFrom::End(0))?;
+            if reference.offset + reference.len == end {
+                file.write_all(extra)?;
+                return Ok(OverflowRef {
+                    offset: reference.offset,
+                    len: reference.len + extra.len() as u64,
+                });
+            }
+        }
+
+        let existing = self.read(reference)?;
+        self.append(&[&existing, extra])
+    }
+
+    /// Reads the region `reference` points at
+    pub fn read(&self, reference: OverflowRef) -> std::io::Result<Vec<u8>> {
+        let mut file = self.file.lock();
+        let file = Overflow::open(&self.path, &mut file)?;
+
+        let end = file.seek(SeekFrom::End(0))?;
+        if reference.offset + reference.len > end {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "overflow reference points past the end of the overflow file",
+            ));
+        }
+
+        let mut buf = vec![0; reference.len as usize];
+        file.seek(SeekFrom::Start(reference.offset))?;
+        file.read_exact(&mut buf)?;
+
+        Ok(buf)
+    }
+
+    fn open<'f>(path: &Path, file: &'f mut Option<File>) -> std::io::Result<&'f mut File> {
+        if file.is_none() {
+            *file = Some(
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(path)?,
+            );
+        }
+
+        Ok(file.as_mut().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        database::{
+            bucket::{
+                config::BucketConfiguration,
+                descriptor::BucketDescription,
+                document::{
+                    field::{descriptor::FieldDescriptor, fieldtype::FieldType, Field},
+                    Document,
+                },
+            },
+            error::NonaneError,
+            Database,
+        },
+        utils::testing::temp_path,
+    };
+
+    use super::EXTENSION;
+
+    fn blob_description() -> BucketDescription {
+        BucketDescription::new(vec![
+            FieldDescriptor::new("name", FieldType::Text).unwrap(),
+            FieldDescriptor::new("blob", FieldType::Bytes).unwrap(),
+        ])
+    }
+
+    fn blob_document(name: &str, blob: Vec<u8>) -> Document {
+        Document::new(vec![
+            Field::new("name", name).unwrap(),
+            Field::new("blob", blob).unwrap(),
+        ])
+    }
+
+    #[test]
+    fn large_blob_is_kept_out_of_the_bucket_file() {
+        let config = BucketConfiguration {
+            overflow_min_bytes: Some(4096),
+            ..Default::default()
+        };
+        let mut db = Database::open(temp_path("overflow-large-blob")).unwrap();
+        db.open_bucket("blobs", Some(blob_description()), Some(config)).unwrap();
+        let mut bucket = db.get_bucket_mut("blobs").unwrap();
+
+        let blob: Vec<u8> = (0..10 << 20).map(|i| (i % 251) as u8).collect();
+        let (large, _) = bucket.insert(&blob_document("large", blob.clone())).unwrap();
+        let (small, _) = bucket.insert(&blob_document("small", vec![1; 10])).unwrap();
+        bucket.flush().unwrap();
+
+        assert!(bucket.file_size().unwrap() < 64 * 1024);
+        let overflow = std::fs::metadata(bucket.path().with_extension(EXTENSION)).unwrap();
+        assert_eq!(overflow.len(), 10 << 20);
+
+        let document = bucket.get_at(large as u64).unwrap().unwrap();
+        assert_eq!(document.get_bytes("blob"), Some(&blob[..]));
+        let document = bucket.get_at(small as u64).unwrap().unwrap();
+        assert_eq!(document.get_bytes("blob"), Some(&[1; 10][..]));
+    }
+
+    #[test]
+    fn failed_insert_drops_its_overflow() {
+        let config = BucketConfiguration {
+            overflow_min_bytes: Some(4096),
+            max_bucket_bytes: Some(1),
+            ..Default::default()
+        };
+        let mut db = Database::open(temp_path("overflow-failed-insert")).unwrap();
+        db.open_bucket("blobs", Some(blob_description()), Some(config)).unwrap();
+        let mut bucket = db.get_bucket_mut("blobs").unwrap();
+
+        let res = bucket.insert(&blob_document("large", vec![7; 1 << 20]));
+        assert!(matches!(res, Err(NonaneError::BucketQuotaExceeded { .. })));
+
+        let overflow = std::fs::metadata(bucket.path().with_extension(EXTENSION)).unwrap();
+        assert_eq!(overflow.len(), 0);
+    }
+}
@@ -1,33 +1,179 @@
-use std::{convert::TryInto, fs::{File, OpenOptions}, io::{Seek, SeekFrom}, path::Path, sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}}};
+use std::{convert::TryInto, ffi::CString, fs::{File, OpenOptions}, io::{Cursor, Error, ErrorKind, Read, Seek, SeekFrom}, ops::{Deref, DerefMut}, path::{Path, PathBuf}, sync::{Arc, atomic::{AtomicUsize, Ordering}}, time::Duration};
 
-use byteorder::{LittleEndian, ReadBytesExt};
-use parking_lot::{Mutex, RawMutex, lock_api::MutexGuard};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+use memmap2::Mmap;
+use parking_lot::{Mutex, RawMutex, RwLock, RwLockReadGuard, lock_api::MutexGuard};
+
+use crate::{
+    database::error::NonaneError,
+    utils::pool::{Factory, Ref},
+};
+
+use super::{
+    document::{
+        field::{fieldtype::FieldType, Field},
+        raw, Document,
+    },
+    overflow::{Overflow, OverflowRef},
+    encryption::{self, EncryptionKey},
+    row::{self, Compression, RowHeader},
+    schema::Schema,
+    writer::count_location,
+};
+
+/// Longest a read waits for a write in progress to finish, see `Reader::read_guard`
+pub const MAX_WRITE_WAIT: Duration = Duration::from_millis(100);
+
+/// A row read by `Reader::read_row_entry`
+#[derive(Debug)]
+pub struct RowEntry {
+    /// The row's decompressed payload without padding and the document parsed from it, `None`
+    /// if the document was deleted
+    pub contents: Option<(Vec<u8>, Document)>,
+    /// Length of the row, including its header and padding
+    pub len: u64,
+}
 
 #[derive(Clone, Debug)]
 pub struct Reader<'a> {
     name: &'a str,
     file: Arc<Mutex<File>>,
-    will_write: Arc<AtomicBool>,
+    will_write: Arc<RwLock<()>>,
     offset: Option<Arc<AtomicUsize>>,
+    overflow: Option<Arc<Overflow>>,
+    schema: Option<Arc<RwLock<Schema>>>,
+    key: Option<EncryptionKey>,
+    mapping: Option<Arc<Mutex<Mapping>>>,
+}
+
+/// The file of a reader, borrowed while no write is in progress, see `Reader::read_guard`
+pub struct ReadGuard<'r> {
+    file: MutexGuard<'r, RawMutex, File>,
+    _shared: RwLockReadGuard<'r, ()>,
+}
+
+impl Deref for ReadGuard<'_> {
+    type Target = File;
+
+    fn deref(&self) -> &File {
+        &self.file
+    }
+}
+
+impl DerefMut for ReadGuard<'_> {
+    fn deref_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+}
+
+/// Read-only mapping of a bucket's file, see `BucketConfiguration::memory_mapped`
+///
+/// The file is mapped on the first read and mapped again once a read reaches past the end of
+/// the mapping, rows appended by the writer are past it until then.
+#[derive(Debug, Default)]
+struct Mapping(Option<Mmap>);
+
+impl Mapping {
+    /// The `len` bytes of `file` from `offset`
+    fn slice(&mut self, file: &File, offset: u64, len: u64) -> std::io::Result<&[u8]> {
+        let end = offset
+            .checked_add(len)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "row ends past the largest offset"))?
+            as usize;
+
+        if !matches!(&self.0, Some(map) if map.len() >= end) {
+            // Safety: the file of a bucket is only shrunk by `Bucket::compact`, reads never
+            // reach past the stored offset so they stay within the file
+            self.0 = Some(unsafe { Mmap::map(file)? });
+        }
+
+        self.0
+            .as_ref()
+            .and_then(|map| map.get(offset as usize..end))
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "row ends past the end of the file"))
+    }
+}
+
+/// Reads the header of the row stored at `offset`, from `mapping` if the file is mapped
+fn read_header(
+    file: &mut File,
+    mapping: Option<&mut Mapping>,
+    offset: u64,
+) -> std::io::Result<RowHeader> {
+    let raw = match mapping {
+        Some(mapping) => LittleEndian::read_u64(mapping.slice(file, offset, row::HEADER_SIZE)?),
+        None => {
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_u64::<LittleEndian>()?
+        }
+    };
+
+    Ok(RowHeader::from_raw(raw))
 }
 
 impl<'a> Reader<'a> {
-    pub fn new(name: &'a str, path: &Path, will_write: Arc<AtomicBool>, offset: Option<Arc<AtomicUsize>>) -> Result<Reader<'a>, Box<dyn std::error::Error>> {
+    pub fn new(name: &'a str, path: &Path, will_write: Arc<RwLock<()>>, offset: Option<Arc<AtomicUsize>>, overflow: Option<Arc<Overflow>>) -> std::io::Result<Reader<'a>> {
         let file = OpenOptions::new().read(true).open(&path)?;
         let reader = Reader {
             name,
             file: Arc::new(Mutex::new(file)),
             will_write,
-            offset
+            offset,
+            overflow,
+            schema: None,
+            key: None,
+            mapping: None,
         };
-        
+
         Ok(reader)
     }
 
+    /// Gives the reader the bucket's schema, required to read rows storing field names by
+    /// position and to fill in the defaults of added fields
+    pub fn with_schema(mut self, schema: Arc<RwLock<Schema>>) -> Reader<'a> {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Gives the reader the key required to read encrypted rows
+    pub fn with_key(mut self, key: Option<EncryptionKey>) -> Reader<'a> {
+        self.key = key;
+        self
+    }
+
+    /// Reads rows from a memory mapping of the file instead of seeking, see
+    /// `BucketConfiguration::memory_mapped`
+    pub fn with_mapping(mut self, mapped: bool) -> Reader<'a> {
+        self.mapping = mapped.then(|| Arc::new(Mutex::new(Mapping::default())));
+        self
+    }
+
+    /// Codec of the bucket's compressed rows, rows are read as snappy without a schema
+    fn compression(&self) -> Compression {
+        self.schema
+            .as_ref()
+            .map_or(Compression::default(), |s| s.read().compression)
+    }
+
     pub fn borrow_file(&mut self) -> MutexGuard<RawMutex, File> {
         self.file.lock()
     }
 
+    /// Borrows the file once no write is in progress, so rows read through it are complete
+    ///
+    /// Writes hold the bucket's `will_write` lock exclusively, see `Bucket::begin_write`. The
+    /// returned guard shares it, so writes wait until it's dropped. Errors with
+    /// `ErrorKind::WouldBlock` if a write still holds it after `MAX_WRITE_WAIT`.
+    pub fn read_guard(&mut self) -> std::io::Result<ReadGuard<'_>> {
+        let shared = self.will_write.try_read_for(MAX_WRITE_WAIT).ok_or_else(|| {
+            Error::new(ErrorKind::WouldBlock, "a write to the bucket is still in progress")
+        })?;
+        Ok(ReadGuard {
+            file: self.file.lock(),
+            _shared: shared,
+        })
+    }
+
     /// Get the current offset for next document
     pub fn get_offset(&mut self) -> std::io::Result<u64> {
         if self.offset.is_some() {
@@ -36,6 +182,13 @@ impl<'a> Reader<'a> {
             return Ok(offset as u64);
         }
 
+        self.get_stored_offset()
+    }
+
+    /// Get the offset for the next document as it's stored on disk
+    ///
+    /// Trails behind `get_offset` while writes are still queued.
+    pub fn get_stored_offset(&mut self) -> std::io::Result<u64> {
         let offset = (page_size::get() - std::mem::size_of::<u64>() * 2)
             .try_into()
             .unwrap();
@@ -46,7 +199,494 @@ impl<'a> Reader<'a> {
 
         Ok(val)
     }
+
+    /// Get the amount of documents stored, see `Bucket::count_documents`
+    pub fn get_stored_count(&mut self) -> std::io::Result<u64> {
+        let mut f = self.borrow_file();
+        f.seek(SeekFrom::Start(count_location()))?;
+        f.read_u64::<LittleEndian>()
+    }
+
+    /// Reads the row stored at `offset`, returning the serialized document
+    ///
+    /// Encrypted rows are decrypted and compressed rows are decompressed, otherwise the document
+    /// is returned including its padding. Rows storing their field names by position aren't laid
+    /// out like a serialized `Document`, see `RowHeader::is_interned`.
+    pub fn read_row(&mut self, offset: u64) -> std::io::Result<Vec<u8>> {
+        let (header, mut payload) = self.read_raw_row(offset)?;
+        if header.is_deleted() {
+            return Err(Error::new(ErrorKind::NotFound, "document was deleted"));
+        }
+
+        if header.is_encrypted() {
+            payload = encryption::decrypt(&payload, self.key.as_ref())
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        }
+
+        if header.is_compressed() {
+            return row::decompress(&payload, self.compression());
+        }
+
+        Ok(payload)
+    }
+
+    /// Reads the header and payload of the row stored at `offset` as they're stored on disk
+    ///
+    /// Deleted rows are returned as well, see `RowHeader::is_deleted`.
+    pub fn read_raw_row(&mut self, offset: u64) -> std::io::Result<(RowHeader, Vec<u8>)> {
+        let mapping = self.mapping.clone();
+        let mut mapping = mapping.as_ref().map(|m| m.lock());
+        let mut f = self.read_guard()?;
+        let header = read_header(&mut f, mapping.as_deref_mut(), offset)?;
+        if header.len <= row::HEADER_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "no document is stored at offset",
+            ));
+        }
+
+        let len = header.len - row::HEADER_SIZE;
+        let buf = match mapping.as_deref_mut() {
+            Some(mapping) => mapping.slice(&f, offset + row::HEADER_SIZE, len)?.to_vec(),
+            None => {
+                // An offset pointing into a row, like one held on to over a compaction, reads
+                // part of the row as its length
+                if offset + header.len > f.metadata()?.len() {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "row ends past the end of the file",
+                    ));
+                }
+
+                let mut buf = vec![0; len as usize];
+                f.read_exact(&mut buf)?;
+                buf
+            }
+        };
+
+        Ok((header, buf))
+    }
+
+    /// Reads a single field of the document stored at `offset`
+    ///
+    /// Only the field names and lengths of the other fields are read, their data is skipped.
+    /// Compressed rows and rows storing their field names by position are read as a whole.
+    pub fn read_field_at(&mut self, offset: u64, key: &str) -> Result<Option<Field>, NonaneError> {
+        let field = {
+            let mapping = self.mapping.clone();
+            let mut mapping = mapping.as_ref().map(|m| m.lock());
+            let mut f = self.read_guard()?;
+            let header = read_header(&mut f, mapping.as_deref_mut(), offset)?;
+            if header.len <= row::HEADER_SIZE {
+                return Err(NonaneError::Io(Error::new(
+                    ErrorKind::InvalidData,
+                    "no document is stored at offset",
+                )));
+            }
+
+            if header.is_deleted() {
+                return Err(NonaneError::NoDocumentAt { offset });
+            }
+
+            let len = header.len - row::HEADER_SIZE;
+            if header.is_compressed() || header.is_interned() || header.is_encrypted() {
+                None
+            } else if let Some(mapping) = mapping.as_deref_mut() {
+                let row = mapping.slice(&f, offset + row::HEADER_SIZE, len)?;
+                Some(raw::read_field_from(&mut Cursor::new(row), len, key)?)
+            } else {
+                Some(raw::read_field_from(&mut *f, len, key)?)
+            }
+        };
+
+        let field = match field {
+            Some(field) => field,
+            None => return Ok(self.read_document_at(offset)?.read_field(key).cloned()),
+        };
+
+        let dropped = self.schema.as_ref().is_some_and(|s| s.read().is_dropped(key));
+        let (field_type, data) = match field {
+            Some(f) if !dropped => f,
+            _ => {
+                let schema = self.schema.as_ref().map(|s| s.read());
+                return Ok(schema.and_then(|s| s.get_default(key).cloned()));
+            }
+        };
+
+        let name = CString::new(key).map_err(|_| {
+            Error::new(ErrorKind::InvalidInput, "field name contains a nul byte")
+        })?;
+        let mut field = Field::from_raw(name, field_type, data);
+        self.resolve_overflow(&mut field)?;
+
+        Ok(Some(field))
+    }
+
+    /// Reads and deserializes the document stored at `offset`
+    ///
+    /// Fields which have been moved to the overflow file are read back as regular `Bytes` fields,
+    /// which requires the reader to have been created with the bucket's overflow file. A deleted
+    /// document errors with `NonaneError::NoDocumentAt`.
+    pub fn read_document_at(&mut self, offset: u64) -> Result<Document, NonaneError> {
+        self.read_document_entry(offset)?
+            .0
+            .ok_or(NonaneError::NoDocumentAt { offset })
+    }
+
+    /// Reads the document stored at `offset` along with the length of its row, the document is
+    /// `None` if it was deleted
+    pub fn read_document_entry(
+        &mut self,
+        offset: u64,
+    ) -> Result<(Option<Document>, u64), NonaneError> {
+        let entry = self.read_row_entry(offset)?;
+        Ok((entry.contents.map(|(_, document)| document), entry.len))
+    }
+
+    /// Reads the row stored at `offset` as its decompressed payload without padding and the
+    /// document parsed from it, along with the length of the row
+    pub fn read_row_entry(&mut self, offset: u64) -> Result<RowEntry, NonaneError> {
+        let (header, mut row) = self.read_raw_row(offset).map_err(|e| match e.kind() {
+            ErrorKind::InvalidData => NonaneError::NoDocumentAt { offset },
+            _ => e.into(),
+        })?;
+        if header.is_deleted() {
+            return Ok(RowEntry {
+                contents: None,
+                len: header.len,
+            });
+        }
+
+        if header.is_encrypted() {
+            row = encryption::decrypt(&row, self.key.as_ref())?;
+        }
+
+        if header.is_compressed() {
+            row = row::decompress(&row, self.compression())?;
+        }
+
+        let mut rest = row.as_slice();
+        let mut document = if header.is_interned() {
+            let schema = self.schema.as_ref().ok_or_else(|| {
+                Error::new(ErrorKind::Unsupported, "reader has no access to the field names")
+            })?;
+            Document::deserialize_interned(&mut rest, &schema.read().names)?
+        } else {
+            Document::deserialize_from(&mut rest)?
+        };
+
+        // Drop the padding following the document
+        let padding = rest.len();
+        row.truncate(row.len() - padding);
+
+        for f in document.get_fields_mut().iter_mut() {
+            self.resolve_overflow(f)?;
+        }
+
+        if let Some(schema) = self.schema.as_ref() {
+            schema.read().apply(&mut document);
+        }
+
+        Ok(RowEntry {
+            contents: Some((row, document)),
+            len: header.len,
+        })
+    }
+
+    /// Reads the bytes of a field which has been moved to the overflow file back into it
+    fn resolve_overflow(&self, field: &mut Field) -> Result<(), NonaneError> {
+        if *field.get_type() != FieldType::Overflow {
+            return Ok(());
+        }
+
+        let overflow = self.overflow.as_ref().ok_or_else(|| {
+            Error::new(ErrorKind::Unsupported, "reader has no access to the overflow file")
+        })?;
+        let reference = OverflowRef::from_bytes(field.get_data()).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, "malformed overflow reference")
+        })?;
+
+        let data = overflow.read(reference)?;
+        field.replace_data(FieldType::Bytes, data);
+        Ok(())
+    }
+}
+
+/// A pooled reader limited to the rows written when it was leased, see `Bucket::lease_reader`
+///
+/// Only rows appended afterwards are hidden, changes to the visible rows show through. The
+/// reader is returned to the pool when the lease is dropped.
+pub struct ReaderLease<'r, 'a> {
+    reader: Ref<'r, Reader<'a>, ReaderFactory<'a>>,
+    end: u64,
+    start: u64,
+}
+
+impl<'r, 'a> ReaderLease<'r, 'a> {
+    pub(crate) fn new(
+        mut reader: Ref<'r, Reader<'a>, ReaderFactory<'a>>,
+        start: u64,
+    ) -> Result<ReaderLease<'r, 'a>, NonaneError> {
+        let end = reader.as_mut_ref().get_stored_offset()?;
+        Ok(ReaderLease { reader, end, start })
+    }
+
+    /// Offset right after the last row visible through the lease
+    pub fn snapshot_offset(&self) -> u64 {
+        self.end
+    }
+
+    /// Reads the document stored at `offset`, `None` if it was written after the lease was taken
+    /// or has been deleted
+    pub fn read_document_at(&mut self, offset: u64) -> Result<Option<Document>, NonaneError> {
+        if offset >= self.end {
+            return Ok(None);
+        }
+
+        Ok(self.reader.as_mut_ref().read_document_entry(offset)?.0)
+    }
+
+    /// Reads a single field of the document stored at `offset`, see `Reader::read_field_at`
+    pub fn read_field_at(&mut self, offset: u64, key: &str) -> Result<Option<Field>, NonaneError> {
+        if offset >= self.end {
+            return Ok(None);
+        }
+
+        self.reader.as_mut_ref().read_field_at(offset, key)
+    }
+
+    /// Calls `f` with the offset and contents of every document visible through the lease
+    pub fn for_each_document<F: FnMut(u64, &Document)>(&mut self, mut f: F) -> Result<(), NonaneError> {
+        let mut offset = self.start;
+        while offset < self.end {
+            let (document, len) = self.reader.as_mut_ref().read_document_entry(offset)?;
+            if let Some(document) = document {
+                f(offset, &document);
+            }
+            offset += len;
+        }
+
+        Ok(())
+    }
+}
+
+/// Opens the readers pooled for a bucket
+pub struct ReaderFactory<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) path: PathBuf,
+    pub(crate) will_write: Arc<RwLock<()>>,
+    pub(crate) offset: Arc<AtomicUsize>,
+    pub(crate) overflow: Arc<Overflow>,
+    pub(crate) schema: Arc<RwLock<Schema>>,
+    pub(crate) key: Option<EncryptionKey>,
+    pub(crate) memory_mapped: bool,
+}
+
+impl<'a> Factory<Reader<'a>> for ReaderFactory<'a> {
+    type Error = NonaneError;
+
+    fn create(&self) -> Result<Reader<'a>, NonaneError> {
+        let reader = Reader::new(
+            self.name,
+            &self.path,
+            self.will_write.clone(),
+            Some(self.offset.clone()),
+            Some(self.overflow.clone()),
+        )?;
+
+        Ok(reader
+            .with_schema(self.schema.clone())
+            .with_key(self.key)
+            .with_mapping(self.memory_mapped))
+    }
 }
 
 unsafe impl<'a> Send for Reader<'a> {}
 unsafe impl<'a> Sync for Reader<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::ErrorKind, thread, time::Instant};
+
+    use crate::{
+        database::{
+            bucket::{
+                config::BucketConfiguration,
+                descriptor::BucketDescription,
+                document::{
+                    field::{descriptor::FieldDescriptor, fieldtype::FieldType, Field},
+                    Document,
+                },
+                Bucket,
+            },
+            error::NonaneError,
+            Database,
+        },
+        utils::testing::{int_description, int_document, temp_path},
+    };
+
+    use super::MAX_WRITE_WAIT;
+
+    fn filled(c: char) -> Document {
+        let text: String = std::iter::repeat_n(c, 64 * 1024).collect();
+        Document::new(vec![Field::new("text", text).unwrap()])
+    }
+
+    #[test]
+    fn reads_never_see_a_row_mid_write() {
+        let description =
+            BucketDescription::new(vec![FieldDescriptor::new("text", FieldType::Text).unwrap()]);
+        let config = BucketConfiguration {
+            synchronous: true,
+            ..Default::default()
+        };
+        let mut db = Database::open(temp_path("reader-mid-write")).unwrap();
+        db.open_bucket("texts", Some(description), Some(config)).unwrap();
+        let mut bucket = db.get_bucket("texts").unwrap().clone();
+        let offset = bucket.insert(&filled('a')).unwrap().0 as u64;
+
+        // Rewrites the row in place over and over, while it's read
+        let reader = bucket.clone();
+        let writes = thread::spawn(move || {
+            for i in 0..40 {
+                let c = if i % 2 == 0 { 'b' } else { 'a' };
+                assert_eq!(bucket.update_at(offset, &filled(c)).unwrap().0, offset);
+            }
+        });
+
+        while !writes.is_finished() {
+            let document = reader.get_at(offset).unwrap().unwrap();
+            let text = document.read_field("text").unwrap().get_value::<&str>().unwrap();
+            assert!(text.chars().all(|c| c == 'a') || text.chars().all(|c| c == 'b'));
+        }
+        writes.join().unwrap();
+    }
+
+    #[test]
+    fn reads_give_up_on_a_write_held_past_the_bound() {
+        let mut db = Database::open(temp_path("reader-write-held")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        let offset = bucket.insert(&int_document(1)).unwrap().0 as u64;
+        bucket.flush().unwrap();
+
+        let mut reader = bucket.readers.as_ref().unwrap().pull();
+        let reader = reader.as_mut_ref();
+        let guard = bucket.begin_write();
+        let started = Instant::now();
+        let err = reader.read_raw_row(offset).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+        assert!(started.elapsed() >= MAX_WRITE_WAIT);
+
+        drop(guard);
+        assert!(reader.read_raw_row(offset).is_ok());
+    }
+
+    #[test]
+    fn read_document_at_reads_a_known_document() {
+        let mut db = Database::open(temp_path("reader-read-document-at")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        let first = bucket.insert(&int_document(1)).unwrap().0 as u64;
+        let second = bucket.insert(&int_document(2)).unwrap().0 as u64;
+        bucket.flush().unwrap();
+        assert!(bucket.delete_at(first).unwrap());
+        bucket.flush().unwrap();
+
+        let mut reader = bucket.readers.as_ref().unwrap().pull();
+        let reader = reader.as_mut_ref();
+        assert_eq!(reader.read_document_at(second).unwrap().get_i32("n"), Some(2));
+        assert!(matches!(
+            reader.read_document_at(first),
+            Err(NonaneError::NoDocumentAt { offset }) if offset == first
+        ));
+    }
+
+    #[test]
+    fn mapped_point_reads_match_seeking_reads() {
+        let mapped = BucketConfiguration {
+            memory_mapped: true,
+            ..Default::default()
+        };
+        let mut db = Database::open(temp_path("reader-mapped")).unwrap();
+        db.open_bucket("mapped", Some(int_description()), Some(mapped)).unwrap();
+        db.open_bucket("seeking", Some(int_description()), None).unwrap();
+        let mut mapped = db.get_bucket("mapped").unwrap().clone();
+        let mut seeking = db.get_bucket("seeking").unwrap().clone();
+        assert!(mapped.readers.as_ref().unwrap().pull().as_mut_ref().mapping.is_some());
+
+        let mut offsets = Vec::new();
+        for round in 0..2 {
+            for n in round * 500..(round + 1) * 500 {
+                let offset = mapped.insert(&int_document(n)).unwrap().0 as u64;
+                assert_eq!(seeking.insert(&int_document(n)).unwrap().0 as u64, offset);
+                offsets.push(offset);
+            }
+            mapped.flush().unwrap();
+            seeking.flush().unwrap();
+
+            // Visits every row in a scattered order, the second round reads past the mapping
+            let scattered: Vec<u64> = (0..offsets.len())
+                .map(|i| offsets[i * 7919 % offsets.len()])
+                .collect();
+            let read = |bucket: &Bucket| {
+                let t = Instant::now();
+                let numbers: Vec<i32> = scattered
+                    .iter()
+                    .map(|o| bucket.get_at(*o).unwrap().unwrap().get_i32("n").unwrap())
+                    .collect();
+                (numbers, t.elapsed())
+            };
+            let (from_mapping, mapped_time) = read(&mapped);
+            let (from_seeking, seeking_time) = read(&seeking);
+            // Only reported, see `cargo test -- --nocapture`, timings are too noisy to assert on
+            println!(
+                "{} point reads took {:?} mapped and {:?} seeking",
+                scattered.len(),
+                mapped_time,
+                seeking_time
+            );
+            assert_eq!(from_mapping, from_seeking);
+        }
+    }
+
+    #[test]
+    fn lease_hides_rows_inserted_while_it_is_held() {
+        let mut db = Database::open(temp_path("reader-lease")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        for n in 0..5 {
+            bucket.insert(&int_document(n)).unwrap();
+        }
+        bucket.flush().unwrap();
+
+        let mut lease = bucket.lease_reader().unwrap();
+        assert_eq!(lease.snapshot_offset(), bucket.disk_usage());
+
+        // Inserts keep going through a clone while the lease is read
+        let mut inserting = bucket.clone();
+        let inserts = thread::spawn(move || {
+            let offsets: Vec<u64> = (5..50)
+                .map(|n| inserting.insert(&int_document(n)).unwrap().0 as u64)
+                .collect();
+            inserting.flush().unwrap();
+            offsets
+        });
+
+        let mut seen = Vec::new();
+        lease.for_each_document(|_, document| seen.push(document.get_i32("n").unwrap())).unwrap();
+        assert_eq!(seen, (0..5).collect::<Vec<_>>());
+
+        let offsets = inserts.join().unwrap();
+        for offset in offsets {
+            assert_eq!(lease.read_document_at(offset).unwrap(), None);
+            assert!(lease.read_field_at(offset, "n").unwrap().is_none());
+        }
+
+        let mut seen = 0;
+        lease.for_each_document(|_, _| seen += 1).unwrap();
+        assert_eq!(seen, 5);
+        drop(lease);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 50);
+    }
+}
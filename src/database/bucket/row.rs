@@ -0,0 +1,299 @@
+//! Layout of the rows stored within a bucket
+//!
+//! Every row starts with a u64 header, its low bits hold the length of the row (including the
+//! header and padding) and its high bits hold flags describing how the document is stored.
+
+use std::io::{Error, ErrorKind};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// Amount of bytes taken up by a row's header
+pub const HEADER_SIZE: u64 = std::mem::size_of::<u64>() as u64;
+
+/// The document is compressed, prefixed by the length of the compressed bytes
+pub const COMPRESSED: u64 = 1 << 63;
+
+/// Field names are stored as their position within the bucket's fields
+pub const INTERNED: u64 = 1 << 62;
+
+/// The document was deleted, the row keeps its length so scans can step over it
+///
+/// A header of zero still marks space past the last row, a deleted row is never read as one.
+pub const DELETED: u64 = 1 << 61;
+
+/// The document is encrypted after being compressed, see `encryption::encrypt`
+pub const ENCRYPTED: u64 = 1 << 60;
+
+/// Bits of the header used for flags
+const FLAGS: u64 = COMPRESSED | INTERNED | DELETED | ENCRYPTED;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RowHeader {
+    pub len: u64,
+    pub flags: u64,
+}
+
+impl RowHeader {
+    pub fn new(len: u64, flags: u64) -> RowHeader {
+        RowHeader { len, flags }
+    }
+
+    /// Splits a header read from disk into its length and flags
+    pub fn from_raw(raw: u64) -> RowHeader {
+        RowHeader {
+            len: raw & !FLAGS,
+            flags: raw & FLAGS,
+        }
+    }
+
+    pub fn to_raw(&self) -> u64 {
+        self.len | (self.flags & FLAGS)
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.flags & COMPRESSED != 0
+    }
+
+    pub fn is_interned(&self) -> bool {
+        self.flags & INTERNED != 0
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.flags & DELETED != 0
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & ENCRYPTED != 0
+    }
+
+    /// Whether the header marks the end of the rows, as its length doesn't reach past it
+    ///
+    /// Zeroed space, like the space left behind a crash or preallocated by `Bucket::reserve`,
+    /// reads as such a header.
+    pub fn ends_data(&self) -> bool {
+        self.len <= HEADER_SIZE
+    }
+}
+
+/// Codec the compressed rows of a bucket are stored with, kept in its descriptor
+///
+/// Whether a row is compressed at all is decided by `BucketConfiguration::compress_min_bytes`,
+/// `None` keeps every row of the bucket uncompressed regardless.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Compression {
+    None,
+    /// Rows compressed before the codec could be chosen were stored with snappy
+    #[default]
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+/// Level zstd compresses rows at
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compresses a serialized document, the result is prefixed with its length so the row's padding
+/// can be told apart from the compressed bytes
+///
+/// Returns `None` for `Compression::None`.
+pub fn compress(data: &[u8], codec: Compression) -> std::io::Result<Option<Vec<u8>>> {
+    let compressed = match codec {
+        Compression::None => return Ok(None),
+        Compression::Snappy => snap::raw::Encoder::new().compress_vec(data)?,
+        Compression::Lz4 => lz4_flex::compress_prepend_size(data),
+        Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL)?,
+    };
+
+    let mut buf = vec![0; std::mem::size_of::<u64>()];
+    LittleEndian::write_u64(&mut buf, compressed.len() as u64);
+    buf.extend_from_slice(&compressed);
+    Ok(Some(buf))
+}
+
+/// Decompresses the payload of a compressed row, ignoring its padding
+pub fn decompress(payload: &[u8], codec: Compression) -> std::io::Result<Vec<u8>> {
+    let prefix = std::mem::size_of::<u64>();
+    if payload.len() < prefix {
+        return Err(Error::new(ErrorKind::InvalidData, "malformed compressed row"));
+    }
+
+    let len = LittleEndian::read_u64(&payload[..prefix]) as usize;
+    let compressed = payload
+        .get(prefix..prefix + len)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "malformed compressed row"))?;
+
+    match codec {
+        Compression::None => Err(Error::new(
+            ErrorKind::InvalidData,
+            "compressed row in a bucket without compression",
+        )),
+        Compression::Snappy => Ok(snap::raw::Decoder::new().decompress_vec(compressed)?),
+        Compression::Lz4 => lz4_flex::decompress_size_prepended(compressed)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+        Compression::Zstd => zstd::stream::decode_all(compressed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        database::{
+            bucket::{
+                config::BucketConfiguration,
+                descriptor::BucketDescription,
+                document::{
+                    field::{descriptor::FieldDescriptor, fieldtype::FieldType, Field},
+                    Document,
+                },
+            },
+            Database,
+        },
+        utils::testing::temp_path,
+    };
+
+    use super::{Compression, HEADER_SIZE};
+
+    fn text_document(text: String) -> Document {
+        Document::new(vec![Field::new("text", text).unwrap()])
+    }
+
+    #[test]
+    fn only_rows_past_the_threshold_are_compressed() {
+        let description =
+            BucketDescription::new(vec![FieldDescriptor::new("text", FieldType::Text).unwrap()]);
+        let config = BucketConfiguration {
+            compress_min_bytes: Some(256),
+            ..Default::default()
+        };
+        let mut db = Database::open(temp_path("row-compress-min-bytes")).unwrap();
+        db.open_bucket("texts", Some(description), Some(config)).unwrap();
+        let mut bucket = db.get_bucket_mut("texts").unwrap();
+
+        let small = text_document("hi".to_string());
+        let large = text_document("a".repeat(10 * 1024));
+        let small_offset = bucket.insert(&small).unwrap().0 as u64;
+        let large_offset = bucket.insert(&large).unwrap().0 as u64;
+        bucket.flush().unwrap();
+
+        {
+            let mut reader = bucket.readers.as_ref().unwrap().pull();
+            let reader = reader.as_mut_ref();
+            let (header, _) = reader.read_raw_row(small_offset).unwrap();
+            assert!(!header.is_compressed());
+            let (header, _) = reader.read_raw_row(large_offset).unwrap();
+            assert!(header.is_compressed());
+            assert!(header.len < 10 * 1024);
+        }
+
+        let read = bucket.get_at(small_offset).unwrap().unwrap();
+        assert_eq!(read.read_field("text").unwrap().get_value::<&str>(), Some("hi"));
+        let read = bucket.get_at(large_offset).unwrap().unwrap();
+        let text = read.read_field("text").unwrap().get_value::<&str>().unwrap();
+        assert_eq!(text, "a".repeat(10 * 1024));
+    }
+
+    /// Length of the row the document is stored in, in a bucket of the given configuration
+    fn row_len(name: &str, document: &Document, config: BucketConfiguration) -> u64 {
+        let description = BucketDescription::new(vec![
+            FieldDescriptor::new("first_name", FieldType::Text).unwrap(),
+            FieldDescriptor::new("family_name", FieldType::Text).unwrap(),
+            FieldDescriptor::new("age_in_years", FieldType::Int32).unwrap(),
+        ]);
+        let mut db = Database::open(temp_path(name)).unwrap();
+        db.open_bucket("people", Some(description), Some(config)).unwrap();
+        let mut bucket = db.get_bucket_mut("people").unwrap();
+        let offset = bucket.insert(document).unwrap().0 as u64;
+        bucket.flush().unwrap();
+
+        assert_eq!(bucket.get_at(offset).unwrap().unwrap().get_i32("age_in_years"), Some(36));
+        let mut reader = bucket.readers.as_ref().unwrap().pull();
+        reader.as_mut_ref().read_raw_row(offset).unwrap().0.len
+    }
+
+    #[test]
+    fn interned_field_names_shrink_rows() {
+        let document = Document::new(vec![
+            Field::new("first_name", "Ada".to_string()).unwrap(),
+            Field::new("family_name", "Lovelace".to_string()).unwrap(),
+            Field::new("age_in_years", 36).unwrap(),
+        ]);
+        let interned = BucketConfiguration {
+            intern_field_names: true,
+            ..Default::default()
+        };
+
+        let full = row_len("row-names-full", &document, BucketConfiguration::default());
+        let interned = row_len("row-names-interned", &document, interned);
+        assert!(interned < full);
+    }
+
+    #[test]
+    fn unpadded_rows_take_up_exactly_their_length() {
+        let description =
+            BucketDescription::new(vec![FieldDescriptor::new("text", FieldType::Text).unwrap()]);
+        let config = BucketConfiguration {
+            pad_rows: false,
+            ..Default::default()
+        };
+        let mut db = Database::open(temp_path("row-unpadded")).unwrap();
+        db.open_bucket("texts", Some(description), Some(config)).unwrap();
+        let mut bucket = db.get_bucket_mut("texts").unwrap();
+
+        let texts = ["a", "bcd", "efghijk"];
+        let offsets: Vec<u64> = texts
+            .iter()
+            .map(|text| bucket.insert(&text_document(text.to_string())).unwrap().0 as u64)
+            .collect();
+        bucket.flush().unwrap();
+
+        let mut lens = Vec::new();
+        for (offset, text) in offsets.iter().zip(texts.iter()) {
+            let read = bucket.get_at(*offset).unwrap().unwrap();
+            assert_eq!(read.read_field("text").unwrap().get_value::<&str>(), Some(*text));
+
+            let mut reader = bucket.readers.as_ref().unwrap().pull();
+            let (header, payload) = reader.as_mut_ref().read_raw_row(*offset).unwrap();
+            assert_eq!(payload, read.serialize().unwrap());
+            assert_eq!(header.len, HEADER_SIZE + payload.len() as u64);
+            lens.push(header.len);
+        }
+        assert!(lens.iter().any(|len| len % 8 != 0));
+        assert_eq!(offsets[1], offsets[0] + lens[0]);
+        assert_eq!(offsets[2], offsets[1] + lens[1]);
+    }
+
+    #[test]
+    fn every_codec_stores_a_compressible_row_smaller() {
+        let large = text_document("abcd".repeat(4096));
+        let raw = large.serialize().unwrap().len() as u64;
+        let config = BucketConfiguration {
+            compress_min_bytes: Some(0),
+            ..Default::default()
+        };
+
+        let mut db = Database::open(temp_path("row-codecs")).unwrap();
+        for (name, codec) in [
+            ("snappy", Compression::Snappy),
+            ("lz4", Compression::Lz4),
+            ("zstd", Compression::Zstd),
+        ] {
+            let description = BucketDescription::new(vec![
+                FieldDescriptor::new("text", FieldType::Text).unwrap(),
+            ])
+            .with_compression(codec);
+            db.open_bucket(name, Some(description), Some(config)).unwrap();
+            let mut bucket = db.get_bucket_mut(name).unwrap();
+            let offset = bucket.insert(&large).unwrap().0 as u64;
+            bucket.flush().unwrap();
+
+            {
+                let mut reader = bucket.readers.as_ref().unwrap().pull();
+                let (header, _) = reader.as_mut_ref().read_raw_row(offset).unwrap();
+                assert!(header.is_compressed());
+                assert!(header.len < raw / 4, "{} stored {} bytes", name, header.len);
+            }
+            let read = bucket.get_at(offset).unwrap().unwrap();
+            assert_eq!(read.read_field("text"), large.read_field("text"));
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::ffi::CString;
+
+use super::{
+    descriptor::BucketDescription,
+    document::{field::Field, Document},
+    row::Compression,
+    ID_FIELD,
+};
+
+/// Field names, defaults and codec of a bucket, shared with its readers so changes apply to every
+/// read
+#[derive(Debug, Default)]
+pub struct Schema {
+    /// Every name a field of the bucket has had, rows storing field names by position refer to
+    /// this list
+    pub(crate) names: Vec<CString>,
+    /// Values read for fields added after documents were stored, see `Bucket::add_field`
+    pub(crate) defaults: Vec<Field>,
+    /// Names of fields removed with `Bucket::drop_field`, stored documents may still hold them
+    pub(crate) dropped: Vec<CString>,
+    /// Codec compressed rows are stored with
+    pub(crate) compression: Compression,
+}
+
+impl Schema {
+    pub fn new(descriptor: &BucketDescription) -> Schema {
+        let dropped = descriptor
+            .field_names
+            .iter()
+            .filter(|n| n.as_bytes() != ID_FIELD.as_bytes())
+            .filter(|n| {
+                !descriptor
+                    .field_description
+                    .iter()
+                    .any(|f| f.get_name() == n.as_c_str())
+            })
+            .cloned()
+            .collect();
+
+        Schema {
+            names: descriptor.field_names.clone(),
+            defaults: descriptor.defaults.clone(),
+            dropped,
+            compression: descriptor.compression,
+        }
+    }
+
+    /// Whether the field named `key` was removed from the bucket
+    pub fn is_dropped(&self, key: &str) -> bool {
+        self.dropped.iter().any(|n| n.as_bytes() == key.as_bytes())
+    }
+
+    /// Default value of the field named `key`, if it was added with a default
+    pub fn get_default(&self, key: &str) -> Option<&Field> {
+        self.defaults
+            .iter()
+            .find(|f| f.get_key().to_bytes() == key.as_bytes())
+    }
+
+    /// Removes the dropped fields from a document read from disk and appends the default of
+    /// every added field it lacks
+    pub fn apply(&self, document: &mut Document) {
+        if !self.dropped.is_empty() {
+            document
+                .get_fields_mut()
+                .retain(|f| !self.dropped.iter().any(|n| n.as_c_str() == f.get_key()));
+        }
+
+        for default in self.defaults.iter() {
+            if !document
+                .get_fields()
+                .iter()
+                .any(|f| f.get_key() == default.get_key())
+            {
+                document.get_fields_mut().push(default.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::{HashMap, HashSet};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::database::error::NonaneError;
+
+use super::{
+    descriptor::{BucketDescription, UniqueConstraint},
+    document::Document,
+};
+
+/// In-memory composite index backing the unique constraints and unique fields of a bucket
+///
+/// Maps the combined values of every constraint to the offset of the document holding them,
+/// it's rebuilt from the stored documents when a bucket is loaded. Inserts check and update it
+/// before their write is queued, while holding it locked, so it's always ahead of the disk.
+#[derive(Debug, Default)]
+pub struct UniqueIndex {
+    constraints: Vec<UniqueConstraint>,
+    keys: Vec<HashMap<Vec<u8>, u64>>,
+    /// Amount of constraints, at the front of `constraints`, taken from the descriptor's unique
+    /// constraints, the rest back a unique field each
+    described: usize,
+}
+
+impl UniqueIndex {
+    pub fn new(description: &BucketDescription) -> UniqueIndex {
+        let mut constraints = description.unique_constraints.clone();
+        let described = constraints.len();
+        constraints.extend(
+            description
+                .field_description
+                .iter()
+                .filter(|f| f.is_unique())
+                .map(|f| {
+                    let name = f.get_name().to_string_lossy().into_owned();
+                    UniqueConstraint {
+                        name: name.clone(),
+                        fields: vec![name],
+                    }
+                }),
+        );
+
+        let keys = constraints.iter().map(|_| HashMap::new()).collect();
+        UniqueIndex {
+            constraints,
+            keys,
+            described,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.constraints.is_empty()
+    }
+
+    /// Errors with `NonaneError::UniqueViolation` if another document already holds the same
+    /// values as `document` for any constraint, or with `NonaneError::DuplicateKey` for a unique
+    /// field. A document stored at `own_offset` is ignored.
+    pub fn check(&self, document: &Document, own_offset: Option<u64>) -> Result<(), NonaneError> {
+        for (i, (constraint, keys)) in self.constraints.iter().zip(self.keys.iter()).enumerate() {
+            let key = match UniqueIndex::key(constraint, document) {
+                Some(k) => k,
+                None => continue,
+            };
+
+            match keys.get(&key) {
+                Some(offset) if Some(*offset) != own_offset => return Err(self.violation(i)),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks documents which are inserted together, see `check`
+    ///
+    /// The documents are checked against each other as well as the stored documents, errors
+    /// along with the position of the first document holding values which are already held.
+    pub fn check_batch(&self, documents: &[&Document]) -> Result<(), (usize, NonaneError)> {
+        let mut batch: Vec<HashSet<Vec<u8>>> = self.keys.iter().map(|_| HashSet::new()).collect();
+        for (index, document) in documents.iter().enumerate() {
+            self.check(document, None).map_err(|e| (index, e))?;
+
+            for (i, keys) in batch.iter_mut().enumerate() {
+                if let Some(key) = UniqueIndex::key(&self.constraints[i], document) {
+                    if !keys.insert(key) {
+                        return Err((index, self.violation(i)));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Error for a document violating the constraint at position `i`
+    fn violation(&self, i: usize) -> NonaneError {
+        let name = self.constraints[i].name.clone();
+        if i >= self.described {
+            NonaneError::DuplicateKey { field: name }
+        } else {
+            NonaneError::UniqueViolation { constraint: name }
+        }
+    }
+
+    /// Makes room for at least `rows` more documents in every constraint
+    pub fn reserve(&mut self, rows: usize) {
+        for keys in self.keys.iter_mut() {
+            keys.reserve(rows);
+        }
+    }
+
+    pub fn insert(&mut self, document: &Document, offset: u64) {
+        for (constraint, keys) in self.constraints.iter().zip(self.keys.iter_mut()) {
+            if let Some(key) = UniqueIndex::key(constraint, document) {
+                keys.insert(key, offset);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, document: &Document, offset: u64) {
+        for (constraint, keys) in self.constraints.iter().zip(self.keys.iter_mut()) {
+            if let Some(key) = UniqueIndex::key(constraint, document) {
+                if keys.get(&key) == Some(&offset) {
+                    keys.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Combines the values of the constrained fields, every value is prefixed by its length
+    /// so different splits of the same bytes don't collide
+    ///
+    /// Returns `None` if the document lacks any of the fields.
+    fn key(constraint: &UniqueConstraint, document: &Document) -> Option<Vec<u8>> {
+        let mut key = Vec::new();
+        for name in constraint.fields.iter() {
+            let data = document.read_field(name)?.get_data();
+            key.write_u64::<LittleEndian>(data.len() as u64).ok()?;
+            key.extend_from_slice(data);
+        }
+
+        Some(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        database::{
+            bucket::{
+                descriptor::BucketDescription,
+                document::{
+                    field::{descriptor::FieldDescriptor, fieldtype::FieldType, Field},
+                    Document,
+                },
+            },
+            error::NonaneError,
+            Database,
+        },
+        utils::testing::temp_path,
+    };
+
+    fn account(email: &str, tenant: i32) -> Document {
+        Document::new(vec![
+            Field::new("email", email.to_string()).unwrap(),
+            Field::new("tenant", tenant).unwrap(),
+        ])
+    }
+
+    fn is_violation(result: Result<impl std::fmt::Debug, NonaneError>, name: &str) -> bool {
+        matches!(result, Err(NonaneError::UniqueViolation { constraint }) if constraint == name)
+    }
+
+    #[test]
+    fn two_field_constraint_rejects_only_the_same_combination() {
+        let description = BucketDescription::new(vec![
+            FieldDescriptor::new("email", FieldType::Text).unwrap(),
+            FieldDescriptor::new("tenant", FieldType::Int32).unwrap(),
+        ])
+        .with_unique("email_tenant", &["email", "tenant"]);
+        let mut db = Database::open(temp_path("unique-two-fields")).unwrap();
+        db.open_bucket("accounts", Some(description), None).unwrap();
+        let mut bucket = db.get_bucket_mut("accounts").unwrap();
+
+        bucket.insert(&account("a@example.com", 1)).unwrap();
+        bucket.insert(&account("a@example.com", 2)).unwrap();
+        let offset = bucket.insert(&account("b@example.com", 1)).unwrap().0 as u64;
+        assert!(is_violation(bucket.insert(&account("a@example.com", 1)), "email_tenant"));
+
+        bucket.flush().unwrap();
+        assert!(is_violation(
+            bucket.update_at(offset, &account("a@example.com", 2)),
+            "email_tenant"
+        ));
+        bucket.flush().unwrap();
+        assert_eq!(bucket.count_documents().unwrap(), 3);
+    }
+
+    #[test]
+    fn unique_field_rejects_a_duplicate_even_when_raced() {
+        let description = BucketDescription::new(vec![
+            FieldDescriptor::new("email", FieldType::Text).unwrap().unique(),
+            FieldDescriptor::new("tenant", FieldType::Int32).unwrap(),
+        ]);
+        let mut db = Database::open(temp_path("unique-field")).unwrap();
+        db.open_bucket("accounts", Some(description), None).unwrap();
+
+        db.insert_document("accounts", 0, account("ada@example.com", 1)).unwrap();
+        assert!(matches!(
+            db.insert_document("accounts", 0, account("ada@example.com", 2)),
+            Err(NonaneError::DuplicateKey { field }) if field == "email"
+        ));
+
+        // Only one of the inserts of the same key wins, although none of them is written yet
+        let bucket = db.get_bucket("accounts").unwrap().clone();
+        let inserted: usize = (0..8)
+            .map(|t| {
+                let mut bucket = bucket.clone();
+                std::thread::spawn(move || bucket.insert(&account("grace@example.com", t)).is_ok())
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|t| t.join().unwrap() as usize)
+            .sum();
+        assert_eq!(inserted, 1);
+
+        bucket.flush().unwrap();
+        assert_eq!(bucket.count_documents_slow().unwrap(), 2);
+    }
+}
@@ -1,27 +1,87 @@
-use std::{convert::TryInto, fs::{File, OpenOptions}, io::{Seek, SeekFrom}, path::Path, sync::{atomic::AtomicBool, Arc}};
+use std::{convert::TryInto, fs::{File, OpenOptions}, io::{Error, ErrorKind, Seek, SeekFrom, Write}, path::Path, sync::{Arc, atomic::{AtomicBool, Ordering}}};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use parking_lot::RwLock;
 
 pub mod queued;
+pub mod wal;
+
+/// Position of the offset for next document, in the last 16 bytes of the first page
+pub(crate) fn offset_location() -> u64 {
+    (page_size::get() - std::mem::size_of::<u64>() * 2) as u64
+}
+
+/// Position of the offset the rows of the last write start at, right in front of the offset for
+/// next document
+///
+/// Rows in front of it were complete before the last write, only the rows following it are
+/// checked for a torn record on open. Only stored by files of `LAST_WRITE_VERSION` or later.
+pub(crate) fn last_write_location() -> u64 {
+    (page_size::get() - std::mem::size_of::<u64>() * 3) as u64
+}
+
+/// Position of the amount of documents stored, right after the offset for next document
+pub(crate) fn count_location() -> u64 {
+    (page_size::get() - std::mem::size_of::<u64>()) as u64
+}
+
+/// Amount of documents stored after counting `delta` more, errors instead of dropping below zero
+pub(crate) fn counted(count: u64, delta: i64) -> std::io::Result<u64> {
+    match count as i64 + delta {
+        c if c < 0 => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("uncounting {} documents from a count of {}", -delta, count),
+        )),
+        c => Ok(c as u64),
+    }
+}
+
+/// Holds a bucket's `will_write` lock exclusively for as long as it's held, see
+/// `Bucket::begin_write`
+///
+/// Waits for the reads in progress to finish, reads started afterwards wait for the guard to
+/// be dropped, even if the write failed, for at most `reader::MAX_WRITE_WAIT`.
+pub struct WriteGuard {
+    will_write: Arc<RwLock<()>>,
+}
+
+impl WriteGuard {
+    pub(crate) fn new(will_write: Arc<RwLock<()>>) -> WriteGuard {
+        // Unlocked on drop, a borrowing guard would keep the owner of the lock borrowed
+        std::mem::forget(will_write.write());
+        WriteGuard { will_write }
+    }
+}
+
+impl Drop for WriteGuard {
+    fn drop(&mut self) {
+        // Safety: the lock was locked exclusively by `new` and its guard forgotten
+        unsafe { self.will_write.force_unlock_write() }
+    }
+}
 
 #[derive(Debug)]
 pub struct Writer<'a> {
     pub(crate) name: &'a str,
     pub(crate) file: File,
-    pub(crate) will_write: Arc<AtomicBool>,
+    pub(crate) will_write: Arc<RwLock<()>>,
+    /// Whether the file stores the offset of the last write, see `last_write_location`
+    pub(crate) stores_last_write: Arc<AtomicBool>,
 }
 
 impl<'a> Writer<'a> {
     pub fn new(
         name: &'a str,
         path: &Path,
-        will_write: Arc<AtomicBool>,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let file = OpenOptions::new().write(true).open(&path)?;
+        will_write: Arc<RwLock<()>>,
+        stores_last_write: Arc<AtomicBool>,
+    ) -> std::io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
         let writer = Self {
             name,
             file,
             will_write,
+            stores_last_write,
         };
 
         Ok(writer)
@@ -33,14 +93,102 @@ impl<'a> Writer<'a> {
 
     /// Sets the offset for next document
     pub fn set_offset(&mut self, offset: u64) -> std::io::Result<()> {
-        let location: u64 = (page_size::get() - std::mem::size_of::<u64>() * 2)
-            .try_into()
-            .unwrap();
-        self.file.seek(SeekFrom::Start(location))?;
+        self.file.seek(SeekFrom::Start(offset_location()))?;
         self.file.write_u64::<LittleEndian>(offset)?;
         Ok(())
     }
+
+    /// Sets the offset the rows of the last write start at, files which don't store it are left
+    /// alone
+    pub fn set_last_write(&mut self, offset: u64) -> std::io::Result<()> {
+        if !self.stores_last_write.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        self.file.seek(SeekFrom::Start(last_write_location()))?;
+        self.file.write_u64::<LittleEndian>(offset)
+    }
+
+    /// Reads the offset the rows of the last write start at, `None` if the file doesn't store it
+    pub fn get_last_write(&mut self) -> std::io::Result<Option<u64>> {
+        if !self.stores_last_write.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+
+        self.file.seek(SeekFrom::Start(last_write_location()))?;
+        self.file.read_u64::<LittleEndian>().map(Some)
+    }
+
+    /// Sets the amount of documents stored, see `Bucket::count_documents`
+    pub fn set_count(&mut self, count: u64) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(count_location()))?;
+        self.file.write_u64::<LittleEndian>(count)
+    }
+
+    /// Moves the amount of documents stored by `delta`
+    pub fn add_to_count(&mut self, delta: i64) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(count_location()))?;
+        let count = self.file.read_u64::<LittleEndian>()?;
+        self.set_count(counted(count, delta)?)
+    }
+
+    /// Writes rows following each other at `offset`, moves the offset for next document past
+    /// them and counts their `documents`, syncing them to disk before returning
+    pub fn write_rows(&mut self, offset: u64, rows: &[u8], documents: i64) -> std::io::Result<()> {
+        let _guard = WriteGuard::new(self.will_write.clone());
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(rows)?;
+        self.set_last_write(offset)?;
+        self.set_offset(offset + rows.len() as u64)?;
+        self.add_to_count(documents)?;
+        self.file.sync_data()
+    }
+
+    /// Writes `bytes` over part of a row which was already written, leaving the offset for the
+    /// next document alone, syncing them to disk before returning
+    pub fn overwrite(&mut self, offset: u64, bytes: &[u8]) -> std::io::Result<()> {
+        let _guard = WriteGuard::new(self.will_write.clone());
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(bytes)?;
+        self.file.sync_data()
+    }
 }
 
 unsafe impl<'a> Send for Writer<'a> {}
 unsafe impl<'a> Sync for Writer<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use parking_lot::RwLock;
+
+    use super::{counted, WriteGuard};
+
+    #[test]
+    fn write_guard_excludes_reads_until_dropped() {
+        let will_write = Arc::new(RwLock::new(()));
+        assert!(!will_write.is_locked());
+
+        let guard = WriteGuard::new(will_write.clone());
+        assert!(will_write.is_locked());
+        assert!(will_write.try_read().is_none());
+
+        drop(guard);
+        assert!(!will_write.is_locked());
+        drop(will_write.try_read().unwrap());
+
+        // Taken again once reads are done
+        let guard = WriteGuard::new(will_write.clone());
+        assert!(will_write.is_locked());
+        drop(guard);
+        assert!(!will_write.is_locked());
+    }
+
+    #[test]
+    fn count_errors_instead_of_dropping_below_zero() {
+        assert_eq!(counted(2, -2).unwrap(), 0);
+        assert_eq!(counted(2, 3).unwrap(), 5);
+        assert!(counted(1, -2).is_err());
+    }
+}
@@ -1,21 +1,212 @@
-use std::{convert::TryInto, fs::{File, OpenOptions}, io::{Seek, SeekFrom, Write}, mem::MaybeUninit, path::{Path, PathBuf}, sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}}, thread::JoinHandle};
+use std::{convert::TryInto, fs::{File, OpenOptions}, io::{Error, Seek, SeekFrom, Write}, mem::MaybeUninit, path::{Path, PathBuf}, sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}}, thread::JoinHandle, time::{Duration, Instant}};
 
 use byteorder::{LittleEndian, WriteBytesExt};
-use crossbeam_queue::ArrayQueue;
+use crossbeam_queue::{ArrayQueue, SegQueue};
 use log::trace;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 
-use crate::utils::threading::BooleanSemaphore;
+use crate::{database::{bucket::config::{DriveType, SyncPolicy}, error::NonaneError}, utils::threading::{BooleanSemaphore, ProgressSemaphore}};
+
+use super::{count_location, counted, last_write_location, offset_location, wal::WriteAheadLog, WriteGuard};
 
 // Information about the writer thread
 #[derive(Debug, Clone)]
 pub struct WriterThread {
-    pub(crate) join_handle: Option<Arc<JoinHandle<QueuedWriter>>>,
+    /// Taken by the first clone to stop the writer, see `stop`
+    pub(crate) join_handle: Arc<Mutex<Option<JoinHandle<QueuedWriter>>>>,
     pub(crate) should_exit: Arc<AtomicBool>,
-    pub(crate) q: Arc<ArrayQueue<QueuedWriteInformation>>,
+    pub(crate) q: Arc<WriteQueue>,
+    /// Wakes the writer once a write is pushed or it's asked to exit
+    pub(crate) has_data: Arc<BooleanSemaphore>,
+    /// Amount of writes that have been pushed to the queue
+    pub(crate) queued: Arc<AtomicU64>,
+    /// Amount of writes that have been written to disk, along with the writes which failed
+    pub(crate) written: Arc<ProgressSemaphore>,
+    /// Amount of overwrites pushed to the queue which haven't been applied yet
+    pub(crate) overwriting: Arc<AtomicU64>,
+    /// Offset for next document and document count last written to disk, shared with the
+    /// writer so they can be replaced once repaired, see `reset_stored`
+    pub(crate) stored_offset: Arc<AtomicU64>,
+    pub(crate) stored_count: Arc<AtomicU64>,
+}
+
+impl WriterThread {
+    /// Pushes a write to the queue, handing it back if the queue is full
+    pub fn push(&self, info: QueuedWriteInformation) -> Result<(), QueuedWriteInformation> {
+        // Counted before pushing, the writer may apply it before this returns
+        let overwrite = info.overwrite;
+        if overwrite {
+            self.overwriting.fetch_add(1, Ordering::SeqCst);
+        }
+        if let Err(info) = self.q.push(info) {
+            if overwrite {
+                self.overwriting.fetch_sub(1, Ordering::SeqCst);
+            }
+            return Err(info);
+        }
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        self.has_data.set_ready(true);
+        Ok(())
+    }
+
+    /// Pushes a write to the queue, waiting up to `wait` for room if it's full
+    ///
+    /// The push is retried every time the writer finishes a batch, as popping the batch made
+    /// room in the queue. The write is handed back if the queue is still full after `wait`.
+    pub fn push_waiting(
+        &self,
+        mut info: QueuedWriteInformation,
+        wait: Duration,
+    ) -> Result<(), QueuedWriteInformation> {
+        let deadline = Instant::now() + wait;
+        loop {
+            // Read before pushing, so a batch finishing in between isn't missed
+            let written = self.written.get();
+            info = match self.push(info) {
+                Ok(()) => return Ok(()),
+                Err(info) => info,
+            };
 
-    // Debugging
-    pub(crate) items: Arc<AtomicUsize>,}
+            if !self.written.wait_until_deadline(written + 1, deadline) {
+                return self.push(info);
+            }
+        }
+    }
+
+    /// Asks the writer to exit once its queue is empty and waits for the thread to finish
+    ///
+    /// Every write queued before the call is written. Stopping a stopped writer does nothing.
+    pub fn stop(&self) -> std::io::Result<()> {
+        self.should_exit.store(true, Ordering::SeqCst);
+        self.has_data.set_ready(true);
+
+        let handle = self.join_handle.lock().take();
+        if let Some(handle) = handle {
+            handle
+                .join()
+                .map_err(|_| Error::other("writer thread panicked"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the offset for next document and the document count the writer moves forward
+    /// from, after they were rewritten on disk
+    ///
+    /// Must only be called while no writes are queued.
+    pub(crate) fn reset_stored(&self, offset: u64, count: u64) {
+        self.stored_offset.store(offset, Ordering::SeqCst);
+        self.stored_count.store(count, Ordering::SeqCst);
+    }
+
+    /// Whether rows below the stored offset may still change, as an overwrite of one is queued
+    pub fn is_overwriting(&self) -> bool {
+        self.overwriting.load(Ordering::SeqCst) > 0
+    }
+
+    /// Whether the writer was asked to exit, writes pushed afterwards may never be written
+    pub fn is_stopped(&self) -> bool {
+        self.should_exit.load(Ordering::SeqCst)
+    }
+
+    /// Blocks until every write queued before the call has been written to disk
+    ///
+    /// Errors with `NonaneError::WritesFailed` if any write pushed since the writer started
+    /// couldn't be written, the writes which were written don't make up for it.
+    pub fn wait_for_writes(&self) -> Result<(), NonaneError> {
+        let target = self.queued.load(Ordering::SeqCst);
+        self.written.wait_until(target);
+
+        match self.written.failed() {
+            0 => Ok(()),
+            writes => Err(NonaneError::WritesFailed { writes }),
+        }
+    }
+}
+
+/// Writes waiting for the writer thread, see `BucketConfiguration::queue_capacity`
+///
+/// Both queues are boxed, their cache-padded heads and tails would otherwise make the enum
+/// as large as the larger of them.
+#[derive(Debug)]
+pub enum WriteQueue {
+    /// Holds at most a fixed amount of writes, pushing to a full queue fails
+    ///
+    /// Only locked exclusively to swap in a larger queue, see `WriteQueue::grow`.
+    Bounded(Box<RwLock<ArrayQueue<QueuedWriteInformation>>>),
+    /// Grows for as long as writes are pushed faster than they're written
+    Unbounded(Box<SegQueue<QueuedWriteInformation>>),
+}
+
+impl WriteQueue {
+    /// Creates a queue holding at most `capacity` writes, or any amount for `None`
+    pub fn new(capacity: Option<usize>) -> WriteQueue {
+        match capacity {
+            Some(capacity) => {
+                WriteQueue::Bounded(Box::new(RwLock::new(ArrayQueue::new(capacity))))
+            }
+            None => WriteQueue::Unbounded(Box::new(SegQueue::new())),
+        }
+    }
+
+    /// Pushes a write, handing it back if the queue is full
+    pub fn push(&self, info: QueuedWriteInformation) -> Result<(), QueuedWriteInformation> {
+        match self {
+            WriteQueue::Bounded(q) => q.read().push(info),
+            WriteQueue::Unbounded(q) => {
+                q.push(info);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<QueuedWriteInformation> {
+        match self {
+            WriteQueue::Bounded(q) => q.read().pop(),
+            WriteQueue::Unbounded(q) => q.pop(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            WriteQueue::Bounded(q) => q.read().len(),
+            WriteQueue::Unbounded(q) => q.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Most writes the queue holds, `None` if it's unbounded
+    pub fn capacity(&self) -> Option<usize> {
+        match self {
+            WriteQueue::Bounded(q) => Some(q.read().capacity()),
+            WriteQueue::Unbounded(_) => None,
+        }
+    }
+
+    /// Grows a bounded queue to hold at least `capacity` writes, keeping the queued writes in
+    /// order. Never shrinks a queue and does nothing for an unbounded one.
+    pub fn grow(&self, capacity: usize) {
+        let q = match self {
+            WriteQueue::Bounded(q) => q,
+            WriteQueue::Unbounded(_) => return,
+        };
+
+        let mut q = q.write();
+        if capacity <= q.capacity() {
+            return;
+        }
+
+        let grown = ArrayQueue::new(capacity);
+        while let Some(info) = q.pop() {
+            // Can't fail, the new queue holds more than the old one did
+            let _ = grown.push(info);
+        }
+        *q = grown;
+    }
+}
 
 /// Data used to describe where the data will be written to
 #[derive(Debug, Clone)]
@@ -23,151 +214,503 @@ pub struct QueuedWriteInformation {
     pub(crate) seek: (u64, u64),
     pub(crate) len: usize,
     pub(crate) bytes: Vec<u8>,
+    /// Replaces bytes of a row which was queued before, without moving the offset of the next
+    /// document
+    pub(crate) overwrite: bool,
+    /// Change to the stored document count once written, 1 for new rows and -1 for deletes
+    pub(crate) documents: i64,
 }
 
 /// A threaded writer which chunks for faster writing
 ///
 /// Chunks together multiple sequential buffers into one bigger buffer
 pub struct QueuedWriter {
-    pub(crate) q: Arc<ArrayQueue<QueuedWriteInformation>>,
+    pub(crate) q: Arc<WriteQueue>,
+    pub(crate) has_data: Arc<BooleanSemaphore>,
+    /// Held while a chunk is being written, see `Bucket::begin_write`
+    pub(crate) will_write: Arc<RwLock<()>>,
     pub(crate) file: File,
     pub(crate) should_exit: Arc<AtomicBool>,
-
-    // Debugging
-    pub(crate) items: Arc<AtomicUsize>,
+    pub(crate) sync_policy: SyncPolicy,
+    /// Chunks written since the file was last synced, see `SyncPolicy::Batch`
+    pub(crate) unsynced: usize,
+    /// When the first of the unsynced chunks was written
+    pub(crate) unsynced_since: Instant,
+    pub(crate) written: Arc<ProgressSemaphore>,
+    /// Amount of queued overwrites, lowered once each one is applied or failed
+    pub(crate) overwriting: Arc<AtomicU64>,
+    /// Offset for next document last written to disk, it only ever moves forward
+    pub(crate) stored_offset: Arc<AtomicU64>,
+    /// Amount of documents last written to disk, see `Bucket::count_documents`
+    pub(crate) stored_count: Arc<AtomicU64>,
+    /// Log of the queued writes, advanced once they're written, see `with_wal`
+    pub(crate) wal: Option<Arc<Mutex<WriteAheadLog>>>,
+    /// Set once a write failed, the log is no longer advanced so it keeps the failed write
+    pub(crate) wal_held: bool,
+    /// Whether the file stores the offset of the last write, see `with_last_write`
+    pub(crate) stores_last_write: Arc<AtomicBool>,
 }
 
 impl QueuedWriter {
     /// Creates a new QueuedWriter
     pub fn new(
         path: PathBuf,
-        q: Arc<ArrayQueue<QueuedWriteInformation>>,
+        q: Arc<WriteQueue>,
         should_exit: Arc<AtomicBool>,
+        will_write: Arc<RwLock<()>>,
+        sync_policy: SyncPolicy,
+        stored_offset: u64,
+        stored_count: u64,
     ) -> (QueuedWriter, WriterThread) {
         let file = OpenOptions::new()
             .write(true)
             .open(&path)
             .expect("Failed to open writer thread");
 
-        let items = Arc::new(AtomicUsize::new(0));
+        let has_data = Arc::new(BooleanSemaphore::new(Arc::new(Mutex::new(false))));
+        let written = Arc::new(ProgressSemaphore::new());
+        let overwriting = Arc::new(AtomicU64::new(0));
+        let stored_offset = Arc::new(AtomicU64::new(stored_offset));
+        let stored_count = Arc::new(AtomicU64::new(stored_count));
 
         (
             QueuedWriter {
                 q: q.clone(),
+                has_data: has_data.clone(),
+                will_write,
                 file,
                 should_exit: should_exit.clone(),
-                items: items.clone(),
+                sync_policy,
+                unsynced: 0,
+                unsynced_since: Instant::now(),
+                written: written.clone(),
+                overwriting: overwriting.clone(),
+                stored_offset: stored_offset.clone(),
+                stored_count: stored_count.clone(),
+                wal: None,
+                wal_held: false,
+                stores_last_write: Arc::new(AtomicBool::new(false)),
             },
 
             WriterThread {
-                join_handle: None,
+                join_handle: Arc::new(Mutex::new(None)),
                 should_exit,
                 q,
-                items,
+                has_data,
+                queued: Arc::new(AtomicU64::new(0)),
+                written,
+                overwriting,
+                stored_offset,
+                stored_count,
             }
         )
     }
 
+    /// Advances the bucket's write-ahead log past every batch once it's written and synced
+    pub fn with_wal(mut self, wal: Option<Arc<Mutex<WriteAheadLog>>>) -> QueuedWriter {
+        self.wal = wal;
+        self
+    }
+
+    /// Stores where each chunk moving the offset forward starts while `stores_last_write` is
+    /// set, see `writer::last_write_location`
+    pub fn with_last_write(mut self, stores_last_write: Arc<AtomicBool>) -> QueuedWriter {
+        self.stores_last_write = stores_last_write;
+        self
+    }
+
     /// Initializes and starts the writer
     ///
-    /// Prepares it for writing
-    pub fn start(&mut self, sleep_ns: u64) {
-        // Todo: Implement some type of system to skip the while loop, as it's a big resource hog (works really well though)
+    /// Prepares it for writing, the writer is parked until a write is queued. Once woken it
+    /// sleeps to let more writes be queued and then drains the queue, how long it sleeps and
+    /// how many writes it pops at once is tuned for `drive_type`, see `DriveType::writer_sleep`.
+    /// Written chunks are synced as set by the bucket's `SyncPolicy`.
+    pub fn start(&mut self, drive_type: DriveType) {
+        let sleep = drive_type.writer_sleep();
+        let batch = drive_type.writer_batch();
+
         while !self.should_exit.as_ref().load(Ordering::SeqCst) || self.q.len() > 0 {
-            std::thread::sleep(std::time::Duration::from_nanos(sleep_ns));
-            let t = std::time::Instant::now();
-            let l = self.q.len().max(25);
-            let mut data = Vec::with_capacity(l);
-            for i in 0..l {
-                let el = self.q.pop();
-                let el = match el {
-                    Some(el) => {
-                        data.push((el.seek, el));
+            // Unsynced chunks of a batch are synced once its interval is up, even without writes
+            match self.sync_policy {
+                SyncPolicy::Batch { interval, .. } if self.unsynced > 0 => {
+                    let ready = self
+                        .has_data
+                        .wait_for(interval.saturating_sub(self.unsynced_since.elapsed()));
+                    if let Err(e) = self.sync_due() {
+                        error!("Failed to sync written chunks: {:?}", e);
                     }
-                    None => {
-                        self.items.store(0, Ordering::SeqCst);
+                    if !ready {
                         continue;
                     }
-                };
+                }
+                _ => self.has_data.wait(),
+            }
+            std::thread::sleep(sleep);
+
+            // Reset before draining, so writes pushed from here on wake the writer again
+            self.has_data.set_ready(false);
+            let t = std::time::Instant::now();
+            let l = self.q.len().max(batch);
+            let mut data = Vec::with_capacity(l);
+            for _ in 0..l {
+                match self.q.pop() {
+                    Some(el) => data.push((el.seek, el)),
+                    None => break,
+                }
             }
 
+            // Overwrites are applied once the rows they modify have been written
+            let (overwrites, mut data): (Vec<_>, Vec<_>) =
+                data.into_iter().partition(|d| d.1.overwrite);
+            let popped = overwrites.len() + data.len();
+
             // Check data length and sort by key to chunk
-            if data.len() == 0 {
-                self.items.store(0, Ordering::SeqCst);
+            if popped == 0 {
                 continue;
             } else {
                 data.sort_unstable_by_key(|x| x.0);
             }
 
             // Find data that can be written sequentially
-            let mut amount_chunked = 0;
-            let mut chunk: (u64, Vec<u8>) = (data.first().unwrap().0 .0, Vec::new());
-            let mut last_offset: i64 = data.first().unwrap().0 .0 as i64;
-            for d in data.iter_mut() {
-                if d.0 .0 as i64 == last_offset || last_offset == -1 {
-                    // Add initial seek if it's the first insert
-                    if chunk.1.len() == 0 {
-                        chunk.0 = d.0 .0;
+            let mut chunks: Vec<Chunk> = Vec::new();
+            for (seek, mut info) in data {
+                match chunks.last_mut() {
+                    Some(chunk) if chunk.end == seek.0 => {
+                        chunk.bytes.append(&mut info.bytes);
+                        chunk.end = seek.1;
+                        chunk.documents += info.documents;
+                        chunk.writes += 1;
                     }
+                    _ => chunks.push(Chunk {
+                        start: seek.0,
+                        end: seek.1,
+                        bytes: info.bytes,
+                        documents: info.documents,
+                        writes: 1,
+                    }),
+                }
+            }
 
-                    // Add the bytes of the data
-                    chunk.1.append(&mut d.1.bytes);
-                    last_offset = d.0 .1 as i64;
-                    amount_chunked += 1;
-                } else {
-                    let res = self.write_chunk(&chunk);
-                    match res {
-                        Ok(_) => {}
-                        Err(_) => {
-                            error!("Failed to write chunks");
-                        }
+            // Only writes which made it to disk count as written, failed writes are counted
+            // apart so waiting for them errors
+            let mut written = 0;
+            let mut failed = 0;
+            for chunk in chunks.iter() {
+                match self.write_chunk(chunk) {
+                    Ok(()) => written += chunk.writes,
+                    Err(e) => {
+                        error!("Failed to write chunk at {}: {:?}", chunk.start, e);
+                        failed += chunk.writes;
                     }
-                    chunk = (0, Vec::new());
-                    last_offset = -1;
                 }
             }
 
-            // Try to write any data that was "forgotten"
-            if chunk.1.len() > 0 {
-                let res = self.write_chunk(&chunk);
-                match res {
-                    Ok(_) => {}
+            for (_, overwrite) in overwrites.iter() {
+                match self.write_overwrite(overwrite) {
+                    Ok(()) => written += 1,
                     Err(e) => {
-                        panic!("Error with writing chunks {:?}", e);
+                        error!("Failed to overwrite row at {}: {:?}", overwrite.seek.0, e);
+                        failed += 1;
                     }
                 }
+                self.overwriting.fetch_sub(1, Ordering::SeqCst);
             }
 
-            self.items.store(amount_chunked, Ordering::SeqCst);
+            self.written.advance(written);
+            self.written.fail(failed);
+
+            // The log counts the writes leading it as written, so it can't skip a failed write.
+            // It keeps the failed write along with every later one, they're written again once
+            // the bucket is reopened, see `Bucket::replay_wal`
+            if failed > 0 && self.wal.is_some() && !self.wal_held {
+                error!("Holding the write-ahead log back at a failed write");
+                self.wal_held = true;
+            }
+
+            // Advanced after the progress, inserts waiting for room hold the log while waiting
+            if !self.wal_held {
+                if let Err(e) = self.advance_wal(popped as u64) {
+                    error!("Failed to advance the write-ahead log: {:?}", e);
+                }
+            }
 
             let el = t.elapsed();
             trace!(
-                "Writes that where chunked: {} | Time to chunk: {:?}",
-                amount_chunked,
+                "Writes that where chunked: {} into {} chunks | Time to chunk: {:?}",
+                popped,
+                chunks.len(),
                 el
             );
         }
+
+        if self.unsynced > 0 {
+            if let Err(e) = self.sync() {
+                error!("Failed to sync written chunks: {:?}", e);
+            }
+        }
+    }
+
+    /// Syncs the written batch of `popped` writes and marks them as written in the log
+    fn advance_wal(&mut self, popped: u64) -> std::io::Result<()> {
+        let wal = match self.wal.as_ref() {
+            Some(wal) => wal.clone(),
+            None => return Ok(()),
+        };
+
+        self.sync()?;
+        let res = wal.lock().advance(popped);
+        res
+    }
+
+    /// Syncs the file after a chunk was written, if the bucket's `SyncPolicy` asks for it
+    fn sync_written(&mut self) -> std::io::Result<()> {
+        match self.sync_policy {
+            SyncPolicy::Never => Ok(()),
+            SyncPolicy::EachChunk => self.sync(),
+            SyncPolicy::Batch { chunks, .. } => {
+                if self.unsynced == 0 {
+                    self.unsynced_since = Instant::now();
+                }
+                self.unsynced += 1;
+                if self.unsynced >= chunks {
+                    self.sync()
+                } else {
+                    self.sync_due()
+                }
+            }
+        }
+    }
+
+    /// Syncs the chunks of a batch once its interval is up, see `SyncPolicy::Batch`
+    fn sync_due(&mut self) -> std::io::Result<()> {
+        match self.sync_policy {
+            SyncPolicy::Batch { interval, .. }
+                if self.unsynced > 0 && self.unsynced_since.elapsed() >= interval =>
+            {
+                self.sync()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Writes the bytes of an overwrite in place, leaving the stored offset alone
+    fn write_overwrite(&mut self, info: &QueuedWriteInformation) -> std::io::Result<()> {
+        let _guard = WriteGuard::new(self.will_write.clone());
+        self.file.seek(SeekFrom::Start(info.seek.0))?;
+        self.file.write_all(&info.bytes)?;
+        self.add_to_count(info.documents)?;
+        self.sync_written()
+    }
+
+    /// Moves the document count stored on disk by `delta`
+    fn add_to_count(&mut self, delta: i64) -> std::io::Result<()> {
+        if delta == 0 {
+            return Ok(());
+        }
+
+        let count = counted(self.stored_count.load(Ordering::SeqCst), delta)?;
+        self.file.seek(SeekFrom::Start(count_location()))?;
+        self.file.write_u64::<LittleEndian>(count)?;
+        self.stored_count.store(count, Ordering::SeqCst);
+        Ok(())
     }
 
     /// Store chunks to disk
-    fn write_chunk(&mut self, chunk: &(u64, Vec<u8>)) -> Result<(), Box<dyn std::error::Error>> {
+    ///
+    /// Rows are handed out in offset order but may be queued in any order, so a chunk can end
+    /// before rows which were already written. The offset for next document is only moved
+    /// forward, never back over written rows.
+    fn write_chunk(&mut self, chunk: &Chunk) -> std::io::Result<()> {
         let t = std::time::Instant::now();
-        self.file.seek(SeekFrom::Start(chunk.0))?;
-        self.file.write(&chunk.1)?;
-
-        let location: u64 = (page_size::get() - std::mem::size_of::<u64>() * 2)
-            .try_into()
-            .unwrap();
+        let _guard = WriteGuard::new(self.will_write.clone());
+        self.file.seek(SeekFrom::Start(chunk.start))?;
+        self.file.write_all(&chunk.bytes)?;
 
         // Write the offset to disk
-        // ! This does not work with multiple QueuedWriters as it does not keep track if the offset is 
-        // ! larger than the old offset or not
-        let offset = chunk.0 + chunk.1.len() as u64;
-        self.file.seek(SeekFrom::Start(location))?;
-        self.file.write_u64::<LittleEndian>(offset)?;
+        if chunk.end > self.stored_offset.load(Ordering::SeqCst) {
+            if self.stores_last_write.load(Ordering::SeqCst) {
+                self.file.seek(SeekFrom::Start(last_write_location()))?;
+                self.file.write_u64::<LittleEndian>(chunk.start)?;
+            }
+            self.file.seek(SeekFrom::Start(offset_location()))?;
+            self.file.write_u64::<LittleEndian>(chunk.end)?;
+            self.stored_offset.store(chunk.end, Ordering::SeqCst);
+        }
+        self.add_to_count(chunk.documents)?;
+        self.sync_written()?;
 
         let el = t.elapsed();
-        trace!("Wrote chunks {:?} to disk with seek {} and length {}", el, chunk.0, chunk.1.len());
+        trace!("Wrote chunks {:?} to disk with seek {} and length {}", el, chunk.start, chunk.bytes.len());
         Ok(())
     }
 }
+
+/// Writes following each other, written to disk at once
+struct Chunk {
+    start: u64,
+    end: u64,
+    bytes: Vec<u8>,
+    /// Change to the document count made by the writes
+    documents: i64,
+    /// Amount of writes joined into the chunk
+    writes: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use crate::{
+        database::{
+            bucket::config::{BucketConfiguration, SyncPolicy},
+            error::NonaneError,
+            Database,
+        },
+        utils::testing::{copy_dir, int_description, int_document, temp_path},
+    };
+
+    use super::QueuedWriteInformation;
+
+    /// A write the writer thread fails to write, its offset is past the largest seekable one
+    fn unwritable() -> QueuedWriteInformation {
+        QueuedWriteInformation {
+            seek: (u64::MAX - 8, u64::MAX),
+            len: 8,
+            bytes: vec![0; 8],
+            overwrite: false,
+            documents: 1,
+        }
+    }
+
+    #[test]
+    fn failed_write_isnt_counted_as_written() {
+        let mut db = Database::open(temp_path("queued-failed-write")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+
+        let (before, _) = bucket.insert(&int_document(1)).unwrap();
+        bucket.writer_thread.as_ref().unwrap().push(unwritable()).unwrap();
+        let (after, _) = bucket.insert(&int_document(2)).unwrap();
+
+        let writer_thread = bucket.writer_thread.clone().unwrap();
+        assert!(matches!(
+            writer_thread.wait_for_writes(),
+            Err(NonaneError::WritesFailed { writes: 1 })
+        ));
+        assert!(matches!(bucket.checkpoint(), Err(NonaneError::WritesFailed { writes: 1 })));
+        assert_eq!(writer_thread.written.get(), 3);
+        assert_eq!(writer_thread.written.failed(), 1);
+
+        // Writes around the failed one are still written
+        assert_eq!(bucket.get_at(before as u64).unwrap().unwrap().get_i32("n"), Some(1));
+        assert_eq!(bucket.get_at(after as u64).unwrap().unwrap().get_i32("n"), Some(2));
+        assert_eq!(bucket.count_documents_slow().unwrap(), 2);
+    }
+
+    #[test]
+    fn chunks_written_out_of_order_never_move_the_offset_back() {
+        let mut db = Database::open(temp_path("queued-out-of-order")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        let bucket = db.get_bucket("numbers").unwrap();
+        let writer_thread = bucket.writer_thread.clone().unwrap();
+
+        // Rows are handed their offsets in order, then queued from the last one to the first
+        let writes: Vec<QueuedWriteInformation> = (0..200)
+            .map(|n| {
+                let (row, _) = bucket.encode_row(&int_document(n)).unwrap();
+                let offset = bucket.reserve_offset(row.len() as u64).unwrap();
+                QueuedWriteInformation {
+                    seek: (offset, offset + row.len() as u64),
+                    len: row.len(),
+                    bytes: row,
+                    overwrite: false,
+                    documents: 1,
+                }
+            })
+            .collect();
+        let end = bucket.disk_usage();
+
+        for (i, write) in writes.into_iter().rev().enumerate() {
+            writer_thread.push(write).unwrap();
+            if i % 20 == 19 {
+                writer_thread.wait_for_writes().unwrap();
+                assert_eq!(writer_thread.stored_offset.load(Ordering::SeqCst), end);
+            }
+        }
+        writer_thread.wait_for_writes().unwrap();
+
+        assert_eq!(writer_thread.stored_offset.load(Ordering::SeqCst), end);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 200);
+        let numbers: Vec<i32> = bucket
+            .offsets()
+            .unwrap()
+            .into_iter()
+            .map(|offset| bucket.get_at(offset as u64).unwrap().unwrap().get_i32("n").unwrap())
+            .collect();
+        assert_eq!(numbers, (0..200).collect::<Vec<_>>());
+    }
+
+    /// Clock ticks the thread named `name` has spent running, as listed by Linux
+    #[cfg(target_os = "linux")]
+    fn cpu_ticks(name: &str) -> u64 {
+        let task = std::fs::read_dir("/proc/self/task")
+            .unwrap()
+            .map(|task| task.unwrap().path())
+            .find(|task| {
+                std::fs::read_to_string(task.join("comm")).is_ok_and(|c| c.trim_end() == name)
+            })
+            .unwrap();
+
+        // utime and stime follow the parenthesized name, as the 12th and 13th fields after it
+        let stat = std::fs::read_to_string(task.join("stat")).unwrap();
+        let fields: Vec<&str> = stat[stat.rfind(')').unwrap() + 2..].split(' ').collect();
+        fields[11].parse::<u64>().unwrap() + fields[12].parse::<u64>().unwrap()
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn idle_writer_thread_stays_parked() {
+        let mut db = Database::open(temp_path("queued-idle")).unwrap();
+        db.open_bucket("idle_writer", Some(int_description()), None).unwrap();
+        let mut bucket = db.get_bucket_mut("idle_writer").unwrap();
+        bucket.insert(&int_document(1)).unwrap();
+        bucket.flush().unwrap();
+
+        let before = cpu_ticks("idle_writer");
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        // A spinning thread would have run for about 50 ticks
+        assert!(cpu_ticks("idle_writer") - before <= 2);
+    }
+
+    #[test]
+    fn each_chunk_policy_leaves_drained_writes_on_disk() {
+        let config = BucketConfiguration {
+            sync_policy: SyncPolicy::EachChunk,
+            ..Default::default()
+        };
+        let path = temp_path("queued-each-chunk");
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("numbers", Some(int_description()), Some(config)).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        for n in 0..50 {
+            bucket.insert(&int_document(n)).unwrap();
+        }
+        bucket.writer_thread.as_ref().unwrap().wait_for_writes().unwrap();
+
+        // The copy is what a crash leaves behind, the bucket is neither flushed nor closed
+        let crashed = temp_path("queued-each-chunk-crashed");
+        copy_dir(path, crashed);
+
+        let mut db = Database::open(crashed).unwrap();
+        db.open_bucket("numbers", None, None).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        assert_eq!(bucket.count_documents().unwrap(), 50);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 50);
+    }
+}
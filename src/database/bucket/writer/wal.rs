@@ -0,0 +1,288 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::queued::QueuedWriteInformation;
+
+/// Extension used for a bucket's write-ahead log
+static EXTENSION: &str = "wal";
+
+/// Amount of bytes in front of the entries, the amount of entries already written to the bucket
+const HEADER_SIZE: u64 = std::mem::size_of::<u64>() as u64;
+
+/// Append-only log of the writes queued for a bucket's writer thread, see
+/// `BucketConfiguration::write_ahead_log`
+///
+/// Every queued write is appended as its start and end offset, whether it's an overwrite, its
+/// change to the document count, the length of its bytes and the bytes themselves. The entries
+/// are in the order the writer thread pops them, it advances the count of written entries in
+/// front of them once a batch is synced. The log is emptied once every entry has been written.
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    file: File,
+    /// Length of the file, entries are appended here
+    len: u64,
+    /// Entries in the log, written to the bucket or not
+    entries: u64,
+    /// Entries written to the bucket, they lead the log
+    written: u64,
+}
+
+impl WriteAheadLog {
+    /// Path of the log of the bucket stored at `bucket_path`
+    pub fn path(bucket_path: &Path) -> PathBuf {
+        bucket_path.with_extension(EXTENSION)
+    }
+
+    /// Opens the log stored at `path`, creating it if it doesn't exist, and returns the entries
+    /// which were never written to the bucket
+    ///
+    /// A partially appended last entry is dropped, its write never made it to the queue.
+    pub fn open(path: &Path) -> std::io::Result<(WriteAheadLog, Vec<QueuedWriteInformation>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let mut pending = Vec::new();
+        let mut len = HEADER_SIZE;
+        let mut entries = 0;
+        let mut written = 0;
+        if file.metadata()?.len() >= HEADER_SIZE {
+            let mut reader = BufReader::new(&file);
+            written = reader.read_u64::<LittleEndian>()?;
+            loop {
+                let info = match WriteAheadLog::read_entry(&mut reader) {
+                    Ok(info) => info,
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                    Err(e) => return Err(e),
+                };
+
+                len += WriteAheadLog::entry_size(&info);
+                entries += 1;
+                if entries > written {
+                    pending.push(info);
+                }
+            }
+        }
+
+        // Drops a partial last entry, or writes the header of a new log
+        file.set_len(len)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_u64::<LittleEndian>(written)?;
+
+        let log = WriteAheadLog {
+            file,
+            len,
+            entries,
+            written: written.min(entries),
+        };
+
+        Ok((log, pending))
+    }
+
+    /// Appends a write about to be queued, returns the length of the log before it so it can be
+    /// discarded if queueing it fails, see `discard_from`
+    pub fn append(&mut self, info: &QueuedWriteInformation) -> std::io::Result<u64> {
+        let mut entry = Vec::with_capacity(WriteAheadLog::entry_size(info) as usize);
+        entry.write_u64::<LittleEndian>(info.seek.0)?;
+        entry.write_u64::<LittleEndian>(info.seek.1)?;
+        entry.write_u8(info.overwrite as u8)?;
+        entry.write_i64::<LittleEndian>(info.documents)?;
+        entry.write_u64::<LittleEndian>(info.bytes.len() as u64)?;
+        entry.extend_from_slice(&info.bytes);
+
+        let len = self.len;
+        self.file.seek(SeekFrom::Start(len))?;
+        self.file.write_all(&entry)?;
+        self.len += entry.len() as u64;
+        self.entries += 1;
+
+        Ok(len)
+    }
+
+    /// Drops the last entry, which starts at `len`, after its write couldn't be queued
+    pub fn discard_from(&mut self, len: u64) -> std::io::Result<()> {
+        self.file.set_len(len)?;
+        self.len = len;
+        self.entries -= 1;
+        Ok(())
+    }
+
+    /// Marks the next `amount` entries as written to the bucket, emptying the log once every
+    /// entry is
+    ///
+    /// The bucket file has to be synced first, so the log never skips a write lost in a crash.
+    pub fn advance(&mut self, amount: u64) -> std::io::Result<()> {
+        self.written = (self.written + amount).min(self.entries);
+        if self.written == self.entries {
+            self.clear()
+        } else {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.write_u64::<LittleEndian>(self.written)
+        }
+    }
+
+    /// Drops every entry, once they were all written to the bucket
+    pub fn clear(&mut self) -> std::io::Result<()> {
+        self.file.set_len(HEADER_SIZE)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_u64::<LittleEndian>(0)?;
+        self.len = HEADER_SIZE;
+        self.entries = 0;
+        self.written = 0;
+        Ok(())
+    }
+
+    fn entry_size(info: &QueuedWriteInformation) -> u64 {
+        (std::mem::size_of::<u64>() * 4 + 1 + info.bytes.len()) as u64
+    }
+
+    fn read_entry<R: Read>(reader: &mut R) -> std::io::Result<QueuedWriteInformation> {
+        let start = reader.read_u64::<LittleEndian>()?;
+        let end = reader.read_u64::<LittleEndian>()?;
+        let overwrite = reader.read_u8()? != 0;
+        let documents = reader.read_i64::<LittleEndian>()?;
+        let len = reader.read_u64::<LittleEndian>()?;
+
+        // A length past the end of the log belongs to a partial entry
+        let mut bytes = Vec::new();
+        reader.take(len).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < len {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(QueuedWriteInformation {
+            seek: (start, end),
+            len: bytes.len(),
+            bytes,
+            overwrite,
+            documents,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::OpenOptions,
+        io::{Seek, SeekFrom, Write},
+    };
+
+    use byteorder::{LittleEndian, WriteBytesExt};
+
+    use crate::{
+        database::{
+            bucket::{
+                config::BucketConfiguration,
+                writer::{count_location, offset_location, queued::QueuedWriteInformation},
+            },
+            error::NonaneError,
+            Database,
+        },
+        utils::testing::{int_description, int_document, temp_path},
+    };
+
+    use super::WriteAheadLog;
+
+    fn logged() -> Option<BucketConfiguration> {
+        Some(BucketConfiguration {
+            write_ahead_log: true,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn writes_lost_mid_batch_are_replayed() {
+        let path = temp_path("wal-killed-mid-batch");
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("numbers", Some(int_description()), logged()).unwrap();
+        let (offsets, page) = {
+            let mut bucket = db.get_bucket_mut("numbers").unwrap();
+            let mut offsets: Vec<u64> = (0..4)
+                .map(|n| bucket.insert(&int_document(n)).unwrap().0 as u64)
+                .collect();
+            offsets.push(bucket.disk_usage());
+            bucket.flush().unwrap();
+            (offsets, bucket.path().to_path_buf())
+        };
+        db.close().unwrap();
+
+        // Recreate what a crash leaves behind after the first chunk of a batch of four writes
+        // was written: every write is in the log, but the last two rows never reached the file
+        let stored = std::fs::read(&page).unwrap();
+        let (mut wal, pending) = WriteAheadLog::open(&WriteAheadLog::path(&page)).unwrap();
+        assert!(pending.is_empty());
+        for row in offsets.windows(2) {
+            let bytes = stored[row[0] as usize..row[1] as usize].to_vec();
+            wal.append(&QueuedWriteInformation {
+                seek: (row[0], row[1]),
+                len: bytes.len(),
+                bytes,
+                overwrite: false,
+                documents: 1,
+            })
+            .unwrap();
+        }
+        drop(wal);
+
+        let mut file = OpenOptions::new().write(true).open(&page).unwrap();
+        file.set_len(offsets[2]).unwrap();
+        file.seek(SeekFrom::Start(offset_location())).unwrap();
+        file.write_u64::<LittleEndian>(offsets[2]).unwrap();
+        file.seek(SeekFrom::Start(count_location())).unwrap();
+        file.write_u64::<LittleEndian>(2).unwrap();
+        file.flush().unwrap();
+
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("numbers", None, logged()).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+        assert_eq!(bucket.count_documents().unwrap(), 4);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 4);
+        for (n, offset) in offsets[..4].iter().enumerate() {
+            let document = bucket.get_at(*offset).unwrap().unwrap();
+            assert_eq!(document.get_i32("n"), Some(n as i32));
+        }
+
+        // Replayed writes are taken out of the log
+        let (_, pending) = WriteAheadLog::open(&WriteAheadLog::path(&page)).unwrap();
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn failed_write_is_kept_in_the_log() {
+        let mut db = Database::open(temp_path("wal-failed-write")).unwrap();
+        db.open_bucket("numbers", Some(int_description()), logged()).unwrap();
+        let mut bucket = db.get_bucket_mut("numbers").unwrap();
+
+        bucket.insert(&int_document(1)).unwrap();
+        bucket.flush().unwrap();
+
+        // Its offset is past the largest seekable one, so the writer fails to write it
+        bucket
+            .push_write(QueuedWriteInformation {
+                seek: (u64::MAX - 8, u64::MAX),
+                len: 8,
+                bytes: vec![0; 8],
+                overwrite: false,
+                documents: 1,
+            })
+            .unwrap();
+        let (after, _) = bucket.insert(&int_document(2)).unwrap();
+        assert!(matches!(bucket.flush(), Err(NonaneError::WritesFailed { writes: 1 })));
+
+        // The log is read from a copy, the open bucket still holds it
+        let wal = WriteAheadLog::path(bucket.path());
+        let copy = wal.with_extension("wal-copy");
+        std::fs::copy(&wal, &copy).unwrap();
+        let (_, pending) = WriteAheadLog::open(&copy).unwrap();
+        let seeks: Vec<u64> = pending.iter().map(|info| info.seek.0).collect();
+        assert_eq!(seeks, vec![u64::MAX - 8, after as u64]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    fs::{File, OpenOptions},
+    path::Path,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crossbeam_channel::{RecvTimeoutError, Sender};
+use dashmap::DashMap;
+
+use super::{bucket::writer::queued::WriterThread, error::NonaneError};
+
+/// Everything required to checkpoint a bucket without borrowing it
+#[derive(Clone)]
+pub(crate) struct CheckpointHandle {
+    writer_thread: Option<WriterThread>,
+    file: Arc<File>,
+}
+
+impl CheckpointHandle {
+    pub fn new(
+        path: &Path,
+        writer_thread: Option<WriterThread>,
+    ) -> Result<CheckpointHandle, NonaneError> {
+        let file = OpenOptions::new().write(true).open(path)?;
+
+        Ok(CheckpointHandle {
+            writer_thread,
+            file: Arc::new(file),
+        })
+    }
+
+    /// Waits for the writes queued so far to be written, then syncs them and the stored offset to disk
+    pub fn checkpoint(&self) -> Result<(), NonaneError> {
+        if let Some(writer_thread) = self.writer_thread.as_ref() {
+            writer_thread.wait_for_writes()?;
+        }
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Background thread checkpointing every open bucket on an interval
+pub(crate) struct AutoCheckpoint {
+    stop: Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl AutoCheckpoint {
+    pub fn start(
+        interval: Duration,
+        handles: Arc<DashMap<String, CheckpointHandle>>,
+    ) -> Result<AutoCheckpoint, NonaneError> {
+        let (stop, receiver) = crossbeam_channel::bounded::<()>(1);
+
+        let thread = thread::Builder::new()
+            .name("checkpoint".into())
+            .spawn(move || {
+                // Anything but a timeout means the thread was asked to stop
+                while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                    // Collect the handles first so opening buckets isn't blocked while syncing
+                    let targets: Vec<(String, CheckpointHandle)> = handles
+                        .iter()
+                        .map(|h| (h.key().clone(), h.value().clone()))
+                        .collect();
+
+                    for (name, handle) in targets {
+                        if let Err(e) = handle.checkpoint() {
+                            error!("Failed to checkpoint bucket {}: {}", name, e);
+                        }
+                    }
+
+                    trace!("Checkpointed all open buckets");
+                }
+            })?;
+
+        Ok(AutoCheckpoint { stop, thread })
+    }
+
+    /// Stops the thread, waiting for a running checkpoint to finish
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        database::Database,
+        utils::testing::{copy_dir, int_description, int_document, temp_path},
+    };
+
+    #[test]
+    fn auto_checkpoint_leaves_inserts_on_disk_after_an_interval() {
+        let path = temp_path("checkpoint-interval");
+        let mut db = Database::open(path).unwrap();
+        db.open_bucket("numbers", Some(int_description()), None).unwrap();
+        db.start_auto_checkpoint(Duration::from_millis(20)).unwrap();
+        for n in 0..100 {
+            db.insert_document("numbers", 0, int_document(n)).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(200));
+
+        // The copy is what a crash leaves behind, the database is neither flushed nor closed
+        let crashed = temp_path("checkpoint-interval-crashed");
+        copy_dir(path, crashed);
+        db.stop_auto_checkpoint();
+
+        let mut db = Database::open(crashed).unwrap();
+        db.open_bucket("numbers", None, None).unwrap();
+        let mut bucket = db.get_mut_bucket("numbers").unwrap();
+        assert_eq!(bucket.count_documents().unwrap(), 100);
+        assert_eq!(bucket.count_documents_slow().unwrap(), 100);
+    }
+}
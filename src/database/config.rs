@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+use super::bucket::config::BucketConfiguration;
+
+/// Options used when opening a database
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct DatabaseConfig {
+    /// Configuration applied to every bucket opened without an explicit configuration
+    ///
+    /// Sets the amount of readers, the queue capacity and sync policy, and also the format rows
+    /// are serialized in: `intern_field_names`, `compress_min_bytes` and `pad_rows`. Rows have a
+    /// single encoding which these options tune, so there's no separate format option here.
+    pub bucket: BucketConfiguration,
+    /// How long to wait for another handle to release the database when opening it,
+    /// `None` errors right away
+    pub lock_timeout: Option<Duration>,
+    /// Maximum number of buckets open at once, each one holds a writer thread and file handles,
+    /// `None` allows any number
+    pub max_open_buckets: Option<usize>,
+    /// Never spawns a thread, every bucket writes synchronously on the caller's thread and reads
+    /// through a single shared reader
+    ///
+    /// Inserts wait for their row to be written and concurrent reads of a bucket wait for each
+    /// other, `Database::start_auto_checkpoint` errors with `NonaneError::ThreadsDisabled`.
+    pub threadless: bool,
+}
+
+impl DatabaseConfig {
+    pub fn new(bucket: BucketConfiguration) -> DatabaseConfig {
+        DatabaseConfig {
+            bucket,
+            ..Default::default()
+        }
+    }
+}
@@ -1,6 +1,5 @@
 use std::{
     convert::TryInto,
-    error::Error,
     fs::{File, OpenOptions},
     io::{Read, Seek, SeekFrom, Write},
     path::Path,
@@ -11,6 +10,8 @@ use fs2::FileExt;
 use log::trace;
 use serde::{Deserialize, Serialize};
 
+use super::error::NonaneError;
+
 /// Extra head-room added on-top of the header size to allow for compatability with future versions
 const HEADER_ROOM: usize = 1024;
 
@@ -50,23 +51,29 @@ impl DBDescriptor {
     }
 
     /// Deserializes a descriptor from bytes
-    pub fn deserialize(data: &[u8]) -> Result<DBDescriptor, Box<dyn Error>> {
+    pub fn deserialize(data: &[u8]) -> Result<DBDescriptor, NonaneError> {
         Ok(bincode::deserialize(&data)?)
     }
 
-    pub fn load_from_path(path: &Path) -> Result<DBDescriptor, Box<dyn Error>> {
+    pub fn load_from_path(path: &Path) -> Result<DBDescriptor, NonaneError> {
         // Open descriptor file
         let mut file = OpenOptions::new().read(true).read(true).write(true).open(&path)?;
 
         // Seek and read description length
         file.seek(SeekFrom::Start(0))?;
-        let length = file.read_u64::<LittleEndian>()?.try_into()?;
-
-        // Initialize buffer for reading
-        let mut buf: Vec<u8> = Vec::with_capacity(length);
-        unsafe { buf.set_len(length) };
+        let length = file.read_u64::<LittleEndian>()? as usize;
+
+        // A length reaching past the end of the file is corrupt, it's not allocated
+        if length as u64 > file.metadata()?.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "database descriptor reaches past the end of its file",
+            )
+            .into());
+        }
+        let mut buf = vec![0; length];
 
-        file.seek(SeekFrom::Current(std::mem::size_of::<u64>().try_into()?))?;
+        file.seek(SeekFrom::Current(std::mem::size_of::<u64>() as i64))?;
         file.read_exact(&mut buf)?;
 
         let descriptor = DBDescriptor::deserialize(&buf)?;
@@ -75,7 +82,7 @@ impl DBDescriptor {
         Ok(descriptor)
     }
 
-    pub fn save_to_path(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+    pub fn save_to_path(&self, path: &Path) -> Result<(), NonaneError> {
         let mut file = OpenOptions::new().create_new(true).read(true).write(true).open(&path)?;
         trace!("Saving page size and header size to database");
 
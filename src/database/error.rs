@@ -0,0 +1,186 @@
+use std::fmt;
+
+/// Errors returned by the database
+#[derive(Debug)]
+pub enum NonaneError {
+    /// A row ends past the end of the bucket file, the file was cut short while writing it
+    TruncatedRow { offset: u64 },
+    /// No row starts at the offset, it's padding or points into another row
+    NoDocumentAt { offset: u64 },
+    /// A database already exists where a new one was to be created
+    AlreadyExists,
+    /// No database exists where an existing one was to be opened
+    NotFound,
+    /// Another handle holds the lock on the database
+    DatabaseLocked,
+    /// The writer thread's queue is full, the write can be retried once it has caught up
+    QueueFull,
+    /// The disk lacks the free space required to create a bucket
+    OutOfSpace,
+    /// A document doesn't hold the fields described by the bucket
+    FieldMismatch { reason: String },
+    /// A bucket name isn't safe to use as a file name, see `Database::open_bucket`
+    InvalidBucketName { name: String },
+    /// A field name holds a nul byte, which field names are stored without
+    InvalidFieldName { name: String },
+    /// No bucket with the name is open
+    BucketNotFound { name: String },
+    /// The bucket was closed, its writer thread no longer takes writes
+    BucketClosed { name: String },
+    /// Opening a bucket would exceed `DatabaseConfig::max_open_buckets`
+    TooManyBuckets { limit: usize },
+    /// A document has the same values as another document for the fields of a unique constraint
+    UniqueViolation { constraint: String },
+    /// A document holds the value of a unique field another document holds, see
+    /// `FieldDescriptor::unique`, or stored documents share a value for a field which is to
+    /// become the primary key
+    DuplicateKey { field: String },
+    /// The bucket's descriptor has no field with the name
+    FieldNotFound { name: String },
+    /// The bucket already has, or had, a field with the name
+    FieldExists { name: String },
+    /// A value is longer than the maximum length of its field, see `FieldDescriptor::with_max_len`
+    FieldTooLong { field: String, max_len: usize },
+    /// The field has no index, see `Bucket::create_index`
+    IndexNotFound { field: String },
+    /// An insert would grow the bucket past `BucketConfiguration::max_bucket_bytes`
+    BucketQuotaExceeded { limit: u64 },
+    /// A bucket was created without a descriptor describing its fields
+    MissingDescriptor { name: String },
+    /// The descriptor supplied when opening a bucket describes other fields than the stored one
+    SchemaMismatch { fields: Vec<String> },
+    /// The bucket's descriptor grew too large to fit in front of the stored rows
+    DescriptorTooLarge,
+    /// A bucket file was written in another format version, 0 for files without a version
+    UnsupportedFormat { version: u32 },
+    /// Spawning a thread was requested from a database opened with `DatabaseConfig::threadless`
+    ThreadsDisabled,
+    /// The writer thread couldn't write the amount of queued writes to disk, see
+    /// `Bucket::checkpoint`
+    WritesFailed { writes: u64 },
+    /// An encrypted row was read without a key or with another key than it was written with
+    DecryptionFailed,
+    /// Indexes are stored unencrypted, so the fields of an encrypted bucket can't be indexed
+    EncryptedIndex { field: String },
+    /// A value couldn't be converted to or from a document, see `Document::from_serialize`
+    Conversion { reason: String },
+    /// A value of a batch failed, nothing from the batch was inserted
+    InvalidBatchItem {
+        index: usize,
+        error: Box<NonaneError>,
+    },
+    Io(std::io::Error),
+    Serde(bincode::Error),
+}
+
+impl fmt::Display for NonaneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NonaneError::TruncatedRow { offset } => {
+                write!(f, "row at offset {} is truncated", offset)
+            }
+            NonaneError::NoDocumentAt { offset } => {
+                write!(f, "no document is stored at offset {}", offset)
+            }
+            NonaneError::AlreadyExists => write!(f, "database already exists"),
+            NonaneError::NotFound => write!(f, "database was not found"),
+            NonaneError::DatabaseLocked => write!(f, "database is locked by another handle"),
+            NonaneError::QueueFull => write!(f, "writer queue is full"),
+            NonaneError::OutOfSpace => write!(f, "out of free space"),
+            NonaneError::FieldMismatch { reason } => {
+                write!(f, "document doesn't match the bucket's fields: {}", reason)
+            }
+            NonaneError::InvalidBucketName { name } => {
+                write!(f, "{:?} is not a valid bucket name", name)
+            }
+            NonaneError::InvalidFieldName { name } => {
+                write!(f, "{:?} is not a valid field name", name)
+            }
+            NonaneError::BucketNotFound { name } => write!(f, "bucket {} is not open", name),
+            NonaneError::BucketClosed { name } => write!(f, "bucket {} was closed", name),
+            NonaneError::TooManyBuckets { limit } => {
+                write!(f, "can't open more than {} buckets", limit)
+            }
+            NonaneError::UniqueViolation { constraint } => {
+                write!(f, "unique constraint {} was violated", constraint)
+            }
+            NonaneError::DuplicateKey { field } => {
+                write!(f, "documents share a value for the key field {}", field)
+            }
+            NonaneError::FieldNotFound { name } => write!(f, "bucket has no field {}", name),
+            NonaneError::FieldExists { name } => write!(f, "bucket already has a field {}", name),
+            NonaneError::FieldTooLong { field, max_len } => {
+                write!(f, "value of field {} is longer than {} bytes", field, max_len)
+            }
+            NonaneError::IndexNotFound { field } => write!(f, "field {} has no index", field),
+            NonaneError::BucketQuotaExceeded { limit } => {
+                write!(f, "bucket would exceed its quota of {} bytes", limit)
+            }
+            NonaneError::MissingDescriptor { name } => {
+                write!(f, "bucket {} can't be created without a descriptor", name)
+            }
+            NonaneError::SchemaMismatch { fields } => write!(
+                f,
+                "descriptor doesn't match the stored one, differing fields: {}",
+                fields.join(", ")
+            ),
+            NonaneError::DescriptorTooLarge => {
+                write!(f, "descriptor doesn't fit in front of the stored rows")
+            }
+            NonaneError::UnsupportedFormat { version: 0 } => {
+                write!(f, "bucket file was written in a legacy format without a version")
+            }
+            NonaneError::UnsupportedFormat { version } => write!(
+                f,
+                "bucket file format version {} is not supported, expected {}",
+                version,
+                crate::database::bucket::FORMAT_VERSION
+            ),
+            NonaneError::ThreadsDisabled => {
+                write!(f, "database was opened without threads")
+            }
+            NonaneError::WritesFailed { writes } => {
+                write!(f, "{} queued writes couldn't be written to disk", writes)
+            }
+            NonaneError::DecryptionFailed => {
+                write!(f, "row could not be decrypted with the bucket's key")
+            }
+            NonaneError::EncryptedIndex { field } => write!(
+                f,
+                "field {} of an encrypted bucket can't be indexed, indexes aren't encrypted",
+                field
+            ),
+            NonaneError::Conversion { reason } => {
+                write!(f, "value couldn't be converted: {}", reason)
+            }
+            NonaneError::InvalidBatchItem { index, error } => {
+                write!(f, "item {} of the batch is invalid: {}", index, error)
+            }
+            NonaneError::Io(e) => write!(f, "io error: {}", e),
+            NonaneError::Serde(e) => write!(f, "serialization error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for NonaneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NonaneError::InvalidBatchItem { error, .. } => Some(error.as_ref()),
+            NonaneError::Io(e) => Some(e),
+            NonaneError::Serde(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for NonaneError {
+    fn from(e: std::io::Error) -> Self {
+        NonaneError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for NonaneError {
+    fn from(e: bincode::Error) -> Self {
+        NonaneError::Serde(e)
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    fs::{File, OpenOptions},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use fs2::FileExt;
+
+use super::error::NonaneError;
+
+/// Name of the file locked while a database is open
+static LOCK_FILE: &str = "database.lock";
+
+/// Longest time slept between two attempts at acquiring a lock
+const MAX_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Exclusive advisory lock on a database directory, released when dropped
+#[derive(Debug)]
+pub struct DatabaseLock {
+    file: File,
+}
+
+impl DatabaseLock {
+    /// Locks the database in `dir`
+    ///
+    /// Without a timeout this errors with `NonaneError::DatabaseLocked` right away if another
+    /// handle holds the lock, otherwise it retries with an increasing backoff until the timeout.
+    pub fn acquire(dir: &Path, timeout: Option<Duration>) -> Result<DatabaseLock, NonaneError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(LOCK_FILE))?;
+
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut backoff = Duration::from_millis(1);
+        loop {
+            if file.try_lock_exclusive().is_ok() {
+                trace!("Acquired lock for database");
+                return Ok(DatabaseLock { file });
+            }
+
+            let now = Instant::now();
+            match deadline {
+                Some(deadline) if now < deadline => {
+                    std::thread::sleep(backoff.min(deadline - now));
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                _ => return Err(NonaneError::DatabaseLocked),
+            }
+        }
+    }
+}
+
+impl Drop for DatabaseLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use crate::{
+        database::{error::NonaneError, Database},
+        utils::testing::temp_path,
+    };
+
+    #[test]
+    fn open_with_lock_timeout_waits_for_the_lock_to_be_released() {
+        let path = temp_path("lock-timeout");
+        let db = Database::open(path).unwrap();
+        assert!(matches!(Database::open(path), Err(NonaneError::DatabaseLocked)));
+        assert!(matches!(
+            Database::open_with_lock_timeout(path, Duration::from_millis(20)),
+            Err(NonaneError::DatabaseLocked)
+        ));
+
+        let release = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(db);
+        });
+        Database::open_with_lock_timeout(path, Duration::from_secs(5)).unwrap();
+        release.join().unwrap();
+    }
+}
@@ -8,11 +8,11 @@ extern crate log;
 pub mod database;
 pub mod utils;
 
-use std::{sync::atomic::Ordering, time::Duration};
+use std::time::Duration;
 
 use database::{
     bucket::{
-        descriptor::BucketDescription,
+        descriptor::{BucketDescription, BucketDesriptor},
         document::{
             field::{descriptor::FieldDescriptor, fieldtype::FieldType, Field},
             Document, DocumentConvert,
@@ -32,21 +32,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     info!("Initializing bucket");
     let t = std::time::Instant::now();
-    let desc = BucketDescription {
-        field_description: vec![
-            FieldDescriptor::new("first_name".into(), FieldType::Text),
-            FieldDescriptor::new("last_name".into(), FieldType::Text),
-            FieldDescriptor::new("email".into(), FieldType::Text),
-            FieldDescriptor::new("data".into(), FieldType::Bytes),
-        ],
-    };
-
-    db.open_bucket("accounts", Some(desc.clone()))?;
+    db.open_bucket_for::<Account>("accounts")?;
 
     let el = t.elapsed();
     debug!("It took {:?} to initialize 'accounts' bucket", el);
 
-    let mut dbe = db.clone();
+    let dbe = db.clone();
     let mut buck = dbe.get_mut_bucket("accounts")?;
     let count = buck.count_documents()?;
 
@@ -72,16 +63,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     // Wait for thread writing to finish
-    {
-        let mut dbx = db.clone();
-        let bucks = dbx.borrow_buckets();
-        let buck = bucks.get_mut("accounts");
-        let buck = buck.as_ref();
-        let buck = buck.unwrap();
-        let writer_thread = buck.writer_thread.clone().unwrap();
-
-        while writer_thread.items.load(Ordering::SeqCst) > 0 {}
-    }
+    db.flush_bucket("accounts")?;
 
     let el = insert_time.elapsed();
 
@@ -143,6 +125,17 @@ impl Account {
     }
 }
 
+impl BucketDesriptor for Account {
+    fn get_description() -> BucketDescription {
+        BucketDescription::new(vec![
+            FieldDescriptor::new("first_name", FieldType::Text).unwrap(),
+            FieldDescriptor::new("last_name", FieldType::Text).unwrap(),
+            FieldDescriptor::new("email", FieldType::Text).unwrap(),
+            FieldDescriptor::new("data", FieldType::Bytes).unwrap(),
+        ])
+    }
+}
+
 impl DocumentConvert for Account {
     type ConvertFrom = Account;
 
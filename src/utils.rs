@@ -1,3 +1,6 @@
 pub mod numbers;
 pub mod pool;
-pub mod threading;
\ No newline at end of file
+pub mod threading;
+
+#[cfg(test)]
+pub mod testing;
@@ -1,42 +1,174 @@
-use parking_lot::Mutex;
-use std::mem::ManuallyDrop;
+use parking_lot::{Condvar, Mutex};
+use std::{
+    convert::Infallible,
+    fmt::Display,
+    mem::ManuallyDrop,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
 
 pub type Stack<T> = Vec<T>;
 
-pub struct Pool<T> {
+/// Creates the items of a pool
+pub trait Factory<T> {
+    /// Error creating an item fails with, `Infallible` for factories which can't fail
+    type Error;
+
+    fn create(&self) -> Result<T, Self::Error>;
+}
+
+impl<T, F: Fn() -> T> Factory<T> for F {
+    type Error = Infallible;
+
+    fn create(&self) -> Result<T, Infallible> {
+        Ok(self())
+    }
+}
+
+/// Items handed out one at a time, pulling from an exhausted pool waits for an item to be
+/// returned
+pub struct Pool<T, F> {
     stack: Mutex<Stack<T>>,
+    /// Signalled whenever an item is returned to the stack
+    returned: Condvar,
+    /// Amount of items created by the pool, never more than `max`
+    created: AtomicUsize,
+    max: usize,
+    factory: F,
+}
+
+impl<T, F: Factory<T, Error = Infallible>> Pool<T, F> {
+    pub fn new(cap: usize, factory: F) -> Pool<T, F> {
+        Pool::lazy(cap, cap, factory)
+    }
+
+    /// Creates a pool holding `initial` items, which creates more when pulled from while empty
+    /// until it has created `max` items
+    pub fn lazy(initial: usize, max: usize, factory: F) -> Pool<T, F> {
+        match Pool::try_lazy(initial, max, factory) {
+            Ok(pool) => pool,
+            Err(e) => match e {},
+        }
+    }
 }
 
-impl<T> Pool<T> {
-    pub fn new<F: Fn() -> T>(cap: usize, init: F) -> Pool<T> {
+impl<T, F: Factory<T>> Pool<T, F>
+where
+    F::Error: Display,
+{
+    /// Like `lazy` for a factory which can fail, erroring if one of the `initial` items can't
+    /// be created
+    ///
+    /// An item which can't be created later on is logged, the pull waits for an item to be
+    /// returned instead.
+    pub fn try_lazy(initial: usize, max: usize, factory: F) -> Result<Pool<T, F>, F::Error> {
+        let initial = initial.min(max);
         let mut stack = Stack::new();
-        (0..cap).for_each(|_| stack.push(init()));
+        for _ in 0..initial {
+            stack.push(factory.create()?);
+        }
 
-        Pool {
+        Ok(Pool {
             stack: Mutex::new(stack),
+            returned: Condvar::new(),
+            created: AtomicUsize::new(initial),
+            max,
+            factory,
+        })
+    }
+
+    /// Pulls an item, blocking until one is returned if every item is handed out and the pool
+    /// can't create more
+    pub fn pull(&self) -> Ref<'_, T, F> {
+        let data = self
+            .acquire(None)
+            .expect("Pulling without a deadline waits until an item is returned");
+
+        Ref::new(self, data)
+    }
+
+    /// Pulls an item without blocking, `None` if every item is handed out
+    pub fn try_pull(&self) -> Option<Ref<'_, T, F>> {
+        let data = self.acquire(Some(Instant::now()))?;
+        Some(Ref::new(self, data))
+    }
+
+    /// Pulls an item, waiting at most `timeout` for one to be returned
+    pub fn pull_timeout(&self, timeout: Duration) -> Option<Ref<'_, T, F>> {
+        let data = self.acquire(Some(Instant::now() + timeout))?;
+        Some(Ref::new(self, data))
+    }
+
+    /// Takes an item from the stack or creates one, otherwise waits for an item to be returned
+    /// until `deadline` passes
+    fn acquire(&self, deadline: Option<Instant>) -> Option<T> {
+        let mut stack = self.stack.lock();
+        let mut failed = false;
+        loop {
+            if let Some(data) = stack.pop() {
+                return Some(data);
+            }
+
+            if !failed && self.reserve() {
+                // Creating an item may take a while, like opening a file, others can return
+                // items meanwhile
+                drop(stack);
+                match self.factory.create() {
+                    Ok(data) => return Some(data),
+                    Err(e) => {
+                        warn!("Failed to create an item for a pool, waiting instead: {}", e);
+                        self.created.fetch_sub(1, Ordering::SeqCst);
+                        failed = true;
+                        stack = self.stack.lock();
+                        continue;
+                    }
+                }
+            }
+
+            match deadline {
+                None => self.returned.wait(&mut stack),
+                Some(deadline) => {
+                    if self.returned.wait_until(&mut stack, deadline).timed_out() {
+                        return stack.pop();
+                    }
+                }
+            }
         }
     }
 
-    pub fn pull(&self) -> Ref<T> {
-        self.stack
-            .lock()
-            .pop()
-            .map(|data| Ref::new(self, data))
-            .expect("Failed to pull from pool")
+    /// Reserves room for creating another item, returns false if the pool is at its maximum
+    fn reserve(&self) -> bool {
+        self.created
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |created| {
+                if created < self.max {
+                    Some(created + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
     }
+}
 
+impl<T, F> Pool<T, F> {
     pub fn attach(&self, t: T) {
-        self.stack.lock().push(t)
+        self.stack.lock().push(t);
+        self.returned.notify_one();
+    }
+
+    /// Amount of items created by the pool so far
+    pub fn size(&self) -> usize {
+        self.created.load(Ordering::SeqCst)
     }
 }
 
-pub struct Ref<'a, T> {
-    pool: &'a Pool<T>,
+pub struct Ref<'a, T, F> {
+    pool: &'a Pool<T, F>,
     data: ManuallyDrop<T>,
 }
 
-impl<'a, T> Ref<'a, T> {
-    pub fn new(pool: &'a Pool<T>, t: T) -> Self {
+impl<'a, T, F> Ref<'a, T, F> {
+    pub fn new(pool: &'a Pool<T, F>, t: T) -> Self {
         Self {
             pool,
             data: ManuallyDrop::new(t),
@@ -56,8 +188,95 @@ impl<'a, T> Ref<'a, T> {
     }
 }
 
-impl<'a, T> Drop for Ref<'a, T> {
+impl<'a, T, F> Drop for Ref<'a, T, F> {
     fn drop(&mut self) {
         unsafe { self.pool.attach(self.take()) }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use super::{Factory, Pool};
+
+    /// Creates items until `left` runs out, failing afterwards
+    struct Failing {
+        left: AtomicUsize,
+    }
+
+    impl Factory<usize> for Failing {
+        type Error = String;
+
+        fn create(&self) -> Result<usize, String> {
+            self.left
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1))
+                .map_err(|_| "out of items".to_string())
+        }
+    }
+
+    #[test]
+    fn exhausted_pool_waits_for_returned_items() {
+        let pool = Pool::new(2, || 0usize);
+        let in_use = AtomicUsize::new(0);
+        let most_in_use = AtomicUsize::new(0);
+
+        // Sixteen threads share two items, each holding one for a while
+        thread::scope(|s| {
+            for _ in 0..16 {
+                s.spawn(|| {
+                    for _ in 0..10 {
+                        let mut item = pool.pull();
+                        let now = in_use.fetch_add(1, Ordering::SeqCst) + 1;
+                        most_in_use.fetch_max(now, Ordering::SeqCst);
+                        *item.as_mut_ref() += 1;
+                        thread::sleep(Duration::from_micros(200));
+                        in_use.fetch_sub(1, Ordering::SeqCst);
+                    }
+                });
+            }
+        });
+
+        assert!(most_in_use.load(Ordering::SeqCst) <= 2);
+        assert_eq!(pool.size(), 2);
+        let first = pool.pull();
+        let second = pool.pull();
+        assert_eq!(first.as_ref() + second.as_ref(), 160);
+    }
+
+    #[test]
+    fn try_pull_and_pull_timeout_give_up_on_an_exhausted_pool() {
+        let pool = Pool::lazy(1, 2, || ());
+        let first = pool.try_pull().unwrap();
+        let second = pool.try_pull().unwrap();
+        assert_eq!(pool.size(), 2);
+        assert!(pool.try_pull().is_none());
+        assert!(pool.pull_timeout(Duration::from_millis(20)).is_none());
+
+        drop(first);
+        assert!(pool.try_pull().is_some());
+        drop(second);
+        assert!(pool.pull_timeout(Duration::from_millis(20)).is_some());
+    }
+
+    #[test]
+    fn failing_factory_errors_up_front_and_is_waited_out_later() {
+        let failing = |left| Failing {
+            left: AtomicUsize::new(left),
+        };
+        assert!(Pool::try_lazy(2, 4, failing(1)).is_err());
+
+        // Creating a second item fails, the pull waits for the first one to be returned
+        let pool = Pool::try_lazy(1, 4, failing(1)).unwrap();
+        let first = pool.pull();
+        assert!(pool.pull_timeout(Duration::from_millis(20)).is_none());
+        assert_eq!(pool.size(), 1);
+
+        drop(first);
+        assert!(pool.pull_timeout(Duration::from_millis(20)).is_some());
+    }
+}
@@ -0,0 +1,37 @@
+use crate::database::bucket::{
+    descriptor::BucketDescription,
+    document::{
+        field::{descriptor::FieldDescriptor, fieldtype::FieldType, Field},
+        Document,
+    },
+};
+
+/// Path for the database of the test named `name`, under the system's temp directory
+///
+/// Anything left there by an earlier run is removed, the directory itself isn't created. The
+/// path is leaked, as databases borrow it for as long as they're open.
+pub fn temp_path(name: &str) -> &'static str {
+    let dir = std::env::temp_dir().join(format!("nonanedb-test-{}", name));
+    let _ = std::fs::remove_dir_all(&dir);
+    Box::leak(dir.to_str().unwrap().to_owned().into_boxed_str())
+}
+
+/// Describes a bucket holding a single `Int32` field named `n`
+pub fn int_description() -> BucketDescription {
+    BucketDescription::new(vec![FieldDescriptor::new("n", FieldType::Int32).unwrap()])
+}
+
+/// A document for a bucket described by `int_description`
+pub fn int_document(n: i32) -> Document {
+    Document::new(vec![Field::new("n", n).unwrap()])
+}
+
+/// Copies the files of the directory at `from` to a new directory at `to`, like a crash would
+/// leave them while the database at `from` is still open
+pub fn copy_dir(from: &str, to: &str) {
+    std::fs::create_dir_all(to).unwrap();
+    for entry in std::fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        std::fs::copy(entry.path(), std::path::Path::new(to).join(entry.file_name())).unwrap();
+    }
+}
@@ -1,5 +1,5 @@
 use parking_lot::{Mutex, Condvar};
-use std::{sync::Arc, time::Duration};
+use std::{sync::Arc, time::{Duration, Instant}};
 
 #[derive(Debug)]
 pub struct BooleanSemaphore {
@@ -22,11 +22,17 @@ impl BooleanSemaphore {
         }
     }
 
-    pub fn wait_for(&self, timeout: Duration) {
+    /// Waits until ready for at most `timeout`, returns whether it became ready
+    pub fn wait_for(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
         let mut value = self.mutex.lock();
         while !(*value) {
-            let _ = self.cvar.wait_for(&mut value, timeout);
+            if self.cvar.wait_until(&mut value, deadline).timed_out() {
+                return *value;
+            }
         }
+
+        true
     }
 
     pub fn set_ready(&self, ready: bool) {
@@ -34,4 +40,76 @@ impl BooleanSemaphore {
         *value = ready;
         self.cvar.notify_all();
     }
-}
\ No newline at end of file
+}
+/// Counts finished work, letting threads wait until a certain amount of work has been finished
+///
+/// Work which failed is counted apart from the finished work, but waiters treat it as done so
+/// they aren't stuck waiting for work which is never going to finish, see `fail`.
+#[derive(Debug, Default)]
+pub struct ProgressSemaphore {
+    done: Mutex<Progress>,
+    cvar: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Progress {
+    finished: u64,
+    failed: u64,
+}
+
+impl Progress {
+    fn done(&self) -> u64 {
+        self.finished + self.failed
+    }
+}
+
+impl ProgressSemaphore {
+    pub fn new() -> Self {
+        ProgressSemaphore::default()
+    }
+
+    /// Amount of work which has been finished or failed
+    pub fn get(&self) -> u64 {
+        self.done.lock().done()
+    }
+
+    /// Amount of work which failed
+    pub fn failed(&self) -> u64 {
+        self.done.lock().failed
+    }
+
+    pub fn advance(&self, amount: u64) {
+        let mut done = self.done.lock();
+        done.finished += amount;
+        self.cvar.notify_all();
+    }
+
+    /// Counts work which failed and is never going to finish
+    pub fn fail(&self, amount: u64) {
+        let mut done = self.done.lock();
+        done.failed += amount;
+        self.cvar.notify_all();
+    }
+
+    /// Blocks until at least `target` work has been finished or failed
+    pub fn wait_until(&self, target: u64) {
+        let mut done = self.done.lock();
+        while done.done() < target {
+            self.cvar.wait(&mut done);
+        }
+    }
+
+    /// Blocks until at least `target` work has been finished or failed, or `deadline` passes
+    ///
+    /// Returns whether the target was reached.
+    pub fn wait_until_deadline(&self, target: u64, deadline: Instant) -> bool {
+        let mut done = self.done.lock();
+        while done.done() < target {
+            if self.cvar.wait_until(&mut done, deadline).timed_out() {
+                return done.done() >= target;
+            }
+        }
+
+        true
+    }
+}